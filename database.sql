@@ -1,5 +1,6 @@
 CREATE TABLE IF NOT EXISTS activities (
-    activity_id TEXT PRIMARY KEY,
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
     pushed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
     activity_name TEXT NOT NULL,
     type INTEGER NOT NULL,
@@ -10,4 +11,65 @@ CREATE TABLE IF NOT EXISTS activities (
     is_open_evaluation INTEGER NOT NULL,
     course_info TEXT,
     description TEXT,
-);
\ No newline at end of file
+    is_overtime_commit INTEGER,
+    completed_at TIMESTAMP,
+    ticktick_task_id TEXT,
+    ticktick_project_id TEXT,
+    attachments TEXT,
+    details_fetched_at TIMESTAMP,
+    PRIMARY KEY (user_id, activity_id)
+);
+CREATE TABLE IF NOT EXISTS deliveries (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    sink TEXT NOT NULL,
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    delivered_at TIMESTAMP,
+    PRIMARY KEY (user_id, activity_id, sink)
+);
+
+CREATE TABLE IF NOT EXISTS change_deliveries (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    sink TEXT NOT NULL,
+    kinds TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    PRIMARY KEY (user_id, activity_id, sink)
+);
+
+CREATE TABLE IF NOT EXISTS completion_deliveries (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    sink TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    PRIMARY KEY (user_id, activity_id, sink)
+);
+
+CREATE TABLE IF NOT EXISTS reminders (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    offset_minutes INTEGER NOT NULL,
+    sent_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, activity_id, offset_minutes)
+);
+
+CREATE TABLE IF NOT EXISTS preferences (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    done_at TIMESTAMP,
+    snoozed_until INTEGER,
+    remind_before INTEGER,
+    PRIMARY KEY (user_id, activity_id)
+);
+
+CREATE TABLE IF NOT EXISTS users (
+    user_id TEXT PRIMARY KEY,
+    chat_id TEXT NOT NULL,
+    ucloud_username TEXT NOT NULL,
+    ucloud_password TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
@@ -0,0 +1,15 @@
+-- Per-sink delivery tracking. Activities pushed before this table existed
+-- went out to every sink at once, so they are backfilled as delivered.
+CREATE TABLE IF NOT EXISTS deliveries (
+    activity_id TEXT NOT NULL,
+    sink TEXT NOT NULL,
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    delivered_at TIMESTAMP,
+    PRIMARY KEY (activity_id, sink)
+);
+
+INSERT OR IGNORE INTO deliveries (activity_id, sink, status, attempts, delivered_at)
+SELECT activity_id, sink, 'delivered', 1, pushed_at
+FROM activities, (SELECT 'telegram' AS sink UNION ALL SELECT 'ticktick' UNION ALL SELECT 'lark');
@@ -0,0 +1,7 @@
+-- Deadline reminders already sent, one row per activity and offset.
+CREATE TABLE IF NOT EXISTS reminders (
+    activity_id TEXT NOT NULL,
+    offset_minutes INTEGER NOT NULL,
+    sent_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (activity_id, offset_minutes)
+);
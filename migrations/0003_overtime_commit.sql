@@ -0,0 +1,2 @@
+-- Stored so that changes to the overtime-commit flag can be announced.
+ALTER TABLE activities ADD COLUMN is_overtime_commit INTEGER;
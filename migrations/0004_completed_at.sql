@@ -0,0 +1,6 @@
+-- Set once an activity drops out of the undone list, i.e. it was submitted.
+ALTER TABLE activities ADD COLUMN completed_at TIMESTAMP;
+
+-- Existing rows count as completed so that history is not announced as newly
+-- submitted; the next run sets it back to NULL for whatever is still open.
+UPDATE activities SET completed_at = CURRENT_TIMESTAMP;
@@ -0,0 +1,2 @@
+-- Id of the TickTick task created for the activity, so pushes update it in place.
+ALTER TABLE activities ADD COLUMN ticktick_task_id TEXT;
@@ -0,0 +1,3 @@
+-- Project the TickTick task lives in, needed to complete it once the project is
+-- no longer fixed by config.
+ALTER TABLE activities ADD COLUMN ticktick_project_id TEXT;
@@ -0,0 +1,3 @@
+-- Attachments of the assignment as resolved from its detail, reused while the
+-- deadline is unchanged like the description.
+ALTER TABLE activities ADD COLUMN attachments TEXT;
@@ -0,0 +1,8 @@
+-- Choices made with the inline keyboard under an assignment. `snoozed_until` is
+-- a unix timestamp, `remind_before` an extra reminder offset in minutes.
+CREATE TABLE IF NOT EXISTS preferences (
+    activity_id TEXT PRIMARY KEY,
+    done_at TIMESTAMP,
+    snoozed_until INTEGER,
+    remind_before INTEGER
+);
@@ -0,0 +1,81 @@
+-- Telegram users with their own UCloud account. The password is encrypted with
+-- the CREDENTIALS_KEY secret.
+CREATE TABLE IF NOT EXISTS users (
+    user_id TEXT PRIMARY KEY,
+    chat_id TEXT NOT NULL,
+    ucloud_username TEXT NOT NULL,
+    ucloud_password TEXT NOT NULL,
+    created_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP
+);
+
+-- Every table is scoped by user. Rows stored so far belong to the account
+-- configured in secrets, whose scope is the empty string.
+CREATE TABLE activities_new (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    pushed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    activity_name TEXT NOT NULL,
+    type INTEGER NOT NULL,
+    start_time TEXT NOT NULL,
+    end_time TEXT NOT NULL,
+    assignment_type INTEGER NOT NULL,
+    evaluation_status INTEGER NOT NULL,
+    is_open_evaluation INTEGER NOT NULL,
+    course_info TEXT,
+    description TEXT,
+    is_overtime_commit INTEGER,
+    completed_at TIMESTAMP,
+    ticktick_task_id TEXT,
+    ticktick_project_id TEXT,
+    attachments TEXT,
+    PRIMARY KEY (user_id, activity_id)
+);
+INSERT INTO activities_new (activity_id, pushed_at, activity_name, type, start_time, end_time,
+    assignment_type, evaluation_status, is_open_evaluation, course_info, description,
+    is_overtime_commit, completed_at, ticktick_task_id, ticktick_project_id, attachments)
+SELECT activity_id, pushed_at, activity_name, type, start_time, end_time,
+    assignment_type, evaluation_status, is_open_evaluation, course_info, description,
+    is_overtime_commit, completed_at, ticktick_task_id, ticktick_project_id, attachments
+FROM activities;
+DROP TABLE activities;
+ALTER TABLE activities_new RENAME TO activities;
+
+CREATE TABLE deliveries_new (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    sink TEXT NOT NULL,
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    delivered_at TIMESTAMP,
+    PRIMARY KEY (user_id, activity_id, sink)
+);
+INSERT INTO deliveries_new (activity_id, sink, status, attempts, last_error, delivered_at)
+SELECT activity_id, sink, status, attempts, last_error, delivered_at FROM deliveries;
+DROP TABLE deliveries;
+ALTER TABLE deliveries_new RENAME TO deliveries;
+
+CREATE TABLE reminders_new (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    offset_minutes INTEGER NOT NULL,
+    sent_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (user_id, activity_id, offset_minutes)
+);
+INSERT INTO reminders_new (activity_id, offset_minutes, sent_at)
+SELECT activity_id, offset_minutes, sent_at FROM reminders;
+DROP TABLE reminders;
+ALTER TABLE reminders_new RENAME TO reminders;
+
+CREATE TABLE preferences_new (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    done_at TIMESTAMP,
+    snoozed_until INTEGER,
+    remind_before INTEGER,
+    PRIMARY KEY (user_id, activity_id)
+);
+INSERT INTO preferences_new (activity_id, done_at, snoozed_until, remind_before)
+SELECT activity_id, done_at, snoozed_until, remind_before FROM preferences;
+DROP TABLE preferences;
+ALTER TABLE preferences_new RENAME TO preferences;
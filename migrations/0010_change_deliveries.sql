@@ -0,0 +1,11 @@
+-- Edits to activities that a sink has not received yet. A row is removed once
+-- the sink takes the change, `kinds` is the JSON list of what changed.
+CREATE TABLE IF NOT EXISTS change_deliveries (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    sink TEXT NOT NULL,
+    kinds TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    PRIMARY KEY (user_id, activity_id, sink)
+);
@@ -0,0 +1,10 @@
+-- Submitted activities whose completion a sink has not received yet. A row is
+-- removed once the sink takes it.
+CREATE TABLE IF NOT EXISTS completion_deliveries (
+    user_id TEXT NOT NULL DEFAULT '',
+    activity_id TEXT NOT NULL,
+    sink TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    PRIMARY KEY (user_id, activity_id, sink)
+);
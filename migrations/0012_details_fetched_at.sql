@@ -0,0 +1,5 @@
+-- When the detail of the assignment was last fetched. Stored details are reused
+-- while the deadline is unchanged, but only for so long, so that edits to the
+-- description or the overtime setting are picked up too. Existing rows have no
+-- time and are fetched again on the next run.
+ALTER TABLE activities ADD COLUMN details_fetched_at TIMESTAMP;
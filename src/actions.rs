@@ -0,0 +1,112 @@
+use crate::api::telegram::Telegram;
+use crate::d1;
+use crate::error::Result;
+use crate::overview::{self, Window};
+use crate::storage::Database;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+use tracing::info;
+
+/// How long "Snooze" silences the reminders of an assignment.
+const SNOOZE: Duration = Duration::days(1);
+/// Offset added by "Remind me before".
+const REMIND_BEFORE_MINUTES: i64 = 120;
+
+/// A button under an assignment message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    Done,
+    Snooze,
+    RemindBefore,
+}
+
+impl Action {
+    fn code(self) -> &'static str {
+        match self {
+            Action::Done => "done",
+            Action::Snooze => "snooze",
+            Action::RemindBefore => "remind",
+        }
+    }
+
+    /// `callback_data` of the button, e.g. `done:123`.
+    pub fn callback_data(self, activity_id: &str) -> String {
+        format!("{}:{}", self.code(), activity_id)
+    }
+
+    /// Splits `callback_data` into the action and the activity id.
+    pub fn parse(data: &str) -> Option<(Self, &str)> {
+        let (code, activity_id) = data.split_once(':')?;
+        let action = [Action::Done, Action::Snooze, Action::RemindBefore]
+            .into_iter()
+            .find(|action| action.code() == code)?;
+        Some((action, activity_id))
+    }
+}
+
+/// Inline keyboard for an assignment. `open_url` may contain `{id}` for the
+/// activity id.
+pub fn keyboard(activity_id: &str, open_url: &str) -> Value {
+    let button = |text: &str, action: Action| {
+        let callback_data = action.callback_data(activity_id);
+        json!({"text": text, "callback_data": callback_data})
+    };
+    json!({
+        "inline_keyboard": [
+            [button("✅ 已完成", Action::Done), button("😴 推迟一天", Action::Snooze)],
+            [
+                {"text": "🔗 在 UCloud 打开", "url": open_url.replace("{id}", activity_id)},
+                button("⏰ 截止前 2 小时提醒", Action::RemindBefore),
+            ],
+        ]
+    })
+}
+
+/// Records `user`'s choice behind a button press and returns the text to answer with.
+pub async fn apply(
+    data: &str,
+    user: &str,
+    db: &impl Database,
+    now: DateTime<Utc>,
+) -> Result<&'static str> {
+    let Some((action, activity_id)) = Action::parse(data) else {
+        return Ok("未知操作");
+    };
+    info!("{:?} for {}", action, activity_id);
+    match action {
+        Action::Done => {
+            d1::mark_done(activity_id, user, db).await?;
+            Ok("已标记完成，不会再提醒啦")
+        }
+        Action::Snooze => {
+            d1::snooze(activity_id, (now + SNOOZE).timestamp(), user, db).await?;
+            Ok("一天之内不会再提醒啦")
+        }
+        Action::RemindBefore => {
+            d1::remind_before(activity_id, REMIND_BEFORE_MINUTES, user, db).await?;
+            Ok("截止前 2 小时会再提醒你")
+        }
+    }
+}
+
+/// Handles a `callback_query` update from an allowed user: either a page button
+/// of `/list` and `/due` or an assignment button.
+pub async fn handle_callback(
+    query: &Value,
+    bot: &Telegram,
+    user: &str,
+    db: &impl Database,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let data = query["data"].as_str().unwrap_or_default();
+    let message_id = query["message"]["message_id"].as_i64();
+    let answer = match (Window::parse_page(data), message_id) {
+        (Some((window, page)), Some(message_id)) => {
+            overview::turn_page(bot, message_id, window, page, user, db, now).await?;
+            ""
+        }
+        _ => apply(data, user, db, now).await?,
+    };
+    bot.answer_callback_query(query["id"].as_str().unwrap_or_default(), answer)
+        .await
+}
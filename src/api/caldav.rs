@@ -0,0 +1,130 @@
+use super::{Api, Outcomes, Update};
+use crate::calendar::{self, Entry};
+use crate::change::Change;
+use crate::config::CalDavConfig;
+use crate::d1::ActivityRecord;
+use crate::error::Result;
+use crate::storage::{Database, KeyValue};
+use chrono::{DateTime, Utc};
+use tracing::{info, warn};
+
+/// Keeps one VTODO per activity in a CalDAV collection, e.g. a Nextcloud or
+/// Radicale calendar.
+///
+/// Resources are named after the activity id, so every push, change and
+/// completion simply PUTs the whole VTODO again and no ids need to be stored.
+pub struct CalDav {
+    collection_url: String,
+    username: String,
+    password: String,
+    client: reqwest::Client,
+}
+
+impl CalDav {
+    pub fn new(collection_url: String, username: String, password: String) -> Self {
+        Self {
+            collection_url,
+            username,
+            password,
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn from_config(config: &CalDavConfig) -> Self {
+        Self::new(
+            config.url.clone(),
+            config.username.clone(),
+            config.password.clone(),
+        )
+    }
+
+    /// URL of the resource holding `activity_id`.
+    pub fn resource_url(&self, activity_id: &str) -> String {
+        format!(
+            "{}/{}.ics",
+            self.collection_url.trim_end_matches('/'),
+            urlencoding::encode(activity_id)
+        )
+    }
+
+    /// Creates or replaces the VTODO of `entry`.
+    async fn put(&self, entry: &Entry<'_>, now: DateTime<Utc>) -> Result<()> {
+        let Some(body) = calendar::todo_resource(entry, now) else {
+            warn!(
+                "skipping {}, unparseable deadline {}",
+                entry.activity_id, entry.end_time
+            );
+            return Ok(());
+        };
+        let response = self
+            .client
+            .put(self.resource_url(entry.activity_id))
+            .basic_auth(&self.username, Some(&self.password))
+            .header("Content-Type", "text/calendar; charset=utf-8")
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        info!(
+            "caldav put {}: {}",
+            entry.activity_id,
+            response.status().as_u16()
+        );
+        Ok(())
+    }
+}
+
+impl Api for CalDav {
+    fn name(&self) -> &'static str {
+        "caldav"
+    }
+
+    async fn push(
+        &self,
+        update: &Update<'_>,
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let now = Utc::now();
+        let mut outcomes = Vec::new();
+        for item in &update.new.undone_list {
+            let outcome = self.put(&Entry::from(item), now).await;
+            outcomes.push((item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+
+    async fn push_changes(
+        &self,
+        changes: &[Change],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let now = Utc::now();
+        let mut outcomes = Vec::new();
+        for change in changes {
+            let outcome = self.put(&Entry::from(&change.item), now).await;
+            outcomes.push((change.item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+
+    async fn push_completed(
+        &self,
+        completed: &[ActivityRecord],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let now = Utc::now();
+        let mut outcomes = Vec::new();
+        for record in completed {
+            let entry = Entry {
+                completed_at: Some(now),
+                ..Entry::from(record)
+            };
+            let outcome = self.put(&entry, now).await;
+            outcomes.push((record.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+}
@@ -0,0 +1,302 @@
+use super::telegram::overtime_text;
+use super::{Api, Outcomes, Update};
+use crate::change::{Change, ChangeKind};
+use crate::config::{FeishuBot, FeishuConfig};
+use crate::d1::ActivityRecord;
+use crate::error::{Error, Result};
+use crate::model::UndoneListItem;
+use crate::storage::{Database, KeyValue};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use std::cell::RefCell;
+use tracing::info;
+
+const DEFAULT_BASE_URL: &str = "https://open.feishu.cn";
+/// Longer descriptions are cut, cards are limited to 30 KB.
+const DESCRIPTION_LIMIT: usize = 1500;
+
+/// Posts an interactive card per assignment to a Feishu chat, through a custom
+/// bot webhook or as an app bot.
+pub struct Feishu {
+    bot: FeishuBot,
+    base_url: String,
+    notify_completed: bool,
+    client: reqwest::Client,
+    /// Fetched on first use, app bots only.
+    tenant_token: RefCell<Option<String>>,
+}
+
+impl Feishu {
+    pub fn new(bot: FeishuBot) -> Self {
+        Self {
+            bot,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            notify_completed: false,
+            client: reqwest::Client::new(),
+            tenant_token: RefCell::new(None),
+        }
+    }
+
+    /// Where the open platform API is, app bots only.
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn with_notify_completed(mut self, notify_completed: bool) -> Self {
+        self.notify_completed = notify_completed;
+        self
+    }
+
+    pub fn from_config(config: &FeishuConfig) -> Self {
+        let feishu = Self::new(config.bot.clone()).with_notify_completed(config.notify_completed);
+        match &config.bot {
+            FeishuBot::App {
+                api_url: Some(url), ..
+            } => feishu.with_base_url(url.clone()),
+            _ => feishu,
+        }
+    }
+
+    pub async fn send_card(&self, card: Value) -> Result<()> {
+        let res: Value = match &self.bot {
+            FeishuBot::Webhook {
+                webhook_url,
+                secret,
+            } => {
+                let mut body = json!({"msg_type": "interactive", "card": card});
+                if let Some(secret) = secret {
+                    let timestamp = chrono::Utc::now().timestamp();
+                    body["timestamp"] = timestamp.to_string().into();
+                    body["sign"] = sign(secret, timestamp)?.into();
+                }
+                self.client
+                    .post(webhook_url)
+                    .json(&body)
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?
+            }
+            FeishuBot::App { chat_id, .. } => {
+                let token = self.tenant_token().await?;
+                self.client
+                    .post(format!(
+                        "{}/open-apis/im/v1/messages?receive_id_type=chat_id",
+                        self.base_url
+                    ))
+                    .bearer_auth(token)
+                    .json(&json!({
+                        "receive_id": chat_id,
+                        "msg_type": "interactive",
+                        "content": card.to_string(),
+                    }))
+                    .send()
+                    .await?
+                    .error_for_status()?
+                    .json()
+                    .await?
+            }
+        };
+        info!("feishu push result: {:?}", res);
+        check(&res)
+    }
+
+    async fn tenant_token(&self) -> Result<String> {
+        if let Some(token) = self.tenant_token.borrow().clone() {
+            return Ok(token);
+        }
+        let FeishuBot::App {
+            app_id, app_secret, ..
+        } = &self.bot
+        else {
+            return Err(Error::config("only app bots have a tenant access token"));
+        };
+        let res: Value = self
+            .client
+            .post(format!(
+                "{}/open-apis/auth/v3/tenant_access_token/internal",
+                self.base_url
+            ))
+            .json(&json!({"app_id": app_id, "app_secret": app_secret}))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        if res["code"].as_i64() != Some(0) {
+            return Err(Error::auth(format!(
+                "feishu rejected the app credentials: {}",
+                res["msg"]
+            )));
+        }
+        let token = res["tenant_access_token"]
+            .as_str()
+            .ok_or_else(|| Error::parse(format!("token response without token: {}", res)))?
+            .to_string();
+        *self.tenant_token.borrow_mut() = Some(token.clone());
+        Ok(token)
+    }
+}
+
+impl Api for Feishu {
+    fn name(&self) -> &'static str {
+        "feishu"
+    }
+
+    async fn push(
+        &self,
+        update: &Update<'_>,
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let mut outcomes = Vec::new();
+        for item in &update.new.undone_list {
+            let outcome = self.send_card(new_card(item)).await;
+            outcomes.push((item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+
+    async fn push_changes(
+        &self,
+        changes: &[Change],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let mut outcomes = Vec::new();
+        for change in changes {
+            let outcome = self.send_card(change_card(change)).await;
+            outcomes.push((change.item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+
+    async fn push_completed(
+        &self,
+        completed: &[ActivityRecord],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        if !self.notify_completed {
+            return Ok(Vec::new());
+        }
+        let mut outcomes = Vec::new();
+        for record in completed {
+            let mut text = String::new();
+            if let Some(course_name) = &record.course_name {
+                text.push_str(&format!("**课程**：{}\n", course_name));
+            }
+            text.push_str(&format!("**作业**：{}", record.activity_name));
+            let outcome = self.send_card(card("✅ 作业已完成", "green", &text)).await;
+            outcomes.push((record.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+}
+
+/// The `sign` of a custom bot request: the base64 HMAC-SHA256 of nothing, keyed
+/// with the timestamp and the secret.
+pub fn sign(secret: &str, timestamp: i64) -> Result<String> {
+    let mac = Hmac::<Sha256>::new_from_slice(format!("{}\n{}", timestamp, secret).as_bytes())
+        .map_err(|e| Error::config(format!("invalid feishu secret: {}", e)))?;
+    Ok(STANDARD.encode(mac.finalize().into_bytes()))
+}
+
+fn new_card(item: &UndoneListItem) -> Value {
+    let mut text = String::new();
+    if let Some(course_info) = &item.course_info {
+        text.push_str(&format!("**课程**：{}\n", course_info.name));
+    }
+    text.push_str(&format!(
+        "**开始时间**：{}\n**截止时间**：{}\n**能否补交**：{}",
+        item.start_time.as_deref().unwrap_or("未知"),
+        item.end_time,
+        overtime_text(item.is_overtime_commit),
+    ));
+    let description = markdown(item.description.as_deref());
+    if !description.is_empty() {
+        text.push_str(&format!("\n\n**详细**：\n{}", description));
+    }
+    let attachments = item.attachments.as_deref().unwrap_or_default();
+    if !attachments.is_empty() {
+        text.push_str("\n\n**附件**：");
+        for attachment in attachments {
+            text.push_str(&format!("\n[{}]({})", attachment.name, attachment.url));
+        }
+    }
+    if item.details_unavailable {
+        text.push_str("\n\n**⚠️ 作业详情暂时无法获取**");
+    }
+    card(&format!("📚 {}", item.activity_name), "blue", &text)
+}
+
+fn change_card(change: &Change) -> Value {
+    let item = &change.item;
+    let mut text = String::new();
+    if let Some(course_info) = &item.course_info {
+        text.push_str(&format!("**课程**：{}\n", course_info.name));
+    }
+    text.push_str(&format!("**作业**：{}", item.activity_name));
+    for kind in &change.kinds {
+        match kind {
+            ChangeKind::Deadline { from, to } => {
+                text.push_str(&format!("\n**截止时间**：{} → {}", from, to));
+            }
+            ChangeKind::OvertimeCommit { from, to } => {
+                let text_of = |b: &bool| if *b { "能" } else { "否" };
+                text.push_str(&format!(
+                    "\n**能否补交**：{} → {}",
+                    text_of(from),
+                    text_of(to)
+                ));
+            }
+            ChangeKind::Description => {
+                text.push_str(&format!(
+                    "\n**作业说明已更新**：\n{}",
+                    markdown(item.description.as_deref())
+                ));
+            }
+        }
+    }
+    card("📢 作业有变动", "orange", &text)
+}
+
+/// A card with a coloured header and a markdown body.
+fn card(title: &str, template: &str, text: &str) -> Value {
+    json!({
+        "config": {"wide_screen_mode": true},
+        "header": {
+            "title": {"tag": "plain_text", "content": title},
+            "template": template,
+        },
+        "elements": [{"tag": "markdown", "content": text}],
+    })
+}
+
+fn markdown(html: Option<&str>) -> String {
+    let text = htmd::HtmlToMarkdown::new()
+        .convert(html.unwrap_or_default())
+        .unwrap_or_default();
+    let text = text.trim();
+    match text.char_indices().nth(DESCRIPTION_LIMIT) {
+        Some((end, _)) => format!("{}…", &text[..end]),
+        None => text.to_string(),
+    }
+}
+
+/// Feishu answers errors with a non-zero `code` and status 200, older custom
+/// bots with `StatusCode`.
+fn check(res: &Value) -> Result<()> {
+    match res["code"].as_i64().or_else(|| res["StatusCode"].as_i64()) {
+        Some(0) => Ok(()),
+        _ => Err(Error::upstream(format!(
+            "feishu rejected the card: {}",
+            res
+        ))),
+    }
+}
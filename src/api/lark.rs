@@ -1,43 +1,152 @@
-use super::Api;
-use crate::model::UndoneList;
-use anyhow::Result;
+use super::{Api, Outcomes, Update};
+use crate::config::LarkConfig;
+use crate::d1::{self, OpenActivity};
+use crate::error::Result;
+use crate::model::parse_ucloud_time;
+use crate::storage::{Database, KeyValue};
+use chrono::{DateTime, Duration, Utc};
 use tracing::info;
 
+const DEFAULT_BASE_URL: &str = "https://internal-api-lark-api.feishu.cn";
+pub const DEFAULT_STATUS_TEMPLATE: &str = "拼尽全力仍有 {count} 个DDL";
+/// KV key of the status text last written, so an unchanged status is not sent again.
+pub const STATUS_KEY: &str = "lark_status";
+/// What counts towards `{due_soon}`.
+const DUE_SOON: Duration = Duration::hours(24);
+/// Stands in for the `{next_*}` placeholders when nothing is due.
+const NOTHING_DUE: &str = "无";
+
+/// Sets the Lark profile status to a summary of the open assignments. Cards to a
+/// chat are sent by [`super::feishu::Feishu`].
 pub struct Lark {
     cookie: String,
+    status_template: String,
+    base_url: String,
+    client: reqwest::Client,
 }
 impl Lark {
     pub fn new(cookie: String) -> Self {
-        Self { cookie }
+        Self {
+            cookie,
+            status_template: DEFAULT_STATUS_TEMPLATE.to_string(),
+            base_url: DEFAULT_BASE_URL.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// See [`render_status`] for the placeholders.
+    pub fn with_status_template(mut self, status_template: String) -> Self {
+        self.status_template = status_template;
+        self
+    }
+
+    pub fn from_config(config: &LarkConfig) -> Self {
+        let mut lark = Self::new(config.cookie.clone());
+        if let Some(template) = &config.status_template {
+            lark = lark.with_status_template(template.clone());
+        }
+        match &config.api_url {
+            Some(url) => lark.with_base_url(url.clone()),
+            None => lark,
+        }
     }
 }
 
 impl Api for Lark {
-    async fn push(&self, message: &UndoneList) -> Result<()> {
-        let url = "https://internal-api-lark-api.feishu.cn/passport/users/details/";
-        let mut headers = worker::Headers::new();
-        headers.append("Content-Type", "application/json")?;
-        headers.append("Cookie", &self.cookie)?;
-
-        let ddl_count = message.undone_list.len();
-
-        let message = format!("拼尽全力仍有 {} 个DDL", ddl_count);
-
-        let body = serde_json::json!({"descriptionType": 0, "description": message});
-
-        let request = worker::Request::new_with_init(
-            url,
-            &worker::RequestInit {
-                method: worker::Method::Put,
-                headers,
-                body: Some(body.to_string().into()),
-                ..Default::default()
-            },
-        )?;
-
-        let mut response = worker::Fetch::Request(request).send().await?;
-        let res = response.text().await?;
+    fn name(&self) -> &'static str {
+        "lark"
+    }
+
+    /// Recomputes the status from the stored activities, which are up to date
+    /// by the time sinks run, and writes it unless it is what the last run wrote.
+    async fn push(
+        &self,
+        _update: &Update<'_>,
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let activities = d1::get_open_activities(d1::OWNER, db).await?;
+        let status = render_status(&self.status_template, &activities, Utc::now());
+        if kv.get(STATUS_KEY).await?.as_deref() == Some(status.as_str()) {
+            info!("lark status unchanged: {}", status);
+            return Ok(Vec::new());
+        }
+
+        let url = format!("{}/passport/users/details/", self.base_url);
+        let body = serde_json::json!({"descriptionType": 0, "description": status});
+        let res = self
+            .client
+            .put(&url)
+            .header("Cookie", &self.cookie)
+            .json(&body)
+            .send()
+            .await?
+            .error_for_status()?
+            .text()
+            .await?;
         info!("lark push response: {:?}", res);
-        Ok(())
+        kv.put(STATUS_KEY, &status).await?;
+        Ok(Vec::new())
+    }
+}
+
+/// Fills the status template:
+///
+/// - `{count}`: open assignments
+/// - `{due_soon}`: of those, due within 24 hours
+/// - `{next_deadline}`, `{next_name}`, `{next_course}`: the assignment due next,
+///   its deadline as `MM-DD HH:MM`
+pub fn render_status(template: &str, activities: &[OpenActivity], now: DateTime<Utc>) -> String {
+    let upcoming: Vec<_> = activities
+        .iter()
+        .filter_map(|activity| Some((activity, parse_ucloud_time(&activity.end_time)?)))
+        .filter(|(_, deadline)| *deadline > now)
+        .collect();
+    let due_soon = upcoming
+        .iter()
+        .filter(|(_, deadline)| deadline.to_utc() - now <= DUE_SOON)
+        .count();
+    let next = upcoming.iter().min_by_key(|(_, deadline)| *deadline);
+
+    // deadlines are in Beijing time, as UCloud shows them
+    let next_deadline = next.map(|(_, deadline)| deadline.format("%m-%d %H:%M").to_string());
+    let next_name = next.map(|(activity, _)| activity.activity_name.as_str());
+    let next_course = next.and_then(|(activity, _)| activity.course_name.as_deref());
+    let count = activities.len().to_string();
+    let due_soon = due_soon.to_string();
+    let value = |name: &str| match name {
+        "count" => Some(count.as_str()),
+        "due_soon" => Some(due_soon.as_str()),
+        "next_deadline" => Some(next_deadline.as_deref().unwrap_or(NOTHING_DUE)),
+        "next_name" => Some(next_name.unwrap_or(NOTHING_DUE)),
+        "next_course" => Some(next_course.unwrap_or(NOTHING_DUE)),
+        _ => None,
+    };
+
+    // one pass over the template, so that braces in names are left as they are
+    let mut status = String::new();
+    let mut rest = template;
+    while let Some(start) = rest.find('{') {
+        status.push_str(&rest[..start]);
+        let placeholder = rest[start + 1..]
+            .find('}')
+            .and_then(|end| Some((value(&rest[start + 1..start + 1 + end])?, end)));
+        match placeholder {
+            Some((value, end)) => {
+                status.push_str(value);
+                rest = &rest[start + end + 2..];
+            }
+            None => {
+                status.push('{');
+                rest = &rest[start + 1..];
+            }
+        }
     }
+    status.push_str(rest);
+    status
 }
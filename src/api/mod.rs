@@ -1,12 +1,219 @@
+pub mod caldav;
+pub mod feishu;
 pub mod lark;
 pub mod telegram;
 pub mod ticktick;
+pub mod webhook;
 
-use anyhow::Result;
+use crate::error::Result;
 
+use crate::change::Change;
+use crate::config::{Config, TickTickConfig};
+use crate::d1::ActivityRecord;
 use crate::model::UndoneList;
+use crate::storage::{Database, KeyValue};
+use tracing::error;
 
+/// What a sink gets to see on every run.
+pub struct Update<'a> {
+    /// Everything currently open on UCloud.
+    pub undone: &'a UndoneList,
+    /// Activities that have not been delivered to this sink yet.
+    pub new: &'a UndoneList,
+}
+
+/// How each activity fared with a sink, by activity id, so that only the ones
+/// that failed are retried. Activities a sink had nothing to send for may be
+/// left out and count as delivered.
+pub type Outcomes = Vec<(String, Result<()>)>;
+
+/// A delivery destination. Every method gets the database so that sinks can
+/// keep their own bookkeeping, such as the ids of the tasks they created.
 pub trait Api {
+    /// Stable identifier used in logs and config.
+    fn name(&self) -> &'static str;
+
+    /// Delivers the new activities. An error means none of them arrived.
+    #[allow(async_fn_in_trait)]
+    async fn push(
+        &self,
+        update: &Update<'_>,
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<Outcomes>;
+
+    /// Announces edits to activities that were already pushed. Changes that
+    /// fail are offered again on the next run.
+    #[allow(async_fn_in_trait)]
+    async fn push_changes(
+        &self,
+        _changes: &[Change],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        Ok(Vec::new())
+    }
+
+    /// Announces activities that were submitted and left the undone list.
+    /// Completions that fail are offered again on the next run.
     #[allow(async_fn_in_trait)]
-    async fn push(&self, message: &UndoneList) -> Result<()>;
+    async fn push_completed(
+        &self,
+        _completed: &[ActivityRecord],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        Ok(Vec::new())
+    }
+}
+
+/// Every sink the pipeline can deliver to. Adding a sink means adding a
+/// variant here and a branch in [`notifiers`]; the pipeline itself only
+/// iterates over whatever is returned.
+pub enum Notifier {
+    Telegram(telegram::Telegram),
+    TickTick(Box<ticktick::TickTick>),
+    Lark(lark::Lark),
+    Feishu(feishu::Feishu),
+    CalDav(caldav::CalDav),
+    Webhook(webhook::Webhook),
+}
+
+impl Api for Notifier {
+    fn name(&self) -> &'static str {
+        match self {
+            Notifier::Telegram(n) => n.name(),
+            Notifier::TickTick(n) => n.name(),
+            Notifier::Lark(n) => n.name(),
+            Notifier::Feishu(n) => n.name(),
+            Notifier::CalDav(n) => n.name(),
+            Notifier::Webhook(n) => n.name(),
+        }
+    }
+
+    async fn push(
+        &self,
+        update: &Update<'_>,
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        match self {
+            Notifier::Telegram(n) => n.push(update, db, kv).await,
+            Notifier::TickTick(n) => n.push(update, db, kv).await,
+            Notifier::Lark(n) => n.push(update, db, kv).await,
+            Notifier::Feishu(n) => n.push(update, db, kv).await,
+            Notifier::CalDav(n) => n.push(update, db, kv).await,
+            Notifier::Webhook(n) => n.push(update, db, kv).await,
+        }
+    }
+
+    async fn push_changes(
+        &self,
+        changes: &[Change],
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        match self {
+            Notifier::Telegram(n) => n.push_changes(changes, db, kv).await,
+            Notifier::TickTick(n) => n.push_changes(changes, db, kv).await,
+            Notifier::Lark(n) => n.push_changes(changes, db, kv).await,
+            Notifier::Feishu(n) => n.push_changes(changes, db, kv).await,
+            Notifier::CalDav(n) => n.push_changes(changes, db, kv).await,
+            Notifier::Webhook(n) => n.push_changes(changes, db, kv).await,
+        }
+    }
+
+    async fn push_completed(
+        &self,
+        completed: &[ActivityRecord],
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        match self {
+            Notifier::Telegram(n) => n.push_completed(completed, db, kv).await,
+            Notifier::TickTick(n) => n.push_completed(completed, db, kv).await,
+            Notifier::Lark(n) => n.push_completed(completed, db, kv).await,
+            Notifier::Feishu(n) => n.push_completed(completed, db, kv).await,
+            Notifier::CalDav(n) => n.push_completed(completed, db, kv).await,
+            Notifier::Webhook(n) => n.push_completed(completed, db, kv).await,
+        }
+    }
+}
+
+/// The names of the sinks the config enables, whether or not they can be built
+/// this run, so that work queued for a sink waits for it to come back.
+pub fn configured_sinks(config: &Config) -> Vec<&'static str> {
+    [
+        ("lark", config.lark.is_some()),
+        ("feishu", config.feishu.is_some()),
+        ("telegram", config.telegram.is_some()),
+        ("ticktick", config.ticktick.is_some()),
+        ("caldav", config.caldav.is_some()),
+        ("webhook", config.webhook.is_some()),
+    ]
+    .into_iter()
+    .filter(|(_, configured)| *configured)
+    .map(|(name, _)| name)
+    .collect()
+}
+
+/// Builds the enabled sinks in delivery order.
+pub async fn notifiers(config: &Config, kv: &impl KeyValue) -> Result<Vec<Notifier>> {
+    let mut notifiers = Vec::new();
+
+    if let Some(lark) = &config.lark {
+        notifiers.push(Notifier::Lark(lark::Lark::from_config(lark)));
+    }
+
+    if let Some(feishu) = &config.feishu {
+        notifiers.push(Notifier::Feishu(feishu::Feishu::from_config(feishu)));
+    }
+
+    let bot = config
+        .telegram
+        .as_ref()
+        .map(telegram::Telegram::from_config);
+    if let Some(bot) = &bot {
+        notifiers.push(Notifier::Telegram(bot.clone()));
+    }
+
+    if let Some(ticktick_config) = &config.ticktick {
+        // a TickTick problem only costs this run's TickTick delivery
+        match ticktick(config, ticktick_config, bot.as_ref(), kv).await {
+            Ok(Some(ticktick)) => notifiers.push(Notifier::TickTick(Box::new(ticktick))),
+            Ok(None) => {}
+            Err(e) => error!("skipping ticktick: {:?}", e),
+        }
+    }
+
+    if let Some(caldav) = &config.caldav {
+        notifiers.push(Notifier::CalDav(caldav::CalDav::from_config(caldav)));
+    }
+
+    if let Some(webhook) = &config.webhook {
+        notifiers.push(Notifier::Webhook(webhook::Webhook::from_config(webhook)));
+    }
+
+    Ok(notifiers)
+}
+
+/// The TickTick sink, or none if it is not logged in, in which case a login
+/// link is sent.
+async fn ticktick(
+    config: &Config,
+    ticktick_config: &TickTickConfig,
+    bot: Option<&telegram::Telegram>,
+    kv: &impl KeyValue,
+) -> Result<Option<ticktick::TickTick>> {
+    let mut ticktick =
+        ticktick::TickTick::from_config(ticktick_config, config.cipher.as_ref(), kv).await?;
+    if let Some(bot) = bot {
+        ticktick = ticktick.with_login_prompt(bot.clone(), ticktick_config.redirect_uri.clone());
+    }
+    if ticktick.ensure_token(kv).await? {
+        Ok(Some(ticktick))
+    } else {
+        ticktick.prompt_login(kv).await?;
+        Ok(None)
+    }
 }
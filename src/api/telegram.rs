@@ -1,15 +1,41 @@
-use crate::model::UndoneList;
+use crate::actions;
+use crate::change::{Change, ChangeKind};
+use crate::config::TelegramConfig;
+use crate::d1::ActivityRecord;
+use crate::error::Error;
+pub use crate::html::escape_html;
+use crate::html::{sanitize, split_html, Sanitized, CAPTION_LIMIT, MESSAGE_LIMIT};
+use crate::model::{Attachment, UndoneListItem};
+use crate::storage::{Database, KeyValue};
 
-use super::Api;
-use anyhow::Result;
-use html5tokenizer::{NaiveParser, Token};
+use super::{Api, Outcomes, Update};
+use crate::error::Result;
+use futures::StreamExt;
+use reqwest::multipart::{Form, Part};
 use serde::Serialize;
-use tracing::info;
-use worker::{Fetch, Headers, Method, Request, RequestInit};
+use tracing::{error, info};
 
+const DEFAULT_BASE_URL: &str = "https://api.telegram.org";
+/// Assignment page behind the "open in UCloud" button, `{id}` is the activity id.
+const DEFAULT_OPEN_URL: &str =
+    "https://ucloud.bupt.edu.cn/uclass/course.html#/student/assignmentDetails_fullpage?assignmentId={id}";
+/// Largest file a bot may upload.
+const MAX_UPLOAD_BYTES: u64 = 50 * 1024 * 1024;
+/// Most files Telegram accepts in one media group.
+const MEDIA_GROUP_LIMIT: usize = 10;
+/// Most bytes of attachments held at once for a media group, to stay well
+/// within the Worker's memory.
+const MEDIA_GROUP_BYTES: u64 = 20 * 1024 * 1024;
+
+#[derive(Clone)]
 pub struct Telegram {
     token: String,
     chat_id: String,
+    base_url: String,
+    notify_completed: bool,
+    digest_only: bool,
+    open_url: String,
+    client: reqwest::Client,
 }
 
 #[derive(Serialize, Debug)]
@@ -17,47 +43,94 @@ struct TelegramMessage<'a> {
     chat_id: &'a str,
     text: &'a str,
     parse_mode: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reply_markup: Option<&'a serde_json::Value>,
 }
 
 impl Telegram {
     pub fn new(token: String, chat_id: String) -> Self {
-        Self { token, chat_id }
+        Self {
+            token,
+            chat_id,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            notify_completed: false,
+            digest_only: false,
+            open_url: DEFAULT_OPEN_URL.to_string(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    pub fn from_config(config: &TelegramConfig) -> Self {
+        let mut bot = Self::new(config.token.clone(), config.chat_id.clone());
+        bot.notify_completed = config.notify_completed;
+        bot.digest_only = config.digest_only;
+        if let Some(open_url) = &config.open_url {
+            bot.open_url = open_url.clone();
+        }
+        match &config.api_url {
+            Some(url) => bot.with_base_url(url.clone()),
+            None => bot,
+        }
+    }
+
+    /// The action buttons shown under an assignment.
+    pub fn keyboard(&self, activity_id: &str) -> serde_json::Value {
+        actions::keyboard(activity_id, &self.open_url)
     }
 
     pub async fn send_message(&self, message: &str) -> Result<()> {
-        let url = &format!("https://api.telegram.org/bot{}/sendMessage", self.token).to_string();
+        self.send(message, None).await
+    }
 
-        let message_body = TelegramMessage {
-            chat_id: &self.chat_id,
-            text: message,
-            parse_mode: "HTML",
-        };
-        let body = serde_json::to_string(&message_body)
-            .map_err(|e| worker::Error::RustError(format!("Serialization failed: {}", e)))?;
-        let mut headers = Headers::new();
-        headers.append("Content-Type", "application/json")?;
-
-        info!("message: {:?}", message_body);
-
-        let request_init = RequestInit {
-            method: Method::Post,
-            headers,
-            body: Some(body.into()),
-            ..Default::default()
-        };
-        let request = Request::new_with_init(&url, &request_init)?;
-        let mut response = Fetch::Request(request).send().await?;
-        let res = response.json::<serde_json::Value>().await?;
-        if res["ok"].as_bool().unwrap() {
-            info!("telegram push success: {:?}", res);
-        } else {
-            info!("telegram push failed: {:?}", res);
+    pub async fn send_message_with_keyboard(
+        &self,
+        message: &str,
+        keyboard: &serde_json::Value,
+    ) -> Result<()> {
+        self.send(message, Some(keyboard)).await
+    }
+
+    /// Sends `message` in as many parts as it takes, the keyboard goes under the last one.
+    async fn send(&self, message: &str, reply_markup: Option<&serde_json::Value>) -> Result<()> {
+        let url = format!("{}/bot{}/sendMessage", self.base_url, self.token);
+
+        let chunks = split_html(message, MESSAGE_LIMIT);
+        let last = chunks.len().saturating_sub(1);
+        for (i, chunk) in chunks.iter().enumerate() {
+            let message_body = TelegramMessage {
+                chat_id: &self.chat_id,
+                text: chunk,
+                parse_mode: "HTML",
+                reply_markup: reply_markup.filter(|_| i == last),
+            };
+            info!("message: {:?}", message_body);
+
+            let res = self
+                .client
+                .post(&url)
+                .json(&message_body)
+                .send()
+                .await?
+                .json::<serde_json::Value>()
+                .await?;
+            check(res)?;
         }
         Ok(())
     }
 
+    /// Sends the images as an album. A caption too long for it is cut and the rest
+    /// follows in a message.
     pub async fn send_media_group(&self, media_urls: Vec<String>, caption: &str) -> Result<()> {
-        let url = &format!("https://api.telegram.org/bot{}/sendMediaGroup", self.token).to_string();
+        let url = format!("{}/bot{}/sendMediaGroup", self.base_url, self.token);
+
+        let mut chunks = split_html(caption, CAPTION_LIMIT).into_iter();
+        let caption = chunks.next().unwrap_or_default();
+        let rest: Vec<String> = chunks.collect();
 
         let mut media_group = media_urls
             .into_iter()
@@ -81,122 +154,407 @@ impl Telegram {
             "media": media_group,
         });
 
-        let body = serde_json::to_string(&message_body)
-            .map_err(|e| worker::Error::RustError(format!("Serialization failed: {}", e)))?;
-        let mut headers = Headers::new();
-        headers.append("Content-Type", "application/json")?;
+        let res = self
+            .client
+            .post(&url)
+            .json(&message_body)
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        check(res)?;
 
-        let request_init = RequestInit {
-            method: Method::Post,
-            headers,
-            body: Some(body.into()),
-            ..Default::default()
-        };
-        let request = Request::new_with_init(&url, &request_init)?;
-        let mut response = Fetch::Request(request).send().await?;
-        let res = response.json::<serde_json::Value>().await?;
-        if res["ok"].as_bool().unwrap() {
-            info!("telegram push success: {:?}", res);
-        } else {
-            info!("telegram push failed: {:?}", res);
+        if rest.is_empty() {
+            return Ok(());
         }
-        Ok(())
+        self.send_message(&rest.join("\n")).await
     }
-}
 
-impl Api for Telegram {
-    async fn push(&self, undone_list: &UndoneList) -> Result<()> {
-        if undone_list.undone_list.is_empty() {
+    /// Replaces the text and keyboard of a message the bot sent earlier.
+    pub async fn edit_message_text(
+        &self,
+        message_id: i64,
+        message: &str,
+        keyboard: Option<&serde_json::Value>,
+    ) -> Result<()> {
+        let url = format!("{}/bot{}/editMessageText", self.base_url, self.token);
+
+        let mut body = serde_json::json!({
+            "chat_id": self.chat_id,
+            "message_id": message_id,
+            "text": message,
+            "parse_mode": "HTML",
+        });
+        if let Some(keyboard) = keyboard {
+            body["reply_markup"] = keyboard.clone();
+        }
+
+        let res = self
+            .client
+            .post(&url)
+            .json(&body)
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        // pressing the button of the page already shown changes nothing
+        if res["description"]
+            .as_str()
+            .is_some_and(|d| d.contains("message is not modified"))
+        {
+            info!("telegram edit: {:?}", res);
             return Ok(());
         }
-        for item in &undone_list.undone_list {
-            info!("pushing message: {:?}", item);
-            let mut msg = String::new();
-            msg.push_str("<b>❤️小助手提醒你写作业啦！</b>\n\n");
-            let (description, image_urls) =
-                filter_and_extract_image(item.description.as_ref().unwrap_or(&String::new()));
-
-            msg.push_str(
-                if let Some(course_info) = &item.course_info {
-                    format!(
-                        "<b>课程</b>：{}\n<b>作业</b>：{}\n<b>开始时间</b>：{}\n<b>结束时间</b>：{}\n<b>能否补交</b>：{}",
-                        course_info.name,
-                        item.activity_name,
-                        item.start_time.as_ref().unwrap(),
-                        item.end_time,
-                        if item.is_overtime_commit.unwrap() {
-                            "能"
-                        } else {
-                            "否"
-                        }
-
-                    )
-                } else {
-                    format!(
-                        "<b>作业</b>：{}\n<b>开始时间</b>：{}\n<b>结束时间</b>：{}\n<b>能否补交</b>：{}",
-                        item.activity_name,
-                        item.start_time.as_ref().unwrap(),
-                        item.end_time,
-                        if item.is_overtime_commit.unwrap() {
-                            "能"
-                        } else {
-                            "否"
-                        }
-                    )
-                }
-                .as_str(),
-            );
+        check(res)
+    }
+
+    pub async fn answer_callback_query(&self, callback_query_id: &str, text: &str) -> Result<()> {
+        let url = format!("{}/bot{}/answerCallbackQuery", self.base_url, self.token);
+
+        let res = self
+            .client
+            .post(&url)
+            .json(&serde_json::json!({
+                "callback_query_id": callback_query_id,
+                "text": text,
+            }))
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        check(res)
+    }
+
+    pub async fn delete_message(&self, message_id: i64) -> Result<()> {
+        let url = format!("{}/bot{}/deleteMessage", self.base_url, self.token);
+
+        let res = self
+            .client
+            .post(&url)
+            .json(&serde_json::json!({
+                "chat_id": self.chat_id,
+                "message_id": message_id,
+            }))
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        check(res)
+    }
+
+    pub async fn send_document(&self, name: &str, bytes: Vec<u8>) -> Result<()> {
+        let url = format!("{}/bot{}/sendDocument", self.base_url, self.token);
 
-            if !description.trim().is_empty() {
-                msg.push_str(format!("\n\n<b>详细：</b>\n\n{}", description.trim()).as_str());
+        let form = Form::new()
+            .text("chat_id", self.chat_id.clone())
+            .part("document", Part::bytes(bytes).file_name(name.to_string()));
+
+        let res = self
+            .client
+            .post(&url)
+            .multipart(form)
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        check(res)
+    }
+
+    /// Uploads files as one document or an album. The message already links
+    /// every attachment, so a failure is only logged.
+    async fn upload(&self, mut files: Vec<(String, Vec<u8>)>) {
+        let result = match files.len() {
+            0 => return,
+            1 => {
+                let (name, bytes) = files.remove(0);
+                self.send_document(&name, bytes).await
             }
+            _ => self.send_document_group(files).await,
+        };
+        if let Err(e) = result {
+            error!("could not upload the attachments: {:?}", e);
+        }
+    }
+
+    async fn send_document_group(&self, files: Vec<(String, Vec<u8>)>) -> Result<()> {
+        let url = format!("{}/bot{}/sendMediaGroup", self.base_url, self.token);
 
-            if image_urls.is_empty() {
-                self.send_message(&msg).await?;
+        let media: Vec<serde_json::Value> = (0..files.len())
+            .map(|i| {
+                serde_json::json!({
+                    "type": "document",
+                    "media": format!("attach://file{}", i),
+                })
+            })
+            .collect();
+        let mut form = Form::new()
+            .text("chat_id", self.chat_id.clone())
+            .text("media", serde_json::to_string(&media)?);
+        for (i, (name, bytes)) in files.into_iter().enumerate() {
+            form = form.part(format!("file{}", i), Part::bytes(bytes).file_name(name));
+        }
+
+        let res = self
+            .client
+            .post(&url)
+            .multipart(form)
+            .send()
+            .await?
+            .json::<serde_json::Value>()
+            .await?;
+        check(res)
+    }
+
+    /// Starts downloading an attachment, refusing files too large to upload.
+    async fn fetch(&self, attachment: &Attachment) -> Result<reqwest::Response> {
+        let response = self
+            .client
+            .get(&attachment.url)
+            .send()
+            .await?
+            .error_for_status()?;
+        if response
+            .content_length()
+            .is_some_and(|length| length > MAX_UPLOAD_BYTES)
+        {
+            return Err(too_large(attachment));
+        }
+        Ok(response)
+    }
+
+    /// The message for an activity, followed by its attachments.
+    async fn send_new(&self, item: &UndoneListItem) -> Result<()> {
+        info!("pushing message: {:?}", item);
+        let mut msg = String::new();
+        msg.push_str("<b>❤️小助手提醒你写作业啦！</b>\n\n");
+        let Sanitized {
+            text: description,
+            images: image_urls,
+        } = sanitize(item.description.as_deref().unwrap_or_default());
+
+        msg.push_str(
+            if let Some(course_info) = &item.course_info {
+                format!(
+                    "<b>课程</b>：{}\n<b>作业</b>：{}\n<b>开始时间</b>：{}\n<b>结束时间</b>：{}\n<b>能否补交</b>：{}",
+                    escape_html(&course_info.name),
+                    escape_html(&item.activity_name),
+                    item.start_time.as_deref().unwrap_or("未知"),
+                    item.end_time,
+                    overtime_text(item.is_overtime_commit),
+                )
             } else {
-                self.send_media_group(image_urls, &msg).await?;
+                format!(
+                    "<b>作业</b>：{}\n<b>开始时间</b>：{}\n<b>结束时间</b>：{}\n<b>能否补交</b>：{}",
+                    escape_html(&item.activity_name),
+                    item.start_time.as_deref().unwrap_or("未知"),
+                    item.end_time,
+                    overtime_text(item.is_overtime_commit),
+                )
             }
+            .as_str(),
+        );
+
+        if !description.trim().is_empty() {
+            msg.push_str(format!("\n\n<b>详细：</b>\n\n{}", description.trim()).as_str());
         }
+        let attachments = item.attachments.as_deref().unwrap_or_default();
+        if !attachments.is_empty() {
+            msg.push_str("\n\n<b>附件：</b>");
+            for attachment in attachments {
+                msg.push_str(&format!(
+                    "\n<a href=\"{}\">{}</a>",
+                    escape_html(&attachment.url).replace('"', "&quot;"),
+                    escape_html(&attachment.name)
+                ));
+            }
+        }
+        if item.details_unavailable {
+            msg.push_str("\n\n<b>⚠️ 作业详情暂时无法获取</b>");
+        }
+
+        let keyboard = self.keyboard(&item.activity_id);
+        if image_urls.is_empty() {
+            self.send_message_with_keyboard(&msg, &keyboard).await?;
+        } else if let Err(e) = self.send_media_group(image_urls, &msg).await {
+            // usually Telegram could not fetch an image, the text alone still helps
+            error!("album for {} failed: {:?}", item.activity_id, e);
+            self.send_message_with_keyboard(&msg, &keyboard).await?;
+        } else {
+            // albums cannot carry buttons, so they follow in a message of their own
+            self.send_message_with_keyboard(
+                &format!("<b>作业</b>：{}", escape_html(&item.activity_name)),
+                &keyboard,
+            )
+            .await?;
+        }
+        self.forward_attachments(attachments).await;
         Ok(())
     }
-}
 
-fn filter_and_extract_image(html: &str) -> (String, Vec<String>) {
-    let allowed_tags = vec![
-        "b", "strong", "i", "em", "u", "ins", "s", "strike", "del", "a", "code", "pre",
-    ];
-    let mut new_html = String::new();
-    let mut image_urls = Vec::new();
-    for token in NaiveParser::new(html).flatten() {
-        match token {
-            Token::StartTag(tag) => {
-                if tag.name == "img" {
-                    if let Some(src) = tag.attributes.get("src") {
-                        image_urls.push(src.to_owned());
-                    }
+    /// Forwards the attachments that can be downloaded and uploaded, one album
+    /// at a time so that only its files are in memory. The message already
+    /// links every attachment, so the ones that fail are only logged.
+    async fn forward_attachments(&self, attachments: &[Attachment]) {
+        let mut group = Vec::new();
+        let mut group_bytes = 0;
+        for attachment in attachments {
+            let response = match self.fetch(attachment).await {
+                Ok(response) => response,
+                Err(e) => {
+                    error!("could not download {}: {:?}", attachment.name, e);
                     continue;
                 }
-                if !allowed_tags.contains(&tag.name.as_str()) {
-                    continue;
+            };
+            // without a Content-Length the file may be as large as allowed
+            let size = response.content_length().unwrap_or(MAX_UPLOAD_BYTES);
+            if group.len() == MEDIA_GROUP_LIMIT
+                || (!group.is_empty() && group_bytes + size > MEDIA_GROUP_BYTES)
+            {
+                self.upload(std::mem::take(&mut group)).await;
+                group_bytes = 0;
+            }
+            match read_body(response, attachment).await {
+                Ok(bytes) => {
+                    group_bytes += bytes.len() as u64;
+                    group.push((attachment.name.clone(), bytes));
                 }
-                new_html.push_str(&format!("<{}>", tag.name));
+                Err(e) => error!("could not download {}: {:?}", attachment.name, e),
             }
-            Token::Char(c) => {
-                new_html.push(c);
+        }
+        self.upload(group).await;
+    }
+}
+
+impl Api for Telegram {
+    fn name(&self) -> &'static str {
+        "telegram"
+    }
+
+    async fn push(
+        &self,
+        update: &Update<'_>,
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        if self.digest_only {
+            return Ok(Vec::new());
+        }
+        let mut outcomes = Vec::new();
+        for item in &update.new.undone_list {
+            let outcome = self.send_new(item).await;
+            outcomes.push((item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+
+    async fn push_changes(
+        &self,
+        changes: &[Change],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let mut outcomes = Vec::new();
+        for change in changes {
+            let item = &change.item;
+            let mut msg = String::from("<b>📢 作业有变动！</b>\n\n");
+            if let Some(course_info) = &item.course_info {
+                msg.push_str(&format!(
+                    "<b>课程</b>：{}\n",
+                    escape_html(&course_info.name)
+                ));
             }
-            Token::EndTag(tag) => {
-                if tag.name == "p" || tag.name == "br" {
-                    new_html.push_str("\n");
-                    continue;
-                }
-                if !allowed_tags.contains(&tag.name.as_str()) {
-                    continue;
+            msg.push_str(&format!(
+                "<b>作业</b>：{}\n",
+                escape_html(&item.activity_name)
+            ));
+
+            for kind in &change.kinds {
+                match kind {
+                    ChangeKind::Deadline { from, to } => {
+                        msg.push_str(&format!("\n<b>截止时间</b>：{} → {}", from, to));
+                    }
+                    ChangeKind::OvertimeCommit { from, to } => {
+                        let text = |b: &bool| if *b { "能" } else { "否" };
+                        msg.push_str(&format!("\n<b>能否补交</b>：{} → {}", text(from), text(to)));
+                    }
+                    ChangeKind::Description => {
+                        let description =
+                            sanitize(item.description.as_deref().unwrap_or_default()).text;
+                        msg.push_str(
+                            format!("\n<b>作业说明已更新：</b>\n\n{}", description.trim()).as_str(),
+                        );
+                    }
                 }
-                new_html.push_str(&format!("</{}>", tag.name));
             }
-            Token::EndOfFile => {}
-            _ => panic!("unexpected input"),
+
+            let outcome = self.send_message(&msg).await;
+            outcomes.push((item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+
+    async fn push_completed(
+        &self,
+        completed: &[ActivityRecord],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        if !self.notify_completed {
+            return Ok(Vec::new());
+        }
+        let mut outcomes = Vec::new();
+        for record in completed {
+            let outcome = self
+                .send_message(&format!(
+                    "<b>✅ 作业已完成</b>\n\n<b>作业</b>：{}",
+                    escape_html(&record.activity_name)
+                ))
+                .await;
+            outcomes.push((record.activity_id.clone(), outcome));
         }
+        Ok(outcomes)
+    }
+}
+
+/// Reads a download, holding the upload limit even when Content-Length is
+/// missing or wrong.
+async fn read_body(response: reqwest::Response, attachment: &Attachment) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    let mut body = response.bytes_stream();
+    while let Some(chunk) = body.next().await {
+        bytes.extend_from_slice(&chunk?);
+        if bytes.len() as u64 > MAX_UPLOAD_BYTES {
+            return Err(too_large(attachment));
+        }
+    }
+    Ok(bytes)
+}
+
+fn too_large(attachment: &Attachment) -> Error {
+    Error::upstream(format!("{} is too large to upload", attachment.name))
+}
+
+pub(crate) fn overtime_text(is_overtime_commit: Option<bool>) -> &'static str {
+    match is_overtime_commit {
+        Some(true) => "能",
+        Some(false) => "否",
+        None => "未知",
+    }
+}
+
+/// Turns an `ok: false` answer into an error, so that a rejected message is retried.
+fn check(res: serde_json::Value) -> Result<()> {
+    if res["ok"].as_bool().unwrap_or(false) {
+        info!("telegram push success: {:?}", res);
+        return Ok(());
     }
-    (new_html, image_urls)
+    Err(Error::Upstream {
+        status: res["error_code"]
+            .as_u64()
+            .and_then(|c| u16::try_from(c).ok()),
+        message: res["description"]
+            .as_str()
+            .unwrap_or("telegram rejected the message")
+            .to_string(),
+    })
 }
@@ -1,52 +1,249 @@
-use crate::model::Task;
-
-use super::Api;
-use anyhow::Result;
-use base64::prelude::BASE64_STANDARD;
-use base64::Engine;
-use tracing::info;
-use worker::kv::KvStore;
-use worker::{Fetch, Request};
-use worker::{RequestInit, Url};
+use crate::change::Change;
+use crate::config::{CourseMapping, TickTickConfig};
+use crate::crypto::{self, Cipher};
+use crate::d1::{self, ActivityRecord, TickTickTask};
+use crate::model::{ucloud_offset, Task, UndoneListItem};
+use crate::storage::{Database, KeyValue};
+
+use super::telegram::Telegram;
+use super::{Api, Outcomes, Update};
+use crate::error::{Error, Result};
+use reqwest::{RequestBuilder, Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use tracing::{error, info};
+use worker::Url;
+
+const DEFAULT_BASE_URL: &str = "https://dida365.com";
+
+/// KV key of the JSON-encoded [`TokenSet`], encrypted when a [`Cipher`] is configured.
+pub const TOKEN_KEY: &str = "ticktick_token";
+/// KV key of the bare access token stored by older versions.
+const LEGACY_TOKEN_KEY: &str = "access_token";
+/// Prefix of the KV keys caching project ids by project name.
+const PROJECT_KEY_PREFIX: &str = "ticktick_project:";
+/// KV key of the OAuth state of the login link last sent. Present while that
+/// link is unused, so the cron does not send a new one on every run.
+const STATE_KEY: &str = "state";
+/// Starts the last line of every task's content, followed by the activity id, so
+/// that a task can be found again when its id is not stored.
+const MARKER_PREFIX: &str = "UCloud ID：";
+/// Refresh this long before the access token expires.
+const REFRESH_MARGIN_SECS: i64 = 24 * 3600;
+
+/// Tokens returned by the OAuth token endpoint.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct TokenSet {
+    pub access_token: String,
+    pub refresh_token: Option<String>,
+    /// Unix timestamp, unknown for tokens migrated from the legacy key.
+    pub expires_at: Option<i64>,
+}
+
+impl TokenSet {
+    fn from_response(res: &serde_json::Value, previous: Option<&TokenSet>) -> Result<Self> {
+        let access_token = res["access_token"]
+            .as_str()
+            .ok_or_else(|| Error::parse(format!("token response without access_token: {}", res)))?;
+        Ok(Self {
+            access_token: access_token.to_string(),
+            // refresh responses may omit the refresh token when it is not rotated
+            refresh_token: res["refresh_token"]
+                .as_str()
+                .map(str::to_string)
+                .or_else(|| previous.and_then(|p| p.refresh_token.clone())),
+            expires_at: res["expires_in"]
+                .as_i64()
+                .map(|secs| chrono::Utc::now().timestamp() + secs),
+        })
+    }
+
+    fn expires_within(&self, secs: i64) -> bool {
+        self.expires_at
+            .is_some_and(|at| at - chrono::Utc::now().timestamp() < secs)
+    }
+
+    /// Reads the stored tokens. With a cipher, tokens found in plaintext, under a
+    /// previous key or in the legacy key are stored again encrypted with the current key.
+    pub async fn load(kv: &impl KeyValue, cipher: Option<&Cipher>) -> Result<Option<Self>> {
+        if let Some(stored) = kv.get(TOKEN_KEY).await? {
+            let token: Self = if Cipher::is_encrypted(&stored) {
+                let cipher = cipher.ok_or_else(|| {
+                    Error::config(format!(
+                        "the ticktick token is encrypted but {} is not set",
+                        crypto::KEY_SECRET
+                    ))
+                })?;
+                serde_json::from_str(&cipher.decrypt(&stored)?)?
+            } else {
+                serde_json::from_str(&stored)?
+            };
+            if cipher.is_some_and(|cipher| !cipher.is_current(&stored)) {
+                info!("re-encrypting the ticktick token");
+                token.save(kv, cipher).await?;
+            }
+            return Ok(Some(token));
+        }
+
+        let Some(access_token) = kv.get(LEGACY_TOKEN_KEY).await? else {
+            return Ok(None);
+        };
+        let token = Self {
+            access_token,
+            refresh_token: None,
+            expires_at: None,
+        };
+        if cipher.is_some() {
+            info!("moving the legacy ticktick token");
+            token.save(kv, cipher).await?;
+            kv.delete(LEGACY_TOKEN_KEY).await?;
+        }
+        Ok(Some(token))
+    }
+
+    async fn save(&self, kv: &impl KeyValue, cipher: Option<&Cipher>) -> Result<()> {
+        let json = serde_json::to_string(self)?;
+        match cipher {
+            Some(cipher) => kv.put(TOKEN_KEY, &cipher.encrypt(&json)?).await,
+            None => kv.put(TOKEN_KEY, &json).await,
+        }
+    }
+}
+
+/// Converts a UCloud timestamp to the format the open API expects.
+/// The line that ties a task to `activity_id`.
+/// Whether a refresh failed because the refresh token itself is gone or was
+/// turned down, as opposed to TickTick being unreachable.
+fn refresh_rejected(error: &Error) -> bool {
+    match error {
+        Error::Auth(_) => true,
+        Error::Upstream {
+            status: Some(status),
+            ..
+        } => *status == 400 || *status == 401,
+        _ => false,
+    }
+}
+
+/// Whether TickTick turned a request down in a way that points at a project
+/// that was deleted or never existed.
+fn project_rejected(error: &Error) -> bool {
+    matches!(
+        error,
+        Error::Upstream {
+            status: Some(400 | 404),
+            ..
+        }
+    )
+}
+
+fn marker(activity_id: &str) -> String {
+    format!("{}{}", MARKER_PREFIX, activity_id)
+}
+
+fn ticktick_date(time: &str, format: &str) -> Result<String> {
+    let date = chrono::NaiveDateTime::parse_from_str(time, format)?
+        .and_local_timezone(ucloud_offset())
+        .single()
+        .ok_or_else(|| Error::parse(format!("invalid time {}", time)))?;
+    Ok(date.format("%Y-%m-%dT%H:%M:%S%z").to_string())
+}
+
 pub struct TickTick {
     client_id: String,
     client_secret: String,
-    project_id: String,
-    pub access_token: Option<String>,
+    project_id: Option<String>,
+    project_name: String,
+    course_mapping: CourseMapping,
+    base_url: String,
+    client: reqwest::Client,
+    token: RefCell<Option<TokenSet>>,
+    cipher: Option<Cipher>,
+    /// Where to send a fresh login link once the tokens are beyond repair.
+    login_prompt: Option<(Telegram, String)>,
 }
 
 impl TickTick {
     pub async fn new(
         client_id: String,
         client_secret: String,
-        project_id: String,
-        kv: KvStore,
-    ) -> Self {
-        let access_token = kv.get("access_token").text().await.unwrap();
-        Self {
+        project_name: String,
+        cipher: Option<Cipher>,
+        kv: &impl KeyValue,
+    ) -> Result<Self> {
+        let token = TokenSet::load(kv, cipher.as_ref()).await?;
+        Ok(Self {
             client_id,
             client_secret,
-            project_id,
-            access_token,
+            project_id: None,
+            project_name,
+            course_mapping: CourseMapping::None,
+            base_url: DEFAULT_BASE_URL.to_string(),
+            client: reqwest::Client::new(),
+            token: RefCell::new(token),
+            cipher,
+            login_prompt: None,
+        })
+    }
+
+    pub fn with_base_url(mut self, base_url: String) -> Self {
+        self.base_url = base_url;
+        self
+    }
+
+    /// Uses this project instead of looking one up by name.
+    pub fn with_project_id(mut self, project_id: String) -> Self {
+        self.project_id = Some(project_id);
+        self
+    }
+
+    pub fn with_course_mapping(mut self, course_mapping: CourseMapping) -> Self {
+        self.course_mapping = course_mapping;
+        self
+    }
+
+    pub fn with_login_prompt(mut self, bot: Telegram, redirect_uri: String) -> Self {
+        self.login_prompt = Some((bot, redirect_uri));
+        self
+    }
+
+    pub async fn from_config(
+        config: &TickTickConfig,
+        cipher: Option<&Cipher>,
+        kv: &impl KeyValue,
+    ) -> Result<Self> {
+        let mut ticktick = Self::new(
+            config.client_id.clone(),
+            config.client_secret.clone(),
+            config.project_name.clone(),
+            cipher.cloned(),
+            kv,
+        )
+        .await?
+        .with_course_mapping(config.course_mapping);
+        if let Some(project_id) = &config.project_id {
+            ticktick = ticktick.with_project_id(project_id.clone());
         }
+        Ok(match &config.api_url {
+            Some(url) => ticktick.with_base_url(url.clone()),
+            None => ticktick,
+        })
     }
 
     pub async fn login(
         &self,
-        bot: &super::telegram::Telegram,
+        bot: &Telegram,
         redirect_uri: &str,
-        kv: KvStore,
+        kv: &impl KeyValue,
     ) -> Result<()> {
-        let state = getrandom::u64().unwrap().to_string();
-        kv.put("state", state.clone())
-            .unwrap()
-            .execute()
-            .await
-            .unwrap();
+        let state = getrandom::u64()
+            .map_err(|e| Error::auth(format!("no randomness for the login state: {}", e)))?
+            .to_string();
+        kv.put(STATE_KEY, &state).await?;
 
         let redirect_url = &format!(
-            "https://dida365.com/oauth/authorize?scope=tasks:write,tasks:read&client_id={}&state={}&redirect_uri={redirect_uri}&response_type=code",
-            self.client_id, state
+            "{}/oauth/authorize?scope=tasks:write,tasks:read&client_id={}&state={}&redirect_uri={redirect_uri}&response_type=code",
+            self.base_url, self.client_id, state
         );
 
         let message = format!("请点击链接登录滴答清单：{}", redirect_url);
@@ -54,167 +251,541 @@ impl TickTick {
         Ok(())
     }
 
-    pub async fn auth(&self, url: Url, redirect_uri: &str, kv: KvStore) -> Result<()> {
-        let code = url.query_pairs().find(|(key, _)| key == "code").unwrap().1;
-        let state = url.query_pairs().find(|(key, _)| key == "state").unwrap().1;
+    /// Sends a login link through the configured bot, if there is one and the
+    /// link sent before has not been used yet.
+    pub async fn prompt_login(&self, kv: &impl KeyValue) -> Result<()> {
+        if kv.get(STATE_KEY).await?.is_some() {
+            info!("ticktick needs a login, the link already sent is still open");
+            return Ok(());
+        }
+        match &self.login_prompt {
+            Some((bot, redirect_uri)) => {
+                self.login(bot, redirect_uri, kv).await?;
+                info!("Sent login link to telegram");
+            }
+            None => info!("ticktick needs a login but telegram is unavailable for the link"),
+        }
+        Ok(())
+    }
+
+    pub async fn auth(&self, url: Url, redirect_uri: &str, kv: &impl KeyValue) -> Result<()> {
+        let query = |name: &str| {
+            url.query_pairs()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.into_owned())
+                .ok_or_else(|| Error::bad_request(format!("missing {} parameter", name)))
+        };
+        let code = query("code")?;
+        let state = query("state")?;
 
-        let saved_state = kv.get("state").text().await.unwrap();
+        let saved_state = kv.get(STATE_KEY).await?;
         if let Some(saved_state) = saved_state {
             if saved_state != state {
-                return Err(anyhow::anyhow!("state not match"));
+                return Err(Error::auth("state not match"));
             }
         } else {
-            return Err(anyhow::anyhow!("state not found"));
+            return Err(Error::auth("state not found"));
         }
 
-        let url = "https://dida365.com/oauth/token";
-        let mut headers = worker::Headers::new();
-        headers.append("Content-Type", "application/x-www-form-urlencoded")?;
-        headers.append(
-            "Authorization",
-            &format!(
-                "Basic {}",
-                BASE64_STANDARD.encode(format!("{}:{}", self.client_id, self.client_secret))
-            ),
-        )?;
-
         let body = format!(
             "code={}&grant_type=authorization_code&scope=tasks:write,tasks:read&redirect_uri={}",
             code, redirect_uri
         );
+        let res = self.token_request(body).await?;
+        info!("auth response: {:?}", res);
 
-        let request = Request::new_with_init(
-            url,
-            &RequestInit {
-                body: Some(body.into()),
-                headers,
-                method: worker::Method::Post,
-                ..Default::default()
-            },
-        )?;
+        let token = TokenSet::from_response(&res, None)?;
+        token.save(kv, self.cipher.as_ref()).await?;
+        self.token.replace(Some(token));
+        kv.delete(STATE_KEY).await?;
+        Ok(())
+    }
 
-        let mut response = Fetch::Request(request).send().await?;
+    async fn token_request(&self, body: String) -> Result<serde_json::Value> {
+        let url = format!("{}/oauth/token", self.base_url);
 
-        let res = response.json::<serde_json::Value>().await?;
+        Ok(self
+            .client
+            .post(&url)
+            .header("Content-Type", "application/x-www-form-urlencoded")
+            .basic_auth(&self.client_id, Some(&self.client_secret))
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?
+            .json::<serde_json::Value>()
+            .await?)
+    }
 
-        info!("auth response: {:?}", res);
-        let access_token = res["access_token"].as_str().unwrap();
+    /// Trades the refresh token for a new token set and persists it.
+    pub async fn refresh(&self, kv: &impl KeyValue) -> Result<()> {
+        let previous = self.token.borrow().clone();
+        let refresh_token = previous
+            .as_ref()
+            .and_then(|t| t.refresh_token.clone())
+            .ok_or_else(|| Error::auth("no ticktick refresh token"))?;
 
-        kv.put("access_token", access_token)
-            .unwrap()
-            .execute()
-            .await
-            .unwrap();
+        let body = format!(
+            "grant_type=refresh_token&refresh_token={}",
+            urlencoding::encode(&refresh_token)
+        );
+        let res = self.token_request(body).await?;
+
+        let token = TokenSet::from_response(&res, previous.as_ref())?;
+        token.save(kv, self.cipher.as_ref()).await?;
+        self.token.replace(Some(token));
+        info!("ticktick token refreshed");
         Ok(())
     }
 
-    pub async fn get_project(&self, name: &str) -> Result<i32> {
-        let url = "https://dida365.com/open/v1/project";
+    /// Whether there is a usable access token, refreshing it first when it is
+    /// about to expire. A token that cannot be refreshed is still used until it
+    /// actually expires; after that a transient refresh failure is returned as
+    /// an error rather than asking for a new login.
+    pub async fn ensure_token(&self, kv: &impl KeyValue) -> Result<bool> {
+        let Some(token) = self.token.borrow().clone() else {
+            return Ok(false);
+        };
+        if token.expires_within(REFRESH_MARGIN_SECS) {
+            if let Err(e) = self.refresh(kv).await {
+                error!("ticktick token refresh failed: {:?}", e);
+                if token.expires_within(0) && !refresh_rejected(&e) {
+                    return Err(e);
+                }
+                return Ok(!token.expires_within(0));
+            }
+        }
+        Ok(true)
+    }
+
+    fn access_token(&self) -> Result<String> {
+        self.token
+            .borrow()
+            .as_ref()
+            .map(|t| t.access_token.clone())
+            .ok_or_else(|| Error::auth("ticktick is not logged in"))
+    }
+
+    /// Sends an open API request, refreshing the token and retrying once when it is
+    /// rejected. If TickTick rejects the refresh too the stored tokens are dropped
+    /// and a login link is sent, so the next run starts from a clean login; any
+    /// other refresh failure keeps the tokens for the next run to retry.
+    async fn send(
+        &self,
+        kv: &impl KeyValue,
+        request: impl Fn(&str) -> RequestBuilder,
+    ) -> Result<Response> {
+        let response = request(&self.access_token()?).send().await?;
+        if response.status() != StatusCode::UNAUTHORIZED {
+            return Ok(response);
+        }
+
+        info!("ticktick rejected the access token, refreshing");
+        if let Err(e) = self.refresh(kv).await {
+            error!("ticktick token refresh failed: {:?}", e);
+            if !refresh_rejected(&e) {
+                return Err(e);
+            }
+            self.token.replace(None);
+            kv.delete(TOKEN_KEY).await?;
+            kv.delete(LEGACY_TOKEN_KEY).await?;
+            // whatever link is still around predates the tokens that just failed
+            kv.delete(STATE_KEY).await?;
+            self.prompt_login(kv).await?;
+            return Err(Error::auth("ticktick access token was rejected"));
+        }
+        Ok(request(&self.access_token()?).send().await?)
+    }
+
+    /// Looks up a project id by name.
+    pub async fn get_project(&self, name: &str, kv: &impl KeyValue) -> Result<Option<String>> {
+        let url = format!("{}/open/v1/project", self.base_url);
+
+        let projects: serde_json::Value = self
+            .send(kv, |token| self.client.get(&url).bearer_auth(token))
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        Ok(projects
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|p| p["name"].as_str() == Some(name))
+            .and_then(|p| p["id"].as_str())
+            .map(str::to_string))
+    }
+
+    pub async fn create_project(&self, name: &str, kv: &impl KeyValue) -> Result<String> {
+        let url = format!("{}/open/v1/project", self.base_url);
+        let body = serde_json::json!({ "name": name });
+
+        let created: serde_json::Value = self
+            .send(kv, |token| {
+                self.client.post(&url).bearer_auth(token).json(&body)
+            })
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        info!("ticktick project created: {:?}", created);
+
+        created["id"]
+            .as_str()
+            .map(str::to_string)
+            .ok_or_else(|| Error::parse(format!("ticktick returned no id for project {}", name)))
+    }
+
+    /// The id of the project called `name`, creating it if needed. Resolved ids
+    /// are cached in KV so the project list is only fetched once per name, until
+    /// TickTick rejects the id, see [`TickTick::forget_project`].
+    async fn resolve_project(&self, name: &str, kv: &impl KeyValue) -> Result<String> {
+        let key = format!("{}{}", PROJECT_KEY_PREFIX, name);
+        if let Some(id) = kv.get(&key).await? {
+            return Ok(id);
+        }
+
+        let id = match self.get_project(name, kv).await? {
+            Some(id) => id,
+            None => self.create_project(name, kv).await?,
+        };
+        kv.put(&key, &id).await?;
+        Ok(id)
+    }
+
+    async fn default_project(&self, kv: &impl KeyValue) -> Result<String> {
+        match &self.project_id {
+            Some(id) => Ok(id.clone()),
+            None => self.resolve_project(&self.project_name, kv).await,
+        }
+    }
+
+    /// The name the project of a task in `course` is resolved by, `None` when
+    /// it goes in the configured project id.
+    fn project_name<'a>(&'a self, course: Option<&'a str>) -> Option<&'a str> {
+        match (&self.course_mapping, course) {
+            (CourseMapping::Project, Some(course)) => Some(course),
+            _ if self.project_id.is_some() => None,
+            _ => Some(&self.project_name),
+        }
+    }
+
+    /// The project an activity's task belongs in.
+    async fn project_for(
+        &self,
+        undone_item: &UndoneListItem,
+        kv: &impl KeyValue,
+    ) -> Result<String> {
+        let course = undone_item.course_info.as_ref().map(|ci| ci.name.as_str());
+        match self.project_name(course) {
+            Some(name) => self.resolve_project(name, kv).await,
+            None => self.default_project(kv).await,
+        }
+    }
+
+    /// Drops the cached id of the project tasks in `course` go in if it is
+    /// `project_id`, which TickTick no longer accepts, so that the next lookup
+    /// resolves the name again. Whether there was anything to drop.
+    async fn forget_project(
+        &self,
+        project_id: &str,
+        course: Option<&str>,
+        kv: &impl KeyValue,
+    ) -> Result<bool> {
+        let Some(name) = self.project_name(course) else {
+            return Ok(false);
+        };
+        let key = format!("{}{}", PROJECT_KEY_PREFIX, name);
+        if kv.get(&key).await?.as_deref() != Some(project_id) {
+            return Ok(false);
+        }
+        info!(
+            "ticktick rejected project {} of {}, resolving it again",
+            project_id, name
+        );
+        kv.delete(&key).await?;
+        Ok(true)
+    }
 
-        let mut headers = worker::Headers::new();
-        headers.append("Content-Type", "application/json")?;
-        headers.append(
-            "Authorization",
-            &format!("Bearer {}", self.access_token.as_ref().unwrap()),
-        )?;
+    fn task(&self, undone_item: &UndoneListItem, project_id: String) -> Result<Task> {
+        Ok(Task {
+            id: None,
+            title: undone_item.activity_name.clone(),
+            project_id,
+            start_date: undone_item
+                .start_time
+                .as_deref()
+                .map(|time| ticktick_date(time, "%Y-%m-%d %H:%M"))
+                .transpose()?,
+            due_date: Some(ticktick_date(&undone_item.end_time, "%Y-%m-%d %H:%M:%S")?),
+            content: {
+                let content = undone_item.description.clone();
 
-        let request = Request::new_with_init(
-            url,
-            &RequestInit {
-                headers,
-                method: worker::Method::Get,
-                ..Default::default()
+                let mut md = htmd::HtmlToMarkdown::new()
+                    .convert(&content.unwrap_or_default())
+                    .unwrap_or_default()
+                    .replace("![", "\n![");
+                if undone_item.details_unavailable {
+                    md.push_str("（作业详情暂时无法获取）");
+                }
+                let attachments = undone_item.attachments.as_deref().unwrap_or_default();
+                if !attachments.is_empty() {
+                    md.push_str("\n\n附件：\n");
+                    for attachment in attachments {
+                        md.push_str(&format!("\n- [{}]({})", attachment.name, attachment.url));
+                    }
+                }
+                md.push_str(&format!("\n\n{}", marker(&undone_item.activity_id)));
+                if let Some(ci) = &undone_item.course_info {
+                    Some(format!(
+                        "课程：{}\n教师：{}\n\n{}\n",
+                        ci.name, ci.teachers, md
+                    ))
+                } else {
+                    Some(md)
+                }
+            },
+            tags: match (&self.course_mapping, &undone_item.course_info) {
+                (CourseMapping::Tag, Some(course_info)) => Some(vec![course_info.name.clone()]),
+                _ => None,
             },
-        )?;
+        })
+    }
+
+    /// Looks up the open task of an activity in a project by the marker in its
+    /// content. Titles are not unique across courses, so they are not trusted.
+    pub async fn find_task(
+        &self,
+        activity_id: &str,
+        project_id: &str,
+        kv: &impl KeyValue,
+    ) -> Result<Option<String>> {
+        let url = format!("{}/open/v1/project/{}/data", self.base_url, project_id);
+        let marker = marker(activity_id);
 
-        let mut response = Fetch::Request(request).send().await?;
-        let projects: serde_json::Value = response.json().await?;
+        let data: serde_json::Value = self
+            .send(kv, |token| self.client.get(&url).bearer_auth(token))
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
 
-        let project = projects
+        Ok(data["tasks"]
             .as_array()
-            .unwrap()
-            .iter()
-            .find(|p| p["name"].as_str().unwrap() == name)
-            .unwrap();
+            .into_iter()
+            .flatten()
+            .find(|task| {
+                task["content"]
+                    .as_str()
+                    .is_some_and(|content| content.lines().any(|line| line.trim() == marker))
+            })
+            .and_then(|task| task["id"].as_str())
+            .map(str::to_string))
+    }
 
-        Ok(project["id"].as_i64().unwrap() as i32)
+    /// The task created for an activity. Activities wiped by `/clear` are found
+    /// by their marker in `project_id` once and remembered.
+    async fn stored_task(
+        &self,
+        activity_id: &str,
+        project_id: &str,
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<Option<TickTickTask>> {
+        if let Some(task) = d1::get_ticktick_task(activity_id, d1::OWNER, db).await? {
+            return Ok(Some(task));
+        }
+        let Some(id) = self.find_task(activity_id, project_id, kv).await? else {
+            return Ok(None);
+        };
+        d1::save_ticktick_task(activity_id, &id, project_id, d1::OWNER, db).await?;
+        Ok(Some(TickTickTask {
+            id,
+            project_id: Some(project_id.to_string()),
+        }))
     }
-}
 
-impl Api for TickTick {
-    async fn push(&self, message: &crate::model::UndoneList) -> Result<()> {
-        if message.undone_list.is_empty() {
-            return Ok(());
+    /// Creates the task for an activity, or updates it if one already exists.
+    /// A cached project that TickTick rejects is resolved again and the task
+    /// pushed once more.
+    async fn upsert_task(
+        &self,
+        undone_item: &UndoneListItem,
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<()> {
+        let project_id = self.project_for(undone_item, kv).await?;
+        match self.upsert_task_in(undone_item, &project_id, db, kv).await {
+            Err(e) if project_rejected(&e) => {
+                let course = undone_item.course_info.as_ref().map(|ci| ci.name.as_str());
+                if !self.forget_project(&project_id, course, kv).await? {
+                    return Err(e);
+                }
+                let project_id = self.project_for(undone_item, kv).await?;
+                self.upsert_task_in(undone_item, &project_id, db, kv).await
+            }
+            result => result,
         }
+    }
 
-        for undone_item in &message.undone_list {
-            let task = Task {
-                title: undone_item.activity_name.clone(),
-                project_id: self.project_id.clone(),
-                start_date: Some(
-                    chrono::NaiveDateTime::parse_from_str(
-                        &undone_item.start_time.clone().unwrap(),
-                        "%Y-%m-%d %H:%M",
-                    )
-                    .unwrap()
-                    .and_local_timezone(chrono::FixedOffset::east_opt(8 * 3600).unwrap())
-                    .unwrap()
-                    .format("%Y-%m-%dT%H:%M:%S%z")
-                    .to_string(),
-                ),
-                due_date: {
-                    Some(
-                        chrono::NaiveDateTime::parse_from_str(
-                            &undone_item.end_time.clone(),
-                            "%Y-%m-%d %H:%M:%S",
-                        )
-                        .unwrap()
-                        .and_local_timezone(chrono::FixedOffset::east_opt(8 * 3600).unwrap())
-                        .unwrap()
-                        .format("%Y-%m-%dT%H:%M:%S%z")
-                        .to_string(),
+    async fn upsert_task_in(
+        &self,
+        undone_item: &UndoneListItem,
+        project_id: &str,
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<()> {
+        let mut task = self.task(undone_item, project_id.to_string())?;
+
+        if let Some(stored) = self
+            .stored_task(&undone_item.activity_id, project_id, db, kv)
+            .await?
+        {
+            task.id = Some(stored.id.clone());
+            let url = format!("{}/open/v1/task/{}", self.base_url, stored.id);
+            let response = self
+                .send(kv, |token| {
+                    self.client.post(&url).bearer_auth(token).json(&task)
+                })
+                .await?;
+            if response.status() != StatusCode::NOT_FOUND {
+                let response = response.error_for_status()?;
+                info!("ticktick update result: {:?}", response);
+                if stored.project_id.as_deref() != Some(project_id) {
+                    d1::save_ticktick_task(
+                        &undone_item.activity_id,
+                        &stored.id,
+                        project_id,
+                        d1::OWNER,
+                        db,
                     )
-                },
-                content: {
-                    let content = undone_item.description.clone();
-
-                    let md = htmd::HtmlToMarkdown::new()
-                        .convert(&content.unwrap_or_default())
-                        .unwrap_or_default()
-                        .replace("![", "\n![");
-                    if let Some(ci) = &undone_item.course_info {
-                        Some(format!(
-                            "课程：{}\n教师：{}\n\n{}\n",
-                            ci.name, ci.teachers, md
-                        ))
-                    } else {
-                        Some(md)
+                    .await?;
+                }
+                return Ok(());
+            }
+            // the task was deleted on the TickTick side, create a fresh one
+            task.id = None;
+        }
+
+        let url = format!("{}/open/v1/task", self.base_url);
+        let created: serde_json::Value = self
+            .send(kv, |token| {
+                self.client.post(&url).bearer_auth(token).json(&task)
+            })
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        info!("ticktick push result: {:?}", created);
+
+        if let Some(id) = created["id"].as_str() {
+            d1::save_ticktick_task(&undone_item.activity_id, id, project_id, d1::OWNER, db).await?;
+        }
+        Ok(())
+    }
+
+    /// Marks the task of a submitted activity done, finding it by its marker if
+    /// its id was never stored.
+    async fn complete_task(
+        &self,
+        record: &ActivityRecord,
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<()> {
+        let stored = match d1::get_ticktick_task(&record.activity_id, d1::OWNER, db).await? {
+            Some(stored) => Some(stored),
+            None => {
+                let project_id = self.default_project(kv).await?;
+                match self
+                    .stored_task(&record.activity_id, &project_id, db, kv)
+                    .await
+                {
+                    Err(e) if project_rejected(&e) => {
+                        self.forget_project(&project_id, None, kv).await?;
+                        return Err(e);
                     }
-                },
-            };
+                    result => result?,
+                }
+            }
+        };
+        let Some(stored) = stored else {
+            info!("no ticktick task for {}", record.activity_id);
+            return Ok(());
+        };
+        let project_id = match stored.project_id {
+            Some(project_id) => project_id,
+            None => self.default_project(kv).await?,
+        };
 
-            let url = "https://dida365.com/open/v1/task";
+        let url = format!(
+            "{}/open/v1/project/{}/task/{}/complete",
+            self.base_url, project_id, stored.id
+        );
+        let response = match self
+            .send(kv, |token| self.client.post(&url).bearer_auth(token))
+            .await?
+            .error_for_status()
+        {
+            Ok(response) => response,
+            Err(e) => {
+                let e = Error::from(e);
+                if project_rejected(&e) {
+                    self.forget_project(&project_id, record.course_name.as_deref(), kv)
+                        .await?;
+                }
+                return Err(e);
+            }
+        };
+        info!("ticktick complete result: {:?}", response);
+        Ok(())
+    }
+}
+
+impl Api for TickTick {
+    fn name(&self) -> &'static str {
+        "ticktick"
+    }
 
-            let mut headers = worker::Headers::new();
-            headers.append("Content-Type", "application/json")?;
-            headers.append(
-                "Authorization",
-                &format!("Bearer {}", self.access_token.as_ref().unwrap()),
-            )?;
+    async fn push(
+        &self,
+        update: &Update<'_>,
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let mut outcomes = Vec::new();
+        for undone_item in &update.new.undone_list {
+            let outcome = self.upsert_task(undone_item, db, kv).await;
+            outcomes.push((undone_item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
 
-            let request = Request::new_with_init(
-                url,
-                &RequestInit {
-                    body: Some(serde_json::to_string(&task)?.into()),
-                    headers,
-                    method: worker::Method::Post,
-                    ..Default::default()
-                },
-            )?;
+    async fn push_changes(
+        &self,
+        changes: &[Change],
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let mut outcomes = Vec::new();
+        for change in changes {
+            let outcome = self.upsert_task(&change.item, db, kv).await;
+            outcomes.push((change.item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
 
-            let response = Fetch::Request(request).send().await?;
-            info!("ticktick push result: {:?}", response);
+    async fn push_completed(
+        &self,
+        completed: &[ActivityRecord],
+        db: &impl Database,
+        kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let mut outcomes = Vec::new();
+        for record in completed {
+            let outcome = self.complete_task(record, db, kv).await;
+            outcomes.push((record.activity_id.clone(), outcome));
         }
-        Ok(())
+        Ok(outcomes)
     }
 }
@@ -0,0 +1,266 @@
+use super::{Api, Outcomes, Update};
+use crate::change::{Change, ChangeKind};
+use crate::config::WebhookConfig;
+use crate::d1::ActivityRecord;
+use crate::error::{Error, Result};
+use crate::model::UndoneListItem;
+use crate::storage::{Database, KeyValue};
+use chrono::Utc;
+use hmac::{Hmac, Mac};
+use serde_json::{json, Map, Value};
+use sha2::Sha256;
+use tracing::info;
+
+/// `sha256=` followed by the hex HMAC-SHA256 of the request body, keyed with the
+/// configured secret.
+pub const SIGNATURE_HEADER: &str = "X-UCloud-Push-Signature";
+/// `new`, `changed` or `completed`.
+pub const EVENT_HEADER: &str = "X-UCloud-Push-Event";
+
+/// POSTs every new, changed and completed activity to a URL, e.g. a Home
+/// Assistant or n8n webhook.
+///
+/// The payload is a JSON template in which strings are searched for
+/// placeholders:
+///
+/// - `event`: `new`, `changed` or `completed`
+/// - `activity_id`, `name`, `course`, `start_time`, `end_time` and
+///   `description` (HTML, as UCloud serves it)
+/// - `changes`: what changed, any of `deadline`, `description` and
+///   `overtime_commit`
+/// - `previous_end_time`: the deadline before it moved, `null` unless it did
+/// - `timestamp`: when the request was sent, RFC 3339
+///
+/// A string that is nothing but `{{placeholder}}` becomes the value itself, so
+/// lists stay lists and missing values become `null`. Placeholders inside
+/// longer strings are replaced by their text.
+pub struct Webhook {
+    url: String,
+    secret: String,
+    template: Value,
+    client: reqwest::Client,
+}
+
+impl Webhook {
+    pub fn new(url: String, secret: String) -> Self {
+        Self {
+            url,
+            secret,
+            template: default_template(),
+            client: reqwest::Client::new(),
+        }
+    }
+
+    pub fn with_template(mut self, template: Value) -> Self {
+        self.template = template;
+        self
+    }
+
+    pub fn from_config(config: &WebhookConfig) -> Self {
+        let webhook = Self::new(config.url.clone(), config.secret.clone());
+        match &config.template {
+            Some(template) => webhook.with_template(template.clone()),
+            None => webhook,
+        }
+    }
+
+    async fn send(&self, event: &str, mut fields: Map<String, Value>) -> Result<()> {
+        fields.insert("event".to_string(), event.into());
+        fields.insert("timestamp".to_string(), Utc::now().to_rfc3339().into());
+        fields.entry("changes").or_insert_with(|| json!([]));
+        fields.entry("previous_end_time").or_insert(Value::Null);
+        let body = serde_json::to_vec(&render(&self.template, &fields))?;
+        let response = self
+            .client
+            .post(&self.url)
+            .header("Content-Type", "application/json")
+            .header(EVENT_HEADER, event)
+            .header(SIGNATURE_HEADER, sign(&self.secret, &body)?)
+            .body(body)
+            .send()
+            .await?
+            .error_for_status()?;
+        info!("webhook {} result: {}", event, response.status().as_u16());
+        Ok(())
+    }
+}
+
+impl Api for Webhook {
+    fn name(&self) -> &'static str {
+        "webhook"
+    }
+
+    async fn push(
+        &self,
+        update: &Update<'_>,
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let mut outcomes = Vec::new();
+        for item in &update.new.undone_list {
+            let outcome = self.send("new", item_fields(item)).await;
+            outcomes.push((item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+
+    async fn push_changes(
+        &self,
+        changes: &[Change],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let mut outcomes = Vec::new();
+        for change in changes {
+            let mut fields = item_fields(&change.item);
+            let kinds: Vec<&str> = change
+                .kinds
+                .iter()
+                .map(|kind| match kind {
+                    ChangeKind::Deadline { .. } => "deadline",
+                    ChangeKind::Description => "description",
+                    ChangeKind::OvertimeCommit { .. } => "overtime_commit",
+                })
+                .collect();
+            fields.insert("changes".to_string(), json!(kinds));
+            if let Some(ChangeKind::Deadline { from, .. }) = change
+                .kinds
+                .iter()
+                .find(|kind| matches!(kind, ChangeKind::Deadline { .. }))
+            {
+                fields.insert("previous_end_time".to_string(), from.as_str().into());
+            }
+            let outcome = self.send("changed", fields).await;
+            outcomes.push((change.item.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+
+    async fn push_completed(
+        &self,
+        completed: &[ActivityRecord],
+        _db: &impl Database,
+        _kv: &impl KeyValue,
+    ) -> Result<Outcomes> {
+        let mut outcomes = Vec::new();
+        for record in completed {
+            let fields = json!({
+                "activity_id": record.activity_id,
+                "name": record.activity_name,
+                "course": record.course_name,
+                "start_time": record.start_time,
+                "end_time": record.end_time,
+                "description": record.description,
+            });
+            let outcome = self.send("completed", object(fields)).await;
+            outcomes.push((record.activity_id.clone(), outcome));
+        }
+        Ok(outcomes)
+    }
+}
+
+/// The signature sent in [`SIGNATURE_HEADER`].
+pub fn sign(secret: &str, body: &[u8]) -> Result<String> {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.as_bytes())
+        .map_err(|e| Error::config(format!("invalid webhook secret: {}", e)))?;
+    mac.update(body);
+    let digest: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(format!("sha256={}", digest))
+}
+
+/// Fills the placeholders in `template` from `fields`. Unknown placeholders are
+/// left alone.
+pub fn render(template: &Value, fields: &Map<String, Value>) -> Value {
+    match template {
+        Value::String(text) => {
+            let whole = text
+                .strip_prefix("{{")
+                .and_then(|rest| rest.strip_suffix("}}"))
+                .map(str::trim)
+                .filter(|name| !name.contains("{{") && !name.contains("}}"));
+            if let Some(name) = whole {
+                if fields.contains_key(name) {
+                    return fields[name].clone();
+                }
+            }
+            Value::String(fill(text, fields))
+        }
+        Value::Array(values) => Value::Array(values.iter().map(|v| render(v, fields)).collect()),
+        Value::Object(entries) => Value::Object(
+            entries
+                .iter()
+                .map(|(key, value)| (key.clone(), render(value, fields)))
+                .collect(),
+        ),
+        other => other.clone(),
+    }
+}
+
+fn fill(text: &str, fields: &Map<String, Value>) -> String {
+    let mut filled = String::new();
+    let mut rest = text;
+    while let Some(start) = rest.find("{{") {
+        let Some(end) = rest[start..].find("}}") else {
+            break;
+        };
+        let name = rest[start + 2..start + end].trim();
+        filled.push_str(&rest[..start]);
+        match fields.get(name) {
+            Some(Value::String(value)) => filled.push_str(value),
+            Some(Value::Null) => {}
+            Some(Value::Array(values)) => {
+                let values: Vec<String> = values
+                    .iter()
+                    .map(|v| v.as_str().map_or_else(|| v.to_string(), str::to_string))
+                    .collect();
+                filled.push_str(&values.join(", "));
+            }
+            Some(value) => filled.push_str(&value.to_string()),
+            None => filled.push_str(&rest[start..start + end + 2]),
+        }
+        rest = &rest[start + end + 2..];
+    }
+    filled.push_str(rest);
+    filled
+}
+
+fn item_fields(item: &UndoneListItem) -> Map<String, Value> {
+    let fields = json!({
+        "activity_id": item.activity_id,
+        "name": item.activity_name,
+        "course": item.course_info.as_ref().map(|course| &course.name),
+        "start_time": item.start_time,
+        "end_time": item.end_time,
+        "description": item.description,
+    });
+    object(fields)
+}
+
+fn object(value: Value) -> Map<String, Value> {
+    match value {
+        Value::Object(fields) => fields,
+        _ => Map::new(),
+    }
+}
+
+fn default_template() -> Value {
+    json!({
+        "event": "{{event}}",
+        "timestamp": "{{timestamp}}",
+        "activity": {
+            "id": "{{activity_id}}",
+            "name": "{{name}}",
+            "course": "{{course}}",
+            "start_time": "{{start_time}}",
+            "end_time": "{{end_time}}",
+            "description": "{{description}}",
+        },
+        "changes": "{{changes}}",
+        "previous_end_time": "{{previous_end_time}}",
+    })
+}
@@ -0,0 +1,326 @@
+use crate::crypto::Cipher;
+use crate::d1::{self, ActivityRecord, OpenActivity};
+use crate::error::{Error, Result};
+use crate::model::{parse_ucloud_time, ucloud_offset, UndoneListItem};
+use crate::storage::{user_key, Database, KeyValue};
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sha2::{Digest, Sha256};
+
+/// KV key of a user's feed token, see [`user_key`]. Encrypted when a
+/// [`Cipher`] is configured.
+pub const TOKEN_KEY: &str = "calendar_token";
+/// Prefix of the KV keys mapping the SHA-256 of a feed token to its user, so
+/// the tokens themselves never show up in key listings.
+const FEED_KEY_PREFIX: &str = "calendar_feed:";
+const TOKEN_BYTES: usize = 16;
+const PRODID: &str = "PRODID:-//ucloud-push//UCloud homework//ZH";
+/// RFC 5545 lines are at most this many octets, longer ones are folded.
+const LINE_LIMIT: usize = 75;
+
+/// What every assignment becomes in the feed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Component {
+    /// An event from the start to the deadline, shown by every calendar.
+    Event,
+    /// A task due at the deadline, for clients with a task list.
+    Todo,
+}
+
+impl Component {
+    /// Parses the `type` query parameter of the feed.
+    pub fn parse(name: Option<&str>) -> Option<Self> {
+        match name {
+            None | Some("event") => Some(Component::Event),
+            Some("todo") => Some(Component::Todo),
+            Some(_) => None,
+        }
+    }
+}
+
+/// One assignment as it shows up in a calendar.
+#[derive(Clone, Debug)]
+pub struct Entry<'a> {
+    pub activity_id: &'a str,
+    pub name: &'a str,
+    pub start_time: Option<&'a str>,
+    pub end_time: &'a str,
+    pub course_name: Option<&'a str>,
+    /// HTML, as UCloud serves it.
+    pub description: Option<&'a str>,
+    /// When the assignment was submitted, only shown by [`Component::Todo`].
+    pub completed_at: Option<DateTime<Utc>>,
+}
+
+impl<'a> From<&'a OpenActivity> for Entry<'a> {
+    fn from(activity: &'a OpenActivity) -> Self {
+        Self {
+            activity_id: &activity.activity_id,
+            name: &activity.activity_name,
+            start_time: Some(&activity.start_time),
+            end_time: &activity.end_time,
+            course_name: activity.course_name.as_deref(),
+            description: activity.description.as_deref(),
+            completed_at: None,
+        }
+    }
+}
+
+impl<'a> From<&'a UndoneListItem> for Entry<'a> {
+    fn from(item: &'a UndoneListItem) -> Self {
+        Self {
+            activity_id: &item.activity_id,
+            name: &item.activity_name,
+            start_time: item.start_time.as_deref(),
+            end_time: &item.end_time,
+            course_name: item.course_info.as_ref().map(|course| course.name.as_str()),
+            description: item.description.as_deref(),
+            completed_at: None,
+        }
+    }
+}
+
+impl<'a> From<&'a ActivityRecord> for Entry<'a> {
+    fn from(record: &'a ActivityRecord) -> Self {
+        Self {
+            activity_id: &record.activity_id,
+            name: &record.activity_name,
+            start_time: record.start_time.as_deref(),
+            end_time: &record.end_time,
+            course_name: record.course_name.as_deref(),
+            description: record.description.as_deref(),
+            completed_at: None,
+        }
+    }
+}
+
+/// Renders `activities` as an iCalendar document.
+pub fn render(activities: &[OpenActivity], component: Component, now: DateTime<Utc>) -> String {
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        PRODID.to_string(),
+        "CALSCALE:GREGORIAN".to_string(),
+        "METHOD:PUBLISH".to_string(),
+        "X-WR-CALNAME:UCloud 作业".to_string(),
+        "REFRESH-INTERVAL;VALUE=DURATION:PT1H".to_string(),
+        "X-PUBLISHED-TTL:PT1H".to_string(),
+    ];
+    for activity in activities {
+        lines.extend(component_lines(&Entry::from(activity), component, now));
+    }
+    lines.push("END:VCALENDAR".to_string());
+    fold_all(lines)
+}
+
+/// `entry` as a calendar object resource holding a single VTODO, as stored on a
+/// CalDAV server. None if the deadline cannot be parsed.
+pub fn todo_resource(entry: &Entry, now: DateTime<Utc>) -> Option<String> {
+    let todo = component_lines(entry, Component::Todo, now);
+    if todo.is_empty() {
+        return None;
+    }
+    let mut lines = vec![
+        "BEGIN:VCALENDAR".to_string(),
+        "VERSION:2.0".to_string(),
+        PRODID.to_string(),
+    ];
+    lines.extend(todo);
+    lines.push("END:VCALENDAR".to_string());
+    Some(fold_all(lines))
+}
+
+/// The unfolded lines of `entry` as a `component`, none if its deadline cannot be parsed.
+fn component_lines(entry: &Entry, component: Component, now: DateTime<Utc>) -> Vec<String> {
+    let Some(due) = parse_ucloud_time(entry.end_time).map(|d| d.to_utc()) else {
+        return Vec::new();
+    };
+    let start = entry
+        .start_time
+        .and_then(parse_start_time)
+        .filter(|start| *start < due);
+    let name = match component {
+        Component::Event => "VEVENT",
+        Component::Todo => "VTODO",
+    };
+
+    let mut lines = vec![
+        format!("BEGIN:{}", name),
+        format!("UID:{}@ucloud-push", entry.activity_id),
+        format!("DTSTAMP:{}", ics_time(now)),
+    ];
+    match component {
+        Component::Event => {
+            lines.push(format!("DTSTART:{}", ics_time(start.unwrap_or(due))));
+            lines.push(format!("DTEND:{}", ics_time(due)));
+        }
+        Component::Todo => {
+            if let Some(start) = start {
+                lines.push(format!("DTSTART:{}", ics_time(start)));
+            }
+            lines.push(format!("DUE:{}", ics_time(due)));
+            match entry.completed_at {
+                Some(completed_at) => {
+                    lines.push("STATUS:COMPLETED".to_string());
+                    lines.push(format!("COMPLETED:{}", ics_time(completed_at)));
+                    lines.push("PERCENT-COMPLETE:100".to_string());
+                }
+                None => lines.push("STATUS:NEEDS-ACTION".to_string()),
+            }
+        }
+    }
+    lines.push(format!("SUMMARY:{}", escape_text(entry.name)));
+    let description = htmd::HtmlToMarkdown::new()
+        .convert(entry.description.unwrap_or_default())
+        .unwrap_or_default();
+    if !description.trim().is_empty() {
+        lines.push(format!("DESCRIPTION:{}", escape_text(description.trim())));
+    }
+    if let Some(course_name) = entry.course_name {
+        lines.push(format!("CATEGORIES:{}", escape_text(course_name)));
+    }
+    lines.push(format!("END:{}", name));
+    lines
+}
+
+fn fold_all(lines: Vec<String>) -> String {
+    let mut document = String::new();
+    for line in lines {
+        document.push_str(&fold(&line));
+    }
+    document
+}
+
+/// The feed behind `token`, or none if the token is unknown.
+pub async fn feed(
+    token: &str,
+    component: Component,
+    db: &impl Database,
+    kv: &impl KeyValue,
+    now: DateTime<Utc>,
+) -> Result<Option<String>> {
+    let Some(user) = kv.get(&feed_key(token)).await? else {
+        return Ok(None);
+    };
+    let activities = d1::get_open_activities(&user, db).await?;
+    Ok(Some(render(&activities, component, now)))
+}
+
+/// `user`'s feed token, created on first use or replaced when `reset` is set,
+/// which stops the old feed URL from working.
+pub async fn token(
+    user: &str,
+    reset: bool,
+    cipher: Option<&Cipher>,
+    kv: &impl KeyValue,
+) -> Result<String> {
+    let key = user_key(TOKEN_KEY, user);
+    let existing = match kv.get(&key).await? {
+        Some(stored) => Some(read_token(&key, &stored, cipher, kv).await?),
+        None => None,
+    };
+    if let Some(existing) = existing {
+        if !reset {
+            return Ok(existing);
+        }
+        kv.delete(&feed_key(&existing)).await?;
+    }
+
+    let mut bytes = [0u8; TOKEN_BYTES];
+    getrandom::fill(&mut bytes)
+        .map_err(|e| Error::config(format!("no randomness for the calendar token: {}", e)))?;
+    let token = hex(&bytes);
+    kv.put(&feed_key(&token), user).await?;
+    match cipher {
+        Some(cipher) => kv.put(&key, &cipher.encrypt(&token)?).await?,
+        None => kv.put(&key, &token).await?,
+    }
+    Ok(token)
+}
+
+/// Stops `user`'s feed, e.g. when they unregister.
+pub async fn revoke(user: &str, cipher: Option<&Cipher>, kv: &impl KeyValue) -> Result<()> {
+    let key = user_key(TOKEN_KEY, user);
+    if kv.get(&key).await?.is_some() {
+        let token = token(user, false, cipher, kv).await?;
+        kv.delete(&feed_key(&token)).await?;
+        kv.delete(&key).await?;
+    }
+    Ok(())
+}
+
+/// Decrypts a stored token, storing it again under the current key if it is in
+/// plaintext or under a previous key.
+async fn read_token(
+    key: &str,
+    stored: &str,
+    cipher: Option<&Cipher>,
+    kv: &impl KeyValue,
+) -> Result<String> {
+    if !Cipher::is_encrypted(stored) {
+        if let Some(cipher) = cipher {
+            kv.put(key, &cipher.encrypt(stored)?).await?;
+        }
+        return Ok(stored.to_string());
+    }
+    let cipher =
+        cipher.ok_or_else(|| Error::config("the calendar token is encrypted but no key is set"))?;
+    let token = cipher.decrypt(stored)?;
+    if !cipher.is_current(stored) {
+        kv.put(key, &cipher.encrypt(&token)?).await?;
+    }
+    Ok(token)
+}
+
+fn feed_key(token: &str) -> String {
+    format!(
+        "{}{}",
+        FEED_KEY_PREFIX,
+        hex(&Sha256::digest(token.as_bytes()))
+    )
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Start times come without seconds.
+fn parse_start_time(time: &str) -> Option<DateTime<Utc>> {
+    parse_ucloud_time(time)
+        .or_else(|| {
+            NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M")
+                .ok()?
+                .and_local_timezone(ucloud_offset())
+                .single()
+        })
+        .map(|time| time.to_utc())
+}
+
+fn ics_time(time: DateTime<Utc>) -> String {
+    time.format("%Y%m%dT%H%M%SZ").to_string()
+}
+
+/// Escapes a TEXT value.
+fn escape_text(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace(';', "\\;")
+        .replace(',', "\\,")
+        .replace("\r\n", "\\n")
+        .replace(['\r', '\n'], "\\n")
+}
+
+/// Ends `line` with CRLF, folding it into continuation lines that start with a
+/// space and never cut a character in half.
+fn fold(line: &str) -> String {
+    let mut folded = String::new();
+    let mut length = 0;
+    for c in line.chars() {
+        if length + c.len_utf8() > LINE_LIMIT {
+            folded.push_str("\r\n ");
+            length = 1;
+        }
+        folded.push(c);
+        length += c.len_utf8();
+    }
+    folded.push_str("\r\n");
+    folded
+}
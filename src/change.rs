@@ -0,0 +1,126 @@
+use crate::d1::ActivityRecord;
+use crate::model::{UndoneList, UndoneListItem};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A single field that differs between the stored activity and the fresh fetch.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Deadline { from: String, to: String },
+    Description,
+    OvertimeCommit { from: bool, to: bool },
+}
+
+/// An already-known activity whose details were edited on UCloud.
+#[derive(Clone, Debug)]
+pub struct Change {
+    pub item: UndoneListItem,
+    pub kinds: Vec<ChangeKind>,
+}
+
+impl Change {
+    pub fn deadline_moved(&self) -> bool {
+        self.kinds
+            .iter()
+            .any(|k| matches!(k, ChangeKind::Deadline { .. }))
+    }
+}
+
+/// Compares the freshly fetched list with what is stored. Activities that are not
+/// stored yet, and fields the stored row has no value for, never count as changed.
+pub fn detect_changes(undone_list: &UndoneList, stored: &[ActivityRecord]) -> Vec<Change> {
+    let stored: HashMap<&str, &ActivityRecord> = stored
+        .iter()
+        .map(|record| (record.activity_id.as_str(), record))
+        .collect();
+
+    undone_list
+        .undone_list
+        .iter()
+        .filter_map(|item| {
+            let record = stored.get(item.activity_id.as_str())?;
+            let mut kinds = Vec::new();
+
+            if record.end_time != item.end_time {
+                kinds.push(ChangeKind::Deadline {
+                    from: record.end_time.clone(),
+                    to: item.end_time.clone(),
+                });
+            }
+
+            if let (Some(old), Some(new)) = (&record.description, &item.description) {
+                if !old.is_empty() && old != new {
+                    kinds.push(ChangeKind::Description);
+                }
+            }
+
+            if let (Some(old), Some(new)) = (record.is_overtime_commit, item.is_overtime_commit) {
+                if (old != 0) != new {
+                    kinds.push(ChangeKind::OvertimeCommit {
+                        from: old != 0,
+                        to: new,
+                    });
+                }
+            }
+
+            if kinds.is_empty() {
+                None
+            } else {
+                Some(Change {
+                    item: item.clone(),
+                    kinds,
+                })
+            }
+        })
+        .collect()
+}
+
+/// Folds a newer change of an activity into one a sink has not received yet, so
+/// that it is told once what changed since it last heard. A field that changed
+/// back is left out.
+pub fn merge(older: &[ChangeKind], newer: &[ChangeKind]) -> Vec<ChangeKind> {
+    let mut kinds = Vec::new();
+
+    let deadlines = older.iter().chain(newer).filter_map(|kind| match kind {
+        ChangeKind::Deadline { from, to } => Some((from, to)),
+        _ => None,
+    });
+    if let Some((from, to)) = span(deadlines) {
+        if from != to {
+            kinds.push(ChangeKind::Deadline {
+                from: from.clone(),
+                to: to.clone(),
+            });
+        }
+    }
+
+    if older
+        .iter()
+        .chain(newer)
+        .any(|k| *k == ChangeKind::Description)
+    {
+        kinds.push(ChangeKind::Description);
+    }
+
+    let overtime = older.iter().chain(newer).filter_map(|kind| match kind {
+        ChangeKind::OvertimeCommit { from, to } => Some((from, to)),
+        _ => None,
+    });
+    if let Some((from, to)) = span(overtime) {
+        if from != to {
+            kinds.push(ChangeKind::OvertimeCommit {
+                from: *from,
+                to: *to,
+            });
+        }
+    }
+
+    kinds
+}
+
+/// The first `from` and the last `to` of a sequence of transitions.
+fn span<T>(mut transitions: impl Iterator<Item = (T, T)>) -> Option<(T, T)> {
+    let (from, to) = transitions.next()?;
+    Some((from, transitions.last().map_or(to, |(_, to)| to)))
+}
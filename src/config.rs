@@ -0,0 +1,467 @@
+use crate::crypto::{self, Cipher};
+use crate::d1;
+use crate::error::{Error, Result};
+use crate::reminder;
+use crate::storage::KeyValue;
+use chrono::NaiveTime;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer};
+use serde_json::{Map, Value};
+use tracing::{info, warn};
+use worker::Env;
+
+/// KV key holding the optional JSON config document.
+pub const CONFIG_KEY: &str = "config";
+
+/// Sink settings that are credentials. In the config document they are
+/// encrypted when a [`Cipher`] is configured, see [`load_document`].
+const SECRET_FIELDS: &[(&str, &str)] = &[
+    ("telegram", "token"),
+    ("ticktick", "client_secret"),
+    ("lark", "cookie"),
+    ("feishu", "secret"),
+    ("feishu", "app_secret"),
+    ("caldav", "password"),
+    ("webhook", "secret"),
+];
+
+const DEFAULT_REMINDER_OFFSETS: &str = "24h,3h,1h";
+const DEFAULT_UCLOUD_TIMEOUT_SECS: u64 = 10;
+const DEFAULT_TICKTICK_PROJECT: &str = "UCloud";
+
+/// Everything the push pipeline needs from the worker environment.
+///
+/// UCloud credentials are mandatory. Every sink is optional: it is enabled
+/// when all of its required settings are present, either as env vars/secrets
+/// or in the KV config document, and can be switched off explicitly with
+/// `"enabled": false` in the document or `<SINK>_ENABLED=false` in the env.
+#[derive(Clone, Debug)]
+pub struct Config {
+    pub username: String,
+    pub password: String,
+    pub api_url: String,
+    /// Timeout of every single UCloud request.
+    pub ucloud_timeout_secs: u64,
+
+    pub telegram: Option<TelegramConfig>,
+    pub ticktick: Option<TickTickConfig>,
+    pub lark: Option<LarkConfig>,
+    pub feishu: Option<FeishuConfig>,
+    pub caldav: Option<CalDavConfig>,
+    pub webhook: Option<WebhookConfig>,
+
+    /// Minutes before a deadline at which Telegram reminders go out, largest first.
+    pub reminder_offsets: Vec<i64>,
+
+    /// Encrypts the credentials stored in D1 and KV, configured by the
+    /// `CREDENTIALS_KEY` and `CREDENTIALS_PREVIOUS_KEYS` secrets.
+    pub cipher: Option<Cipher>,
+
+    /// Whose activities these are, [`d1::OWNER`] unless built by [`Config::for_user`].
+    pub user_id: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TelegramConfig {
+    pub token: String,
+    pub chat_id: String,
+    pub api_url: Option<String>,
+    /// Also post a message when a homework is submitted.
+    #[serde(default, deserialize_with = "flag")]
+    pub notify_completed: bool,
+    /// Link behind the "open in UCloud" button, `{id}` is replaced by the activity id.
+    pub open_url: Option<String>,
+    /// Beijing time at which the daily digest goes out, e.g. `21:30`.
+    #[serde(default, deserialize_with = "local_time")]
+    pub digest_time: Option<NaiveTime>,
+    /// Leave new homework to the digest instead of posting one message each.
+    #[serde(default, deserialize_with = "flag")]
+    pub digest_only: bool,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct TickTickConfig {
+    pub client_id: String,
+    pub client_secret: String,
+    /// Skips the lookup by name when set.
+    pub project_id: Option<String>,
+    /// Project the tasks go to, created on first use if it does not exist.
+    #[serde(default = "default_ticktick_project")]
+    pub project_name: String,
+    #[serde(default)]
+    pub course_mapping: CourseMapping,
+    pub redirect_uri: String,
+    pub api_url: Option<String>,
+}
+
+/// How TickTick tasks are told apart by course.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CourseMapping {
+    /// Everything goes to the one configured project.
+    #[default]
+    None,
+    /// One project per course, named after the course.
+    Project,
+    /// The configured project, with the course name as a tag.
+    Tag,
+}
+
+/// Keeps the Lark profile status up to date with the session cookie of the
+/// account. Unrelated to the [`FeishuConfig`] bot.
+#[derive(Clone, Debug, Deserialize)]
+pub struct LarkConfig {
+    pub cookie: String,
+    /// See [`crate::api::lark::render_status`], defaults to
+    /// [`crate::api::lark::DEFAULT_STATUS_TEMPLATE`].
+    pub status_template: Option<String>,
+    pub api_url: Option<String>,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct FeishuConfig {
+    #[serde(flatten)]
+    pub bot: FeishuBot,
+    /// Also post a card when a homework is submitted.
+    #[serde(default, deserialize_with = "flag")]
+    pub notify_completed: bool,
+}
+
+/// How Feishu cards are delivered, told apart by which settings are present.
+#[derive(Clone, Debug, Deserialize)]
+#[serde(untagged)]
+pub enum FeishuBot {
+    /// A custom bot added to a group chat.
+    Webhook {
+        webhook_url: String,
+        /// Set when the bot has signature verification turned on.
+        secret: Option<String>,
+    },
+    /// An app bot posting to a chat with a tenant access token.
+    App {
+        app_id: String,
+        app_secret: String,
+        chat_id: String,
+        api_url: Option<String>,
+    },
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct CalDavConfig {
+    /// The calendar collection the VTODOs go to, e.g.
+    /// `https://cloud.example/remote.php/dav/calendars/alice/homework/`.
+    pub url: String,
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Clone, Debug, Deserialize)]
+pub struct WebhookConfig {
+    pub url: String,
+    /// Key of the HMAC-SHA256 signature sent with every request.
+    pub secret: String,
+    /// JSON payload with `{{placeholder}}`s, see [`crate::api::webhook`].
+    #[serde(default, deserialize_with = "json_template")]
+    pub template: Option<Value>,
+}
+
+/// Field name in the sink config, the env var it can be read from and whether the
+/// sink is unusable without it.
+type Fields = &'static [(&'static str, &'static str, bool)];
+
+const TELEGRAM_FIELDS: Fields = &[
+    ("token", "TELEGRAM_TOKEN", true),
+    ("chat_id", "TELEGRAM_CHAT_ID", true),
+    ("api_url", "TELEGRAM_API_URL", false),
+    ("notify_completed", "TELEGRAM_NOTIFY_COMPLETED", false),
+    ("open_url", "TELEGRAM_OPEN_URL", false),
+    ("digest_time", "TELEGRAM_DIGEST_TIME", false),
+    ("digest_only", "TELEGRAM_DIGEST_ONLY", false),
+];
+
+const TICKTICK_FIELDS: Fields = &[
+    ("client_id", "TICKTICK_CLIENT_ID", true),
+    ("client_secret", "TICKTICK_CLIENT_SECRET", true),
+    ("project_id", "TICKTICK_PROJECT_ID", false),
+    ("project_name", "TICKTICK_PROJECT_NAME", false),
+    ("course_mapping", "TICKTICK_COURSE_MAPPING", false),
+    ("redirect_uri", "REDIRECT_URI", true),
+    ("api_url", "TICKTICK_API_URL", false),
+];
+
+const LARK_FIELDS: Fields = &[
+    ("cookie", "LARK_COOKIE", true),
+    ("status_template", "LARK_STATUS_TEMPLATE", false),
+    ("api_url", "LARK_API_URL", false),
+];
+
+/// None is required on its own, the bot is enabled by either the webhook or the
+/// app settings, see [`FeishuBot`] and [`feishu`].
+const FEISHU_FIELDS: Fields = &[
+    ("webhook_url", "FEISHU_WEBHOOK_URL", false),
+    ("secret", "FEISHU_SECRET", false),
+    ("app_id", "FEISHU_APP_ID", false),
+    ("app_secret", "FEISHU_APP_SECRET", false),
+    ("chat_id", "FEISHU_CHAT_ID", false),
+    ("api_url", "FEISHU_API_URL", false),
+    ("notify_completed", "FEISHU_NOTIFY_COMPLETED", false),
+];
+
+const CALDAV_FIELDS: Fields = &[
+    ("url", "CALDAV_URL", true),
+    ("username", "CALDAV_USERNAME", true),
+    ("password", "CALDAV_PASSWORD", true),
+];
+
+const WEBHOOK_FIELDS: Fields = &[
+    ("url", "WEBHOOK_URL", true),
+    ("secret", "WEBHOOK_SECRET", true),
+    ("template", "WEBHOOK_TEMPLATE", false),
+];
+
+impl Config {
+    /// Reads the env and overlays the KV config document, if one is stored.
+    pub async fn load(env: &Env, kv: &impl KeyValue) -> Result<Self> {
+        let lookup = |name: &str| {
+            env.secret(name)
+                .map(|s| s.to_string())
+                .or_else(|_| env.var(name).map(|v| v.to_string()))
+                .ok()
+        };
+        let document = load_document(kv, Cipher::from_env(lookup)?.as_ref()).await?;
+        Self::from_sources(lookup, document.as_ref())
+    }
+
+    /// Builds the config from an env lookup and an optional config document such as
+    /// `{"telegram": {"chat_id": "1"}, "lark": {"enabled": false}, "reminder_offsets": "3h"}`.
+    pub fn from_sources(
+        env: impl Fn(&str) -> Option<String>,
+        document: Option<&Value>,
+    ) -> Result<Self> {
+        let required =
+            |name: &str| env(name).ok_or_else(|| Error::config(format!("{} is not set", name)));
+
+        Ok(Self {
+            username: required("USERNAME")?,
+            password: required("PASSWORD")?,
+            api_url: required("API_URL")?,
+            ucloud_timeout_secs: match setting("ucloud_timeout", "UCLOUD_TIMEOUT", &env, document) {
+                Some(secs) => secs
+                    .parse()
+                    .map_err(|_| Error::config(format!("invalid UCloud timeout: {}", secs)))?,
+                None => DEFAULT_UCLOUD_TIMEOUT_SECS,
+            },
+
+            telegram: sink("telegram", TELEGRAM_FIELDS, &env, document)?,
+            ticktick: sink("ticktick", TICKTICK_FIELDS, &env, document)?,
+            lark: sink("lark", LARK_FIELDS, &env, document)?,
+            feishu: feishu(&env, document)?,
+            caldav: sink("caldav", CALDAV_FIELDS, &env, document)?,
+            webhook: sink("webhook", WEBHOOK_FIELDS, &env, document)?,
+
+            reminder_offsets: reminder::parse_offsets(
+                &setting("reminder_offsets", "REMINDER_OFFSETS", &env, document)
+                    .unwrap_or_else(|| DEFAULT_REMINDER_OFFSETS.to_string()),
+            )?,
+
+            cipher: Cipher::from_env(&env)?,
+
+            user_id: d1::OWNER.to_string(),
+        })
+    }
+
+    /// The config a registered user is served with: their UCloud account and chat,
+    /// the bot and settings of the deployment. The other sinks belong to the
+    /// owner, so they stay off.
+    pub fn for_user(&self, user: &d1::User, password: String) -> Self {
+        Self {
+            username: user.ucloud_username.clone(),
+            password,
+            telegram: self.telegram.clone().map(|telegram| TelegramConfig {
+                chat_id: user.chat_id.clone(),
+                ..telegram
+            }),
+            ticktick: None,
+            lark: None,
+            feishu: None,
+            caldav: None,
+            webhook: None,
+            user_id: user.user_id.clone(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Reads the config document with its credentials decrypted. With a cipher,
+/// credentials found in plaintext or under a previous key are stored again
+/// encrypted with the current key.
+pub async fn load_document(kv: &impl KeyValue, cipher: Option<&Cipher>) -> Result<Option<Value>> {
+    let Some(text) = kv.get(CONFIG_KEY).await? else {
+        return Ok(None);
+    };
+    let mut document: Value = serde_json::from_str(&text)
+        .map_err(|e| Error::config(format!("invalid config document: {}", e)))?;
+    let mut stored = document.clone();
+    let mut reencrypted = false;
+
+    for (sink, field) in SECRET_FIELDS {
+        let Some(value) = document.get_mut(*sink).and_then(|s| s.get_mut(*field)) else {
+            continue;
+        };
+        let Some(text) = value.as_str() else {
+            continue;
+        };
+        if Cipher::is_encrypted(text) {
+            let cipher = cipher.ok_or_else(|| {
+                Error::config(format!(
+                    "{}.{} in the config document is encrypted but {} is not set",
+                    sink,
+                    field,
+                    crypto::KEY_SECRET
+                ))
+            })?;
+            let plaintext = cipher.decrypt(text)?;
+            if !cipher.is_current(text) {
+                stored[*sink][*field] = cipher.encrypt(&plaintext)?.into();
+                reencrypted = true;
+            }
+            *value = plaintext.into();
+        } else if let Some(cipher) = cipher {
+            stored[*sink][*field] = cipher.encrypt(text)?.into();
+            reencrypted = true;
+        }
+    }
+
+    if reencrypted {
+        info!("encrypting the credentials in the config document");
+        kv.put(CONFIG_KEY, &stored.to_string()).await?;
+    }
+    Ok(Some(document))
+}
+
+fn default_ticktick_project() -> String {
+    DEFAULT_TICKTICK_PROJECT.to_string()
+}
+
+/// Accepts both JSON booleans from the config document and `"true"`/`"1"` from env vars.
+fn flag<'de, D: Deserializer<'de>>(deserializer: D) -> std::result::Result<bool, D::Error> {
+    Ok(match Value::deserialize(deserializer)? {
+        Value::Bool(b) => b,
+        Value::String(s) => s == "true" || s == "1",
+        _ => false,
+    })
+}
+
+/// Parses `HH:MM`.
+fn local_time<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<NaiveTime>, D::Error> {
+    match Option::<String>::deserialize(deserializer)? {
+        Some(time) => NaiveTime::parse_from_str(time.trim(), "%H:%M")
+            .map(Some)
+            .map_err(|_| serde::de::Error::custom(format!("invalid time of day: {}", time))),
+        None => Ok(None),
+    }
+}
+
+/// Takes the webhook template either as JSON in the config document or as a
+/// JSON string from the env.
+fn json_template<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> std::result::Result<Option<Value>, D::Error> {
+    match Option::<Value>::deserialize(deserializer)? {
+        Some(Value::String(text)) => serde_json::from_str(&text)
+            .map(Some)
+            .map_err(|e| serde::de::Error::custom(format!("invalid template: {}", e))),
+        template => Ok(template),
+    }
+}
+
+/// Top-level string setting, preferring the config document over the env.
+fn setting(
+    key: &str,
+    var: &str,
+    env: &impl Fn(&str) -> Option<String>,
+    document: Option<&Value>,
+) -> Option<String> {
+    document
+        .and_then(|d| d.get(key))
+        .and_then(Value::as_str)
+        .map(str::to_string)
+        .or_else(|| env(var))
+}
+
+/// The Feishu sink, if either bot kind is fully configured. Anything less, like
+/// a secret without a webhook, leaves it off like a missing required field does.
+fn feishu(
+    env: &impl Fn(&str) -> Option<String>,
+    document: Option<&Value>,
+) -> Result<Option<FeishuConfig>> {
+    let Some(fields) = sink::<Map<String, Value>>("feishu", FEISHU_FIELDS, env, document)? else {
+        return Ok(None);
+    };
+    let app = ["app_id", "app_secret", "chat_id"];
+    if !fields.contains_key("webhook_url") && !app.iter().all(|f| fields.contains_key(*f)) {
+        warn!(
+            "feishu is not configured, it needs webhook_url or all of {:?}",
+            app
+        );
+        return Ok(None);
+    }
+    serde_json::from_value(Value::Object(fields))
+        .map(Some)
+        .map_err(|e| Error::config(format!("invalid feishu config: {}", e)))
+}
+
+fn sink<T: DeserializeOwned>(
+    name: &str,
+    fields: Fields,
+    env: &impl Fn(&str) -> Option<String>,
+    document: Option<&Value>,
+) -> Result<Option<T>> {
+    let mut merged = Map::new();
+    for (field, var, _) in fields {
+        if let Some(value) = env(var) {
+            merged.insert(field.to_string(), Value::String(value));
+        }
+    }
+    if let Some(enabled) = env(&format!("{}_ENABLED", name.to_uppercase())) {
+        merged.insert(
+            "enabled".to_string(),
+            Value::Bool(enabled != "false" && enabled != "0"),
+        );
+    }
+    match document.and_then(|d| d.get(name)) {
+        Some(Value::Object(overrides)) => merged.extend(overrides.clone()),
+        Some(Value::Null) | None => {}
+        Some(_) => {
+            return Err(Error::config(format!(
+                "config for {} must be an object",
+                name
+            )))
+        }
+    }
+
+    if merged.remove("enabled") == Some(Value::Bool(false)) {
+        info!("{} is disabled", name);
+        return Ok(None);
+    }
+    if merged.is_empty() {
+        info!("{} is not configured", name);
+        return Ok(None);
+    }
+
+    let missing: Vec<&str> = fields
+        .iter()
+        .filter(|(field, _, required)| *required && !merged.contains_key(*field))
+        .map(|(field, _, _)| *field)
+        .collect();
+    if !missing.is_empty() {
+        info!("{} is not configured, missing {:?}", name, missing);
+        return Ok(None);
+    }
+
+    serde_json::from_value(Value::Object(merged))
+        .map(Some)
+        .map_err(|e| Error::config(format!("invalid {} config: {}", name, e)))
+}
@@ -0,0 +1,194 @@
+use crate::error::{Error, Result};
+use aes_gcm::aead::Aead;
+use aes_gcm::{Aes256Gcm, Key, KeyInit, Nonce};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use sha2::{Digest, Sha256};
+use std::fmt;
+
+/// Secret holding the base64 of the 32-byte key that credentials are encrypted with.
+pub const KEY_SECRET: &str = "CREDENTIALS_KEY";
+/// Secret holding retired keys, comma separated, that are still accepted for reading.
+pub const PREVIOUS_KEYS_SECRET: &str = "CREDENTIALS_PREVIOUS_KEYS";
+
+/// Data encrypted directly with the key, as stored by earlier versions.
+const V1: &str = "v1:";
+const V2: &str = "v2:";
+const NONCE_LEN: usize = 12;
+const KEY_LEN: usize = 32;
+
+/// A key-encryption key and the fingerprint it is referred to by.
+#[derive(Clone)]
+struct Kek {
+    id: String,
+    cipher: Aes256Gcm,
+}
+
+impl Kek {
+    fn new(key: &str) -> Result<Self> {
+        let key = STANDARD
+            .decode(key.trim())
+            .map_err(|e| Error::config(format!("invalid {}: {}", KEY_SECRET, e)))?;
+        if key.len() != KEY_LEN {
+            return Err(Error::config(format!(
+                "{} must be {} bytes, got {}",
+                KEY_SECRET,
+                KEY_LEN,
+                key.len()
+            )));
+        }
+        let id = Sha256::digest(&key)[..4]
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        Ok(Self {
+            id,
+            cipher: Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&key)),
+        })
+    }
+}
+
+/// Envelope encryption of credentials such as UCloud passwords and TickTick
+/// tokens before they are stored in D1 or KV.
+///
+/// Every value is encrypted with a fresh AES-256-GCM data key, which is in turn
+/// encrypted with the key from [`KEY_SECRET`]:
+/// `v2:<key id>:<base64 of the encrypted data key>:<base64 of the encrypted value>`.
+///
+/// To rotate, move the current key to [`PREVIOUS_KEYS_SECRET`] and set a new one.
+/// Values found under a previous key are re-encrypted when they are next read,
+/// see [`Cipher::is_current`], so the old key can go once every stored value
+/// has been read, i.e. after a cron run.
+#[derive(Clone)]
+pub struct Cipher {
+    current: Kek,
+    previous: Vec<Kek>,
+}
+
+impl fmt::Debug for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Cipher")
+            .field("key", &self.current.id)
+            .field("previous", &self.previous.len())
+            .finish()
+    }
+}
+
+impl Cipher {
+    pub fn new(key: &str) -> Result<Self> {
+        Ok(Self {
+            current: Kek::new(key)?,
+            previous: Vec::new(),
+        })
+    }
+
+    /// Also reads values encrypted with any of the comma separated `keys`.
+    pub fn with_previous_keys(mut self, keys: &str) -> Result<Self> {
+        for key in keys.split(',').filter(|key| !key.trim().is_empty()) {
+            self.previous.push(Kek::new(key)?);
+        }
+        Ok(self)
+    }
+
+    /// The cipher configured in the env, if a key is set.
+    pub fn from_env(env: impl Fn(&str) -> Option<String>) -> Result<Option<Self>> {
+        let Some(key) = env(KEY_SECRET) else {
+            return Ok(None);
+        };
+        let cipher = Self::new(&key)?;
+        match env(PREVIOUS_KEYS_SECRET) {
+            Some(keys) => cipher.with_previous_keys(&keys).map(Some),
+            None => Ok(Some(cipher)),
+        }
+    }
+
+    /// Whether `value` looks like something [`Cipher::encrypt`] produced.
+    pub fn is_encrypted(value: &str) -> bool {
+        value.starts_with(V1) || value.starts_with(V2)
+    }
+
+    /// Whether `ciphertext` is encrypted with the current key in the current format.
+    pub fn is_current(&self, ciphertext: &str) -> bool {
+        ciphertext
+            .strip_prefix(V2)
+            .and_then(|rest| rest.split_once(':'))
+            .is_some_and(|(id, _)| id == self.current.id)
+    }
+
+    pub fn encrypt(&self, plaintext: &str) -> Result<String> {
+        let data_key = random::<KEY_LEN>()?;
+        let wrapped = seal(&self.current.cipher, &data_key)?;
+        let data = seal(
+            &Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key)),
+            plaintext.as_bytes(),
+        )?;
+        Ok(format!(
+            "{}{}:{}:{}",
+            V2,
+            self.current.id,
+            STANDARD.encode(wrapped),
+            STANDARD.encode(data)
+        ))
+    }
+
+    pub fn decrypt(&self, ciphertext: &str) -> Result<String> {
+        let plaintext = if let Some(rest) = ciphertext.strip_prefix(V2) {
+            let mut parts = rest.splitn(3, ':');
+            let (Some(id), Some(wrapped), Some(data)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(Error::parse("malformed ciphertext"));
+            };
+            let kek = self
+                .keys()
+                .find(|kek| kek.id == id)
+                .ok_or_else(|| Error::auth(format!("credentials key {} is not configured", id)))?;
+            let data_key = open(&kek.cipher, wrapped)?;
+            if data_key.len() != KEY_LEN {
+                return Err(Error::parse("malformed data key"));
+            }
+            open(
+                &Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(&data_key)),
+                data,
+            )?
+        } else if let Some(data) = ciphertext.strip_prefix(V1) {
+            // v1 carries no key id, so every key is tried
+            self.keys()
+                .find_map(|kek| open(&kek.cipher, data).ok())
+                .ok_or_else(|| Error::auth("could not decrypt, was the key changed?"))?
+        } else {
+            return Err(Error::parse("malformed ciphertext"));
+        };
+        String::from_utf8(plaintext).map_err(|e| Error::parse(e.to_string()))
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &Kek> {
+        std::iter::once(&self.current).chain(&self.previous)
+    }
+}
+
+fn random<const N: usize>() -> Result<[u8; N]> {
+    let mut bytes = [0u8; N];
+    getrandom::fill(&mut bytes).map_err(|e| Error::config(format!("no randomness: {}", e)))?;
+    Ok(bytes)
+}
+
+/// Nonce followed by the ciphertext.
+fn seal(cipher: &Aes256Gcm, plaintext: &[u8]) -> Result<Vec<u8>> {
+    let nonce = random::<NONCE_LEN>()?;
+    let ciphertext = cipher
+        .encrypt(Nonce::from_slice(&nonce), plaintext)
+        .map_err(|_| Error::config("could not encrypt"))?;
+    Ok([nonce.as_slice(), &ciphertext].concat())
+}
+
+fn open(cipher: &Aes256Gcm, data: &str) -> Result<Vec<u8>> {
+    let data = STANDARD
+        .decode(data)
+        .ok()
+        .filter(|data| data.len() > NONCE_LEN)
+        .ok_or_else(|| Error::parse("malformed ciphertext"))?;
+    let (nonce, ciphertext) = data.split_at(NONCE_LEN);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| Error::auth("could not decrypt, was the key changed?"))
+}
@@ -1,19 +1,80 @@
+use crate::change::{self, Change, ChangeKind};
+use crate::error::Result;
 use crate::model::{UndoneList, UndoneListItem};
+use crate::storage::{Database, Statement};
 use serde::Deserialize;
-use std::collections::HashSet;
-use worker::{D1Database, Error};
+use serde_json::Value;
+use std::collections::{HashMap, HashSet};
 
 const CHUNK_SIZE: usize = 100; // 根据 D1 参数限制调整
+const PARAMS_LIMIT: usize = 100; // D1 单条语句最多绑定 100 个参数
+const ACTIVITY_COLUMNS: usize = 14;
+
+/// Scope of the account configured in secrets. Registered users are scoped by
+/// their Telegram user id, so every table keeps their activities apart.
+pub const OWNER: &str = "";
+
+/// The parts of a stored activity that are compared against fresh fetches.
+#[derive(Clone, Debug, Deserialize)]
+pub struct ActivityRecord {
+    pub activity_id: String,
+    pub activity_name: String,
+    pub end_time: String,
+    pub start_time: Option<String>,
+    pub description: Option<String>,
+    pub is_overtime_commit: Option<i32>,
+    /// JSON list of [`Attachment`](crate::model::Attachment)s.
+    pub attachments: Option<String>,
+    pub course_name: Option<String>,
+    #[serde(default)]
+    pub details_fetched_at: Option<String>,
+}
 
 #[derive(Debug, Deserialize)]
 struct ActivityRow {
     activity_id: String,
 }
 
-pub async fn filter_pushed_undone_list(
+#[derive(Debug, Deserialize)]
+struct ReminderRow {
+    activity_id: String,
+    offset_minutes: i64,
+}
+
+#[derive(Debug, Deserialize)]
+struct StateRow {
+    state: String,
+}
+
+pub const DELIVERED: &str = "delivered";
+pub const FAILED: &str = "failed";
+
+/// Keeps the items that have not been delivered to `sink` yet, including earlier failures.
+pub async fn filter_undelivered(
+    undone_list: &UndoneList,
+    sink: &str,
+    user: &str,
+    db: &impl Database,
+) -> Result<UndoneList> {
+    filter_existing(
+        undone_list,
+        "SELECT activity_id FROM deliveries
+             WHERE activity_id IN (SELECT value FROM json_each(?1))
+             AND sink = ?2 AND status = 'delivered' AND user_id = ?3",
+        &[sink.into(), user.into()],
+        db,
+    )
+    .await
+}
+
+/// Drops every item whose id is returned by `sql`, which receives the JSON array of
+/// incoming ids as `?1` followed by `extra_params`.
+async fn filter_existing(
     undone_list: &UndoneList,
-    db: &D1Database,
-) -> worker::Result<UndoneList> {
+    sql: &str,
+    extra_params: &[Value],
+    db: &impl Database,
+) -> Result<UndoneList> {
     let incoming_ids: Vec<&str> = undone_list
         .undone_list
         .iter()
@@ -29,24 +90,13 @@ pub async fn filter_pushed_undone_list(
     }
 
     // 分块处理查询
-    let mut stmts = Vec::new();
+    let mut existing_ids = HashSet::new();
     for chunk in incoming_ids.chunks(CHUNK_SIZE) {
-        let json_ids = serde_json::to_string(chunk).map_err(|e| Error::RustError(e.to_string()))?;
+        let json_ids = serde_json::to_string(chunk)?;
+        let mut params = vec![json_ids.into()];
+        params.extend_from_slice(extra_params);
 
-        let stmt = db
-            .prepare(
-                "SELECT activity_id FROM activities 
-             WHERE activity_id IN (SELECT value FROM json_each(?1))",
-            )
-            .bind(&[json_ids.into()])?;
-
-        stmts.push(stmt);
-    }
-
-    // 批量执行所有查询
-    let mut existing_ids = HashSet::new();
-    for result_chunk in db.batch(stmts).await? {
-        let rows = result_chunk.results::<ActivityRow>()?;
+        let rows = db.query::<ActivityRow>(sql, &params).await?;
         for row in rows {
             existing_ids.insert(row.activity_id);
         }
@@ -67,34 +117,189 @@ pub async fn filter_pushed_undone_list(
     })
 }
 
+/// Records how pushing each activity to `sink` went, counting one attempt per call.
+pub async fn record_deliveries(
+    outcomes: &[(&str, std::result::Result<(), String>)],
+    sink: &str,
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
+    let stmts = outcomes
+        .iter()
+        .map(|(activity_id, outcome)| {
+            let (status, last_error) = match outcome {
+                Ok(()) => (DELIVERED, Value::Null),
+                Err(e) => (FAILED, Value::String(e.clone())),
+            };
+            Statement::new(
+                "INSERT INTO deliveries (user_id, activity_id, sink, status, attempts, last_error, delivered_at)
+                VALUES (?5, ?1, ?2, ?3, 1, ?4, CASE WHEN ?3 = 'delivered' THEN CURRENT_TIMESTAMP END)
+                ON CONFLICT(user_id, activity_id, sink) DO UPDATE SET
+                    status = excluded.status,
+                    attempts = deliveries.attempts + 1,
+                    last_error = excluded.last_error,
+                    delivered_at = excluded.delivered_at",
+                vec![
+                    (*activity_id).into(),
+                    sink.into(),
+                    status.into(),
+                    last_error,
+                    user.into(),
+                ],
+            )
+        })
+        .collect();
+
+    db.batch(stmts).await
+}
+
+/// An edit that has not reached a sink yet.
+#[derive(Clone, Debug)]
+pub struct PendingChange {
+    pub activity_id: String,
+    pub kinds: Vec<ChangeKind>,
+}
+
+#[derive(Deserialize)]
+struct PendingChangeRow {
+    activity_id: String,
+    sink: String,
+    kinds: String,
+}
+
+/// Queues `changes` for every one of `sinks`, folded into what each sink has
+/// not received yet, so that a sink that fails keeps the change for its next run.
+pub async fn queue_changes(
+    changes: &[Change],
+    sinks: &[&str],
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
+    if changes.is_empty() || sinks.is_empty() {
+        return Ok(());
+    }
+    let ids: Vec<&str> = changes
+        .iter()
+        .map(|c| c.item.activity_id.as_str())
+        .collect();
+    let queued = db
+        .query::<PendingChangeRow>(
+            "SELECT activity_id, sink, kinds FROM change_deliveries
+            WHERE user_id = ?1 AND activity_id IN (SELECT value FROM json_each(?2))",
+            &[user.into(), serde_json::to_string(&ids)?.into()],
+        )
+        .await?;
+
+    let mut stmts = Vec::new();
+    for change in changes {
+        for sink in sinks {
+            let older = match queued
+                .iter()
+                .find(|row| row.activity_id == change.item.activity_id && row.sink == *sink)
+            {
+                Some(row) => serde_json::from_str(&row.kinds)?,
+                None => Vec::new(),
+            };
+            let kinds = change::merge(&older, &change.kinds);
+            let activity_id = change.item.activity_id.as_str();
+            stmts.push(if kinds.is_empty() {
+                Statement::new(
+                    "DELETE FROM change_deliveries
+                    WHERE activity_id = ?1 AND sink = ?2 AND user_id = ?3",
+                    vec![activity_id.into(), (*sink).into(), user.into()],
+                )
+            } else {
+                Statement::new(
+                    "INSERT INTO change_deliveries (activity_id, sink, user_id, kinds)
+                    VALUES (?1, ?2, ?3, ?4)
+                    ON CONFLICT(user_id, activity_id, sink) DO UPDATE SET kinds = excluded.kinds",
+                    vec![
+                        activity_id.into(),
+                        (*sink).into(),
+                        user.into(),
+                        serde_json::to_string(&kinds)?.into(),
+                    ],
+                )
+            });
+        }
+    }
+    db.batch(stmts).await
+}
+
+/// The edits `sink` has not received yet.
+pub async fn get_pending_changes(
+    sink: &str,
+    user: &str,
+    db: &impl Database,
+) -> Result<Vec<PendingChange>> {
+    db.query::<PendingChangeRow>(
+        "SELECT activity_id, sink, kinds FROM change_deliveries
+        WHERE sink = ?1 AND user_id = ?2
+        ORDER BY activity_id",
+        &[sink.into(), user.into()],
+    )
+    .await?
+    .into_iter()
+    .map(|row| {
+        Ok(PendingChange {
+            activity_id: row.activity_id,
+            kinds: serde_json::from_str(&row.kinds)?,
+        })
+    })
+    .collect()
+}
+
+/// Drops the changes `sink` received and counts an attempt for the others.
+pub async fn record_change_deliveries(
+    outcomes: &[(&str, std::result::Result<(), String>)],
+    sink: &str,
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
+    let stmts = outcomes
+        .iter()
+        .map(|(activity_id, outcome)| match outcome {
+            Ok(()) => Statement::new(
+                "DELETE FROM change_deliveries
+                WHERE activity_id = ?1 AND sink = ?2 AND user_id = ?3",
+                vec![(*activity_id).into(), sink.into(), user.into()],
+            ),
+            Err(e) => Statement::new(
+                "UPDATE change_deliveries SET attempts = attempts + 1, last_error = ?4
+                WHERE activity_id = ?1 AND sink = ?2 AND user_id = ?3",
+                vec![
+                    (*activity_id).into(),
+                    sink.into(),
+                    user.into(),
+                    e.clone().into(),
+                ],
+            ),
+        })
+        .collect();
+    db.batch(stmts).await
+}
+
+/// Inserts new activities and refreshes the mutable fields of known ones.
 pub async fn save_activities_batch(
     items: &[UndoneListItem],
-    db: &D1Database,
-) -> worker::Result<()> {
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
     if items.is_empty() {
         return Ok(());
     }
 
     let mut stmts = Vec::new();
 
-    for chunk in items.chunks(CHUNK_SIZE) {
+    for chunk in items.chunks(PARAMS_LIMIT / ACTIVITY_COLUMNS) {
         let mut placeholders = Vec::new();
-        let mut params = Vec::new();
+        let mut params: Vec<Value> = Vec::new();
 
         for (i, item) in chunk.iter().enumerate() {
-            placeholders.push(format!(
-                "(?{}, ?{}, ?{}, ?{}, ?{}, ?{}, ?{}, ?{}, ?{}, ?{})",
-                i * 10 + 1,
-                i * 10 + 2,
-                i * 10 + 3,
-                i * 10 + 4,
-                i * 10 + 5,
-                i * 10 + 6,
-                i * 10 + 7,
-                i * 10 + 8,
-                i * 10 + 9,
-                i * 10 + 10
-            ));
+            let row: Vec<String> = (1..=ACTIVITY_COLUMNS)
+                .map(|c| format!("?{}", i * ACTIVITY_COLUMNS + c))
+                .collect();
+            placeholders.push(format!("({})", row.join(", ")));
 
             let course_info = item
                 .course_info
@@ -103,6 +308,7 @@ pub async fn save_activities_batch(
                 .unwrap_or_default();
 
             params.extend_from_slice(&[
+                user.into(),
                 item.activity_id.clone().into(),
                 item.activity_name.clone().into(),
                 item.r#type.into(),
@@ -111,51 +317,479 @@ pub async fn save_activities_batch(
                 item.evaluation_status.into(),
                 item.is_open_evaluation.into(),
                 course_info.into(),
-                item.description.clone().unwrap_or_default().into(),
+                item.description.clone().into(),
                 item.start_time.clone().unwrap_or_default().into(),
+                item.is_overtime_commit.map(i32::from).into(),
+                item.attachments
+                    .as_ref()
+                    .map(serde_json::to_string)
+                    .transpose()?
+                    .into(),
+                item.details_fetched_at.clone().into(),
             ]);
         }
 
         let sql = format!(
-            "INSERT OR IGNORE INTO activities (
-                activity_id, activity_name, type, end_time,
-                assignment_type, evaluation_status, 
-                is_open_evaluation, course_info, description, start_time
+            "INSERT INTO activities (
+                user_id, activity_id, activity_name, type, end_time,
+                assignment_type, evaluation_status,
+                is_open_evaluation, course_info, description, start_time,
+                is_overtime_commit, attachments, details_fetched_at
             ) VALUES {}
-            ON CONFLICT(activity_id) DO UPDATE SET
+            ON CONFLICT(user_id, activity_id) DO UPDATE SET
+                activity_name = excluded.activity_name,
                 end_time = excluded.end_time,
-                evaluation_status = excluded.evaluation_status",
+                evaluation_status = excluded.evaluation_status,
+                description = COALESCE(excluded.description, activities.description),
+                start_time = COALESCE(NULLIF(excluded.start_time, ''), activities.start_time),
+                is_overtime_commit = COALESCE(excluded.is_overtime_commit, activities.is_overtime_commit),
+                attachments = COALESCE(excluded.attachments, activities.attachments),
+                details_fetched_at = COALESCE(excluded.details_fetched_at, activities.details_fetched_at),
+                completed_at = NULL",
             placeholders.join(",")
         );
 
-        let stmt = db.prepare(&sql).bind(&params)?;
-        stmts.push(stmt);
+        stmts.push(Statement::new(sql, params));
     }
 
     db.batch(stmts).await?;
     Ok(())
 }
 
-pub async fn cleanup_activities(db: &D1Database) -> worker::Result<()> {
-    db.exec("DELETE FROM activities").await?;
-    Ok(())
+/// Stored state of the given activities, for the ones that are known.
+pub async fn get_activities(
+    items: &[UndoneListItem],
+    user: &str,
+    db: &impl Database,
+) -> Result<Vec<ActivityRecord>> {
+    let mut records = Vec::new();
+    for chunk in items.chunks(CHUNK_SIZE) {
+        let ids: Vec<&str> = chunk.iter().map(|item| item.activity_id.as_str()).collect();
+        records.extend(
+            db.query::<ActivityRecord>(
+                "SELECT activity_id, activity_name, end_time, start_time, description, is_overtime_commit,
+                attachments, details_fetched_at,
+                CASE WHEN json_valid(course_info)
+                    THEN json_extract(course_info, '$.name') END AS course_name
+                FROM activities
+                WHERE activity_id IN (SELECT value FROM json_each(?1)) AND user_id = ?2",
+                &[serde_json::to_string(&ids)?.into(), user.into()],
+            )
+            .await?,
+        );
+    }
+    Ok(records)
+}
+
+/// Every `(activity_id, offset_minutes)` reminder already sent for `items`.
+pub async fn get_sent_reminders(
+    items: &[UndoneListItem],
+    user: &str,
+    db: &impl Database,
+) -> Result<HashSet<(String, i64)>> {
+    let mut sent = HashSet::new();
+    for chunk in items.chunks(CHUNK_SIZE) {
+        let ids: Vec<&str> = chunk.iter().map(|item| item.activity_id.as_str()).collect();
+        let rows = db
+            .query::<ReminderRow>(
+                "SELECT activity_id, offset_minutes FROM reminders
+             WHERE activity_id IN (SELECT value FROM json_each(?1)) AND user_id = ?2",
+                &[serde_json::to_string(&ids)?.into(), user.into()],
+            )
+            .await?;
+        sent.extend(rows.into_iter().map(|r| (r.activity_id, r.offset_minutes)));
+    }
+    Ok(sent)
 }
 
-pub async fn save_state(state: &str, db: &D1Database) -> worker::Result<()> {
-    let stmts = vec![db
-        .prepare("INSERT OR REPLACE INTO state state VALUES ?1")
-        .bind(&[state.into()])?];
+/// What the user chose for an activity through the inline keyboard.
+#[derive(Clone, Debug, Default, Deserialize)]
+pub struct Preference {
+    pub activity_id: String,
+    pub done_at: Option<String>,
+    /// Unix timestamp until which no reminders are sent.
+    pub snoozed_until: Option<i64>,
+    /// Extra reminder offset in minutes.
+    pub remind_before: Option<i64>,
+}
+
+pub async fn get_preferences(
+    items: &[UndoneListItem],
+    user: &str,
+    db: &impl Database,
+) -> Result<HashMap<String, Preference>> {
+    let mut preferences = HashMap::new();
+    for chunk in items.chunks(CHUNK_SIZE) {
+        let ids: Vec<&str> = chunk.iter().map(|item| item.activity_id.as_str()).collect();
+        let rows = db
+            .query::<Preference>(
+                "SELECT activity_id, done_at, snoozed_until, remind_before FROM preferences
+                WHERE activity_id IN (SELECT value FROM json_each(?1)) AND user_id = ?2",
+                &[serde_json::to_string(&ids)?.into(), user.into()],
+            )
+            .await?;
+        preferences.extend(rows.into_iter().map(|p| (p.activity_id.clone(), p)));
+    }
+    Ok(preferences)
+}
+
+pub async fn mark_done(activity_id: &str, user: &str, db: &impl Database) -> Result<()> {
+    db.batch(vec![Statement::new(
+        "INSERT INTO preferences (user_id, activity_id, done_at) VALUES (?2, ?1, CURRENT_TIMESTAMP)
+        ON CONFLICT(user_id, activity_id) DO UPDATE SET done_at = excluded.done_at",
+        vec![activity_id.into(), user.into()],
+    )])
+    .await
+}
+
+pub async fn snooze(activity_id: &str, until: i64, user: &str, db: &impl Database) -> Result<()> {
+    db.batch(vec![Statement::new(
+        "INSERT INTO preferences (user_id, activity_id, snoozed_until) VALUES (?3, ?1, ?2)
+        ON CONFLICT(user_id, activity_id) DO UPDATE SET snoozed_until = excluded.snoozed_until",
+        vec![activity_id.into(), until.into(), user.into()],
+    )])
+    .await
+}
+
+pub async fn remind_before(
+    activity_id: &str,
+    minutes: i64,
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
+    db.batch(vec![Statement::new(
+        "INSERT INTO preferences (user_id, activity_id, remind_before) VALUES (?3, ?1, ?2)
+        ON CONFLICT(user_id, activity_id) DO UPDATE SET remind_before = excluded.remind_before",
+        vec![activity_id.into(), minutes.into(), user.into()],
+    )])
+    .await
+}
+
+pub async fn save_reminders(
+    activity_id: &str,
+    offsets: &[i64],
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
+    let stmts = offsets
+        .iter()
+        .map(|offset| {
+            Statement::new(
+                "INSERT OR IGNORE INTO reminders (user_id, activity_id, offset_minutes)
+                VALUES (?3, ?1, ?2)",
+                vec![activity_id.into(), (*offset).into(), user.into()],
+            )
+        })
+        .collect();
+    db.batch(stmts).await
+}
+
+/// Stored activities that are still open but no longer in the fetched undone list,
+/// which is how UCloud reports a submitted homework.
+pub async fn get_vanished_activities(
+    undone_list: &UndoneList,
+    user: &str,
+    db: &impl Database,
+) -> Result<Vec<ActivityRecord>> {
+    let ids: Vec<&str> = undone_list
+        .undone_list
+        .iter()
+        .map(|item| item.activity_id.as_str())
+        .collect();
+    db.query::<ActivityRecord>(
+        "SELECT activity_id, activity_name, end_time, start_time, description, is_overtime_commit,
+                attachments,
+                CASE WHEN json_valid(course_info)
+                    THEN json_extract(course_info, '$.name') END AS course_name
+        FROM activities
+        WHERE completed_at IS NULL AND user_id = ?2
+            AND activity_id NOT IN (SELECT value FROM json_each(?1))",
+        &[serde_json::to_string(&ids)?.into(), user.into()],
+    )
+    .await
+}
+
+pub async fn mark_completed(
+    records: &[ActivityRecord],
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
+    let stmts = records
+        .iter()
+        .flat_map(|record| {
+            let params = vec![record.activity_id.clone().into(), user.into()];
+            [
+                Statement::new(
+                    "UPDATE activities SET completed_at = CURRENT_TIMESTAMP
+                    WHERE activity_id = ?1 AND user_id = ?2",
+                    params.clone(),
+                ),
+                // edits of a submitted activity are no longer worth announcing
+                Statement::new(
+                    "DELETE FROM change_deliveries WHERE activity_id = ?1 AND user_id = ?2",
+                    params,
+                ),
+            ]
+        })
+        .collect();
+    db.batch(stmts).await
+}
+
+/// Queues the completion of `records` for every one of `sinks`, so that a sink
+/// that fails keeps it for its next run.
+pub async fn queue_completions(
+    records: &[ActivityRecord],
+    sinks: &[&str],
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
+    let stmts = records
+        .iter()
+        .flat_map(|record| {
+            sinks.iter().map(|sink| {
+                Statement::new(
+                    "INSERT INTO completion_deliveries (activity_id, sink, user_id)
+                    VALUES (?1, ?2, ?3)
+                    ON CONFLICT(user_id, activity_id, sink) DO NOTHING",
+                    vec![
+                        record.activity_id.clone().into(),
+                        (*sink).into(),
+                        user.into(),
+                    ],
+                )
+            })
+        })
+        .collect();
+    db.batch(stmts).await
+}
+
+/// The submitted activities whose completion `sink` has not received yet.
+pub async fn get_pending_completions(
+    sink: &str,
+    user: &str,
+    db: &impl Database,
+) -> Result<Vec<ActivityRecord>> {
+    db.query::<ActivityRecord>(
+        "SELECT a.activity_id, a.activity_name, a.end_time, a.start_time, a.description,
+                a.is_overtime_commit, a.attachments,
+                CASE WHEN json_valid(a.course_info)
+                    THEN json_extract(a.course_info, '$.name') END AS course_name
+        FROM completion_deliveries c
+        JOIN activities a ON a.user_id = c.user_id AND a.activity_id = c.activity_id
+        WHERE c.sink = ?1 AND c.user_id = ?2 AND a.completed_at IS NOT NULL
+        ORDER BY a.activity_id",
+        &[sink.into(), user.into()],
+    )
+    .await
+}
+
+/// Drops the completions `sink` received and counts an attempt for the others.
+pub async fn record_completion_deliveries(
+    outcomes: &[(&str, std::result::Result<(), String>)],
+    sink: &str,
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
+    let stmts = outcomes
+        .iter()
+        .map(|(activity_id, outcome)| match outcome {
+            Ok(()) => Statement::new(
+                "DELETE FROM completion_deliveries
+                WHERE activity_id = ?1 AND sink = ?2 AND user_id = ?3",
+                vec![(*activity_id).into(), sink.into(), user.into()],
+            ),
+            Err(e) => Statement::new(
+                "UPDATE completion_deliveries SET attempts = attempts + 1, last_error = ?4
+                WHERE activity_id = ?1 AND sink = ?2 AND user_id = ?3",
+                vec![
+                    (*activity_id).into(),
+                    sink.into(),
+                    user.into(),
+                    e.clone().into(),
+                ],
+            ),
+        })
+        .collect();
+    db.batch(stmts).await
+}
+
+/// A stored activity that is neither submitted nor marked done.
+#[derive(Clone, Debug, Deserialize)]
+pub struct OpenActivity {
+    pub activity_id: String,
+    pub activity_name: String,
+    /// Empty when the details could not be fetched yet.
+    pub start_time: String,
+    pub end_time: String,
+    pub course_name: Option<String>,
+    /// HTML, as UCloud serves it.
+    pub description: Option<String>,
+    /// When the activity was first seen, in UTC.
+    pub pushed_at: Option<String>,
+}
+
+/// Every open activity, earliest deadline first.
+pub async fn get_open_activities(user: &str, db: &impl Database) -> Result<Vec<OpenActivity>> {
+    db.query::<OpenActivity>(
+        "SELECT a.activity_id, a.activity_name, a.start_time, a.end_time,
+                CASE WHEN json_valid(a.course_info)
+                    THEN json_extract(a.course_info, '$.name') END AS course_name,
+                a.description, a.pushed_at
+        FROM activities a
+        LEFT JOIN preferences p ON p.user_id = a.user_id AND p.activity_id = a.activity_id
+        WHERE a.user_id = ?1 AND a.completed_at IS NULL AND p.done_at IS NULL
+        ORDER BY a.end_time, a.activity_id",
+        &[user.into()],
+    )
+    .await
+}
+
+/// The TickTick task created for an activity.
+#[derive(Debug, Deserialize)]
+pub struct TickTickTask {
+    #[serde(rename = "ticktick_task_id")]
+    pub id: String,
+    /// Unknown for tasks created before projects were resolved by name.
+    #[serde(rename = "ticktick_project_id")]
+    pub project_id: Option<String>,
+}
+
+pub async fn get_ticktick_task(
+    activity_id: &str,
+    user: &str,
+    db: &impl Database,
+) -> Result<Option<TickTickTask>> {
+    let rows = db
+        .query::<TickTickTask>(
+            "SELECT ticktick_task_id, ticktick_project_id FROM activities
+             WHERE activity_id = ?1 AND user_id = ?2 AND ticktick_task_id IS NOT NULL",
+            &[activity_id.into(), user.into()],
+        )
+        .await?;
+    Ok(rows.into_iter().next())
+}
+
+pub async fn save_ticktick_task(
+    activity_id: &str,
+    task_id: &str,
+    project_id: &str,
+    user: &str,
+    db: &impl Database,
+) -> Result<()> {
+    db.batch(vec![Statement::new(
+        "UPDATE activities SET ticktick_task_id = ?2, ticktick_project_id = ?3
+         WHERE activity_id = ?1 AND user_id = ?4",
+        vec![
+            activity_id.into(),
+            task_id.into(),
+            project_id.into(),
+            user.into(),
+        ],
+    )])
+    .await
+}
+
+/// Forgets the reminders of an activity, e.g. after its deadline moved.
+pub async fn clear_reminders(activity_id: &str, user: &str, db: &impl Database) -> Result<()> {
+    db.batch(vec![Statement::new(
+        "DELETE FROM reminders WHERE activity_id = ?1 AND user_id = ?2",
+        vec![activity_id.into(), user.into()],
+    )])
+    .await
+}
+
+/// Forgets everything stored for `user`'s activities.
+pub async fn cleanup_activities(user: &str, db: &impl Database) -> Result<()> {
+    let stmts = [
+        "preferences",
+        "reminders",
+        "change_deliveries",
+        "completion_deliveries",
+        "deliveries",
+        "activities",
+    ]
+    .into_iter()
+    .map(|table| {
+        Statement::new(
+            format!("DELETE FROM {} WHERE user_id = ?1", table),
+            vec![user.into()],
+        )
+    })
+    .collect();
+    db.batch(stmts).await
+}
+
+/// A Telegram user who registered their own UCloud account.
+#[derive(Clone, Debug, Deserialize)]
+pub struct User {
+    pub user_id: String,
+    pub chat_id: String,
+    pub ucloud_username: String,
+    /// Encrypted, see [`crate::crypto::Cipher`].
+    pub ucloud_password: String,
+}
+
+pub async fn get_users(db: &impl Database) -> Result<Vec<User>> {
+    db.query(
+        "SELECT user_id, chat_id, ucloud_username, ucloud_password FROM users ORDER BY created_at",
+        &[],
+    )
+    .await
+}
+
+pub async fn get_user(user_id: &str, db: &impl Database) -> Result<Option<User>> {
+    let rows = db
+        .query::<User>(
+            "SELECT user_id, chat_id, ucloud_username, ucloud_password FROM users
+             WHERE user_id = ?1",
+            &[user_id.into()],
+        )
+        .await?;
+    Ok(rows.into_iter().next())
+}
+
+/// Registers a user, or replaces the account of one registered before.
+pub async fn save_user(user: &User, db: &impl Database) -> Result<()> {
+    db.batch(vec![Statement::new(
+        "INSERT INTO users (user_id, chat_id, ucloud_username, ucloud_password)
+        VALUES (?1, ?2, ?3, ?4)
+        ON CONFLICT(user_id) DO UPDATE SET
+            chat_id = excluded.chat_id,
+            ucloud_username = excluded.ucloud_username,
+            ucloud_password = excluded.ucloud_password",
+        vec![
+            user.user_id.clone().into(),
+            user.chat_id.clone().into(),
+            user.ucloud_username.clone().into(),
+            user.ucloud_password.clone().into(),
+        ],
+    )])
+    .await
+}
+
+/// Unregisters a user together with everything stored for them.
+pub async fn delete_user(user_id: &str, db: &impl Database) -> Result<()> {
+    cleanup_activities(user_id, db).await?;
+    db.batch(vec![Statement::new(
+        "DELETE FROM users WHERE user_id = ?1",
+        vec![user_id.into()],
+    )])
+    .await
+}
+
+pub async fn save_state(state: &str, db: &impl Database) -> Result<()> {
+    let stmts = vec![Statement::new(
+        "INSERT OR REPLACE INTO state state VALUES ?1",
+        vec![state.into()],
+    )];
     db.batch(stmts).await?;
     Ok(())
 }
 
-pub async fn get_state(db: &D1Database) -> worker::Result<Option<String>> {
-    let rows = db
-        .batch(vec![db.prepare("SELECT state FROM state")])
-        .await?[0]
-        .results::<(String,)>()?;
-    if let Some((state,)) = rows.into_iter().next() {
-        Ok(Some(state))
+pub async fn get_state(db: &impl Database) -> Result<Option<String>> {
+    let rows = db.query::<StateRow>("SELECT state FROM state", &[]).await?;
+    if let Some(row) = rows.into_iter().next() {
+        Ok(Some(row.state))
     } else {
         Ok(None)
     }
@@ -0,0 +1,146 @@
+use crate::api::telegram::{escape_html, Telegram};
+use crate::config::TelegramConfig;
+use crate::d1::{self, OpenActivity};
+use crate::error::Result;
+use crate::model::{parse_ucloud_time, ucloud_offset};
+use crate::reminder::format_remaining;
+use crate::storage::{user_key, Database, KeyValue};
+use chrono::{DateTime, Duration, NaiveDateTime, NaiveTime, Utc};
+use std::collections::BTreeMap;
+use tracing::info;
+
+/// KV key holding the unix timestamp of the last digest, see [`user_key`].
+pub const DIGEST_KEY: &str = "digest_sent_at";
+/// Deadlines this close count as due soon.
+const DUE_SOON: Duration = Duration::hours(48);
+/// Heading of assignments without a course.
+const NO_COURSE: &str = "其他";
+
+/// Why an assignment made it into the digest, most urgent first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum Section {
+    Overdue,
+    DueSoon,
+    New,
+}
+
+impl Section {
+    fn icon(self) -> &'static str {
+        match self {
+            Section::Overdue => "⌛",
+            Section::DueSoon => "⏰",
+            Section::New => "🆕",
+        }
+    }
+}
+
+/// Whether the digest for today is due: `digest_time` has passed in Beijing
+/// and none went out since.
+pub fn is_due(
+    digest_time: NaiveTime,
+    last_sent: Option<DateTime<Utc>>,
+    now: DateTime<Utc>,
+) -> bool {
+    let local = now.with_timezone(&ucloud_offset());
+    let Some(scheduled) = local
+        .date_naive()
+        .and_time(digest_time)
+        .and_local_timezone(ucloud_offset())
+        .single()
+    else {
+        return false;
+    };
+    local >= scheduled && last_sent.is_none_or(|sent| sent < scheduled)
+}
+
+/// Summarises the assignments that are overdue, due within two days or were
+/// first seen after `since`, grouped by course.
+pub fn render(activities: &[OpenActivity], since: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let mut courses: BTreeMap<&str, Vec<String>> = BTreeMap::new();
+    let mut counts: BTreeMap<Section, usize> = BTreeMap::new();
+
+    for activity in activities {
+        let deadline = parse_ucloud_time(&activity.end_time).map(|d| d.to_utc());
+        let first_seen = activity
+            .pushed_at
+            .as_deref()
+            .and_then(|t| NaiveDateTime::parse_from_str(t, "%Y-%m-%d %H:%M:%S").ok())
+            .map(|t| t.and_utc());
+        let section = match deadline {
+            Some(deadline) if deadline <= now => Section::Overdue,
+            Some(deadline) if deadline - now <= DUE_SOON => Section::DueSoon,
+            _ if first_seen.is_some_and(|t| t > since) => Section::New,
+            _ => continue,
+        };
+        *counts.entry(section).or_default() += 1;
+
+        let remaining = match deadline {
+            Some(deadline) if deadline > now => {
+                format!("还剩 {}", format_remaining((deadline - now).num_minutes()))
+            }
+            Some(_) => "已截止".to_string(),
+            None => "截止时间未知".to_string(),
+        };
+        courses
+            .entry(activity.course_name.as_deref().unwrap_or(NO_COURSE))
+            .or_default()
+            .push(format!(
+                "{} {}（{}，{}）",
+                section.icon(),
+                escape_html(&activity.activity_name),
+                activity.end_time,
+                remaining
+            ));
+    }
+
+    let mut msg = String::from("<b>📰 每日作业汇总</b>\n");
+    if courses.is_empty() {
+        msg.push_str("\n今天没有需要关注的作业 🎉");
+        return msg;
+    }
+    let count = |section| counts.get(&section).copied().unwrap_or_default();
+    msg.push_str(&format!(
+        "🆕 新作业 {} · ⏰ 即将截止 {} · ⌛ 已逾期 {}\n",
+        count(Section::New),
+        count(Section::DueSoon),
+        count(Section::Overdue)
+    ));
+    for (course, lines) in courses {
+        msg.push_str(&format!("\n<b>{}</b>\n", escape_html(course)));
+        for line in lines {
+            msg.push_str(&line);
+            msg.push('\n');
+        }
+    }
+    msg.trim_end().to_string()
+}
+
+/// Sends the daily digest if one is configured and due. Assignments first seen
+/// since the previous digest, or within the last day before the first one, count as new.
+pub async fn send_if_due(
+    config: &TelegramConfig,
+    bot: &Telegram,
+    user: &str,
+    db: &impl Database,
+    kv: &impl KeyValue,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let Some(digest_time) = config.digest_time else {
+        return Ok(());
+    };
+    let key = user_key(DIGEST_KEY, user);
+    let last_sent = kv
+        .get(&key)
+        .await?
+        .and_then(|t| t.parse().ok())
+        .and_then(|t| DateTime::from_timestamp(t, 0));
+    if !is_due(digest_time, last_sent, now) {
+        return Ok(());
+    }
+
+    info!("sending the daily digest");
+    let activities = d1::get_open_activities(user, db).await?;
+    let since = last_sent.unwrap_or(now - Duration::days(1));
+    bot.send_message(&render(&activities, since, now)).await?;
+    kv.put(&key, &now.timestamp().to_string()).await
+}
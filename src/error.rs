@@ -0,0 +1,127 @@
+use thiserror::Error;
+
+pub type Result<T, E = Error> = std::result::Result<T, E>;
+
+/// Everything that can go wrong while serving a request or running the cron.
+#[derive(Debug, Error)]
+pub enum Error {
+    /// A secret, var or config document entry is missing or malformed.
+    #[error("config error: {0}")]
+    Config(String),
+    /// An upstream service could not be reached or answered with an error.
+    #[error("upstream request failed: {message}")]
+    Upstream {
+        status: Option<u16>,
+        message: String,
+    },
+    /// A response or request body did not have the expected shape.
+    #[error("parse error: {0}")]
+    Parse(String),
+    /// D1 or KV failed.
+    #[error("storage error: {0}")]
+    Storage(String),
+    /// Credentials were rejected or the OAuth flow was tampered with.
+    #[error("auth error: {0}")]
+    Auth(String),
+    /// The incoming request is missing something it needs.
+    #[error("bad request: {0}")]
+    BadRequest(String),
+}
+
+impl Error {
+    pub fn config(message: impl Into<String>) -> Self {
+        Self::Config(message.into())
+    }
+
+    pub fn upstream(message: impl Into<String>) -> Self {
+        Self::Upstream {
+            status: None,
+            message: message.into(),
+        }
+    }
+
+    pub fn parse(message: impl Into<String>) -> Self {
+        Self::Parse(message.into())
+    }
+
+    pub fn storage(message: impl Into<String>) -> Self {
+        Self::Storage(message.into())
+    }
+
+    pub fn auth(message: impl Into<String>) -> Self {
+        Self::Auth(message.into())
+    }
+
+    pub fn bad_request(message: impl Into<String>) -> Self {
+        Self::BadRequest(message.into())
+    }
+
+    /// Whether trying again later may succeed: network errors, timeouts,
+    /// rate limits and server errors.
+    pub fn is_transient(&self) -> bool {
+        match self {
+            Error::Upstream { status: None, .. } => true,
+            Error::Upstream {
+                status: Some(status),
+                ..
+            } => *status == 429 || *status >= 500,
+            _ => false,
+        }
+    }
+
+    /// HTTP status the fetch handler answers with.
+    pub fn status(&self) -> u16 {
+        match self {
+            Error::Config(_) | Error::Storage(_) => 500,
+            Error::Upstream {
+                status: Some(408 | 504),
+                ..
+            } => 504,
+            Error::Upstream { .. } | Error::Parse(_) => 502,
+            Error::Auth(_) => 401,
+            Error::BadRequest(_) => 400,
+        }
+    }
+}
+
+impl From<reqwest::Error> for Error {
+    fn from(e: reqwest::Error) -> Self {
+        // urls can carry secrets such as the Telegram bot token
+        let e = e.without_url();
+        if e.is_decode() {
+            return Error::Parse(e.to_string());
+        }
+        Error::Upstream {
+            status: if e.is_timeout() {
+                Some(504)
+            } else {
+                e.status().map(|s| s.as_u16())
+            },
+            message: e.to_string(),
+        }
+    }
+}
+
+impl From<serde_json::Error> for Error {
+    fn from(e: serde_json::Error) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl From<chrono::ParseError> for Error {
+    fn from(e: chrono::ParseError) -> Self {
+        Error::Parse(e.to_string())
+    }
+}
+
+impl From<worker::Error> for Error {
+    fn from(e: worker::Error) -> Self {
+        Error::Storage(e.to_string())
+    }
+}
+
+impl From<Error> for worker::Error {
+    fn from(e: Error) -> Self {
+        worker::Error::RustError(e.to_string())
+    }
+}
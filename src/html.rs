@@ -0,0 +1,472 @@
+use html5tokenizer::attr::AttributeMap;
+use html5tokenizer::{NaiveParser, Token};
+
+/// Most UTF-16 code units Telegram accepts in a message.
+pub const MESSAGE_LIMIT: usize = 4096;
+/// Most UTF-16 code units Telegram accepts in a media caption.
+pub const CAPTION_LIMIT: usize = 1024;
+
+/// A piece of Telegram HTML that must not be cut: a tag, an entity or a character.
+enum Unit<'a> {
+    Open { name: &'a str, tag: &'a str },
+    Close { name: &'a str, tag: &'a str },
+    Text(&'a str),
+}
+
+impl Unit<'_> {
+    fn as_str(&self) -> &str {
+        match self {
+            Unit::Open { tag, .. } | Unit::Close { tag, .. } => tag,
+            Unit::Text(text) => text,
+        }
+    }
+}
+
+fn units(html: &str) -> Vec<Unit<'_>> {
+    let mut units = Vec::new();
+    let mut rest = html;
+    while let Some(c) = rest.chars().next() {
+        let end = match c {
+            '<' => rest.find('>').map(|i| i + 1),
+            // entities are short, a lone `&` is just a character
+            '&' => rest
+                .char_indices()
+                .take(12)
+                .find(|(_, c)| *c == ';')
+                .map(|(i, _)| i + 1),
+            _ => None,
+        }
+        .unwrap_or(c.len_utf8());
+        let (unit, tail) = rest.split_at(end);
+        rest = tail;
+
+        units.push(if let Some(name) = unit.strip_prefix("</") {
+            Unit::Close {
+                name: tag_name(name),
+                tag: unit,
+            }
+        } else if unit.len() > 1 && unit.starts_with('<') {
+            Unit::Open {
+                name: tag_name(&unit[1..]),
+                tag: unit,
+            }
+        } else {
+            Unit::Text(unit)
+        });
+    }
+    units
+}
+
+fn tag_name(tag: &str) -> &str {
+    let end = tag
+        .find(|c: char| !c.is_ascii_alphanumeric())
+        .unwrap_or(tag.len());
+    &tag[..end]
+}
+
+fn length(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+fn closing(open: &[(&str, &str)]) -> String {
+    open.iter()
+        .rev()
+        .map(|(name, _)| format!("</{}>", name))
+        .collect()
+}
+
+fn reopening(open: &[(&str, &str)]) -> String {
+    open.iter().map(|(_, tag)| *tag).collect()
+}
+
+/// Whether a chunk has anything to show once the tags are stripped.
+fn is_blank(chunk: &str) -> bool {
+    units(chunk)
+        .iter()
+        .all(|unit| !matches!(unit, Unit::Text(text) if !text.trim().is_empty()))
+}
+
+/// Splits Telegram HTML into chunks of at most `limit` UTF-16 code units,
+/// preferring line breaks, then spaces, in the second half of a chunk. Tags open at a cut are closed at the
+/// end of the chunk and reopened at the start of the next one, and neither
+/// tags nor entities are ever cut. Only a single tag longer than `limit`
+/// produces a chunk above it.
+pub fn split_html(html: &str, limit: usize) -> Vec<String> {
+    let mut chunks = Vec::new();
+    let mut open: Vec<(&str, &str)> = Vec::new();
+    let mut current = String::new();
+    // the last line break and space in the current chunk, with the tags open there
+    let mut newline: Option<(usize, Vec<(&str, &str)>)> = None;
+    let mut space: Option<(usize, Vec<(&str, &str)>)> = None;
+
+    for unit in units(html) {
+        let mut after = open.clone();
+        match &unit {
+            Unit::Open { name, tag } => after.push((name, tag)),
+            Unit::Close { name, .. } => {
+                if let Some(i) = after.iter().rposition(|(open, _)| open == name) {
+                    after.remove(i);
+                }
+            }
+            Unit::Text(_) => {}
+        }
+
+        while length(&current) + length(unit.as_str()) + length(&closing(&after)) > limit
+            && !is_blank(&current)
+        {
+            // a break in the first half would leave a short chunk behind
+            let half = current.len() / 2;
+            let at = [newline.take(), space.take()]
+                .into_iter()
+                .flatten()
+                .find(|(at, _)| *at >= half);
+            let (chunk, rest) = match at {
+                // the break itself is dropped
+                Some((at, tags)) => (
+                    format!("{}{}", &current[..at], closing(&tags)),
+                    format!("{}{}", reopening(&tags), &current[at + 1..]),
+                ),
+                None => (format!("{}{}", current, closing(&open)), reopening(&open)),
+            };
+            chunks.push(chunk);
+            current = rest;
+        }
+
+        match unit {
+            Unit::Text("\n") => newline = Some((current.len(), open.clone())),
+            Unit::Text(" ") => space = Some((current.len(), open.clone())),
+            _ => {}
+        }
+        current.push_str(unit.as_str());
+        open = after;
+    }
+    if !is_blank(&current) {
+        chunks.push(format!("{}{}", current, closing(&open)));
+    }
+    chunks
+        .into_iter()
+        .map(|chunk| chunk.trim().to_string())
+        .filter(|chunk| !is_blank(chunk))
+        .collect()
+}
+
+/// A description rewritten into Telegram's HTML subset, and the images it showed.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct Sanitized {
+    pub text: String,
+    pub images: Vec<String>,
+}
+
+/// Escapes text for Telegram's HTML parse mode.
+pub fn escape_html(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+}
+
+/// Escapes an attribute value, which Telegram expects in double quotes.
+fn escape_attribute(value: &str) -> String {
+    escape_html(value).replace('"', "&quot;")
+}
+
+/// Telegram's tag for a formatting element, if it has one.
+fn inline_tag(name: &str) -> Option<&'static str> {
+    Some(match name {
+        "b" | "strong" => "b",
+        "i" | "em" | "cite" | "var" => "i",
+        "u" | "ins" => "u",
+        "s" | "strike" | "del" => "s",
+        "code" | "kbd" | "samp" | "tt" => "code",
+        "pre" => "pre",
+        "blockquote" => "blockquote",
+        _ => return None,
+    })
+}
+
+/// Elements that start on a line of their own.
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div"
+            | "section"
+            | "article"
+            | "header"
+            | "footer"
+            | "ul"
+            | "ol"
+            | "li"
+            | "table"
+            | "tr"
+            | "pre"
+            | "blockquote"
+            | "hr"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+    )
+}
+
+fn is_heading(name: &str) -> bool {
+    matches!(name, "h1" | "h2" | "h3" | "h4" | "h5" | "h6")
+}
+
+/// Elements whose content is never shown.
+fn is_hidden(name: &str) -> bool {
+    matches!(
+        name,
+        "script" | "style" | "head" | "title" | "template" | "noscript"
+    )
+}
+
+/// Links Telegram can open; everything else keeps its text only.
+fn is_safe_url(url: &str) -> bool {
+    let url = url.trim().to_ascii_lowercase();
+    ["http://", "https://", "mailto:", "tg://"]
+        .iter()
+        .any(|scheme| url.starts_with(scheme))
+}
+
+/// An open list, with the number of the next item for ordered ones.
+enum List {
+    Bullet,
+    Numbered(usize),
+}
+
+#[derive(Default)]
+struct Sanitizer {
+    out: String,
+    images: Vec<String>,
+    /// Open Telegram tags: the element that opened it, the tag and where it starts in `out`.
+    open: Vec<(String, String, usize)>,
+    lists: Vec<List>,
+    /// Cells written in the current table row.
+    cells: usize,
+    /// Depth inside elements whose content is dropped.
+    hidden: usize,
+    /// Depth inside `pre`, where whitespace is kept.
+    preformatted: usize,
+    /// Collapsed whitespace waiting for the next visible character.
+    pending_space: bool,
+}
+
+impl Sanitizer {
+    /// Whether nothing but tags opened on this line was written since the last break.
+    fn at_line_start(&self) -> bool {
+        let mut end = self.out.len();
+        for (_, tag, start) in self.open.iter().rev() {
+            if start + tag.len() != end {
+                break;
+            }
+            end = *start;
+        }
+        end == 0 || self.out[..end].ends_with('\n')
+    }
+
+    fn newline(&mut self) {
+        self.pending_space = false;
+        if !self.at_line_start() {
+            self.out.push('\n');
+        }
+    }
+
+    fn text(&mut self, c: char) {
+        if self.hidden > 0 {
+            return;
+        }
+        if self.preformatted == 0 && c.is_ascii_whitespace() {
+            self.pending_space = !self.at_line_start();
+            return;
+        }
+        if std::mem::take(&mut self.pending_space) {
+            self.out.push(' ');
+        }
+        match c {
+            '&' => self.out.push_str("&amp;"),
+            '<' => self.out.push_str("&lt;"),
+            '>' => self.out.push_str("&gt;"),
+            c => self.out.push(c),
+        }
+    }
+
+    fn in_code(&self) -> bool {
+        self.open
+            .iter()
+            .any(|(_, tag, _)| tag == "<code>" || tag == "<pre>")
+    }
+
+    fn open(&mut self, element: &str, tag: String) {
+        if std::mem::take(&mut self.pending_space) {
+            self.out.push(' ');
+        }
+        self.open
+            .push((element.to_string(), tag.clone(), self.out.len()));
+        self.out.push_str(&tag);
+    }
+
+    /// Closes the innermost `element`. Tags opened inside it are closed first and
+    /// reopened after it, like browsers do for misnested formatting.
+    fn close(&mut self, element: &str) {
+        let Some(i) = self.open.iter().rposition(|(open, _, _)| open == element) else {
+            return;
+        };
+        for (element, tag, _) in self.close_from(i).into_iter().skip(1) {
+            self.open(&element, tag);
+        }
+    }
+
+    /// Closes the tags from `i` up, dropping the ones that ended up empty and
+    /// keeping line breaks outside of them.
+    fn close_from(&mut self, i: usize) -> Vec<(String, String, usize)> {
+        let closed = self.open.split_off(i);
+        let breaks = self.out.len() - self.out.trim_end_matches('\n').len();
+        self.out.truncate(self.out.len() - breaks);
+        for (_, tag, start) in closed.iter().rev() {
+            if self.out.len() == start + tag.len() {
+                self.out.truncate(*start);
+            } else {
+                self.out.push_str(&format!("</{}>", tag_name(&tag[1..])));
+            }
+        }
+        self.out.push_str(&"\n".repeat(breaks));
+        closed
+    }
+
+    fn start(&mut self, name: &str, attributes: &AttributeMap, self_closing: bool) {
+        if is_hidden(name) {
+            if !self_closing {
+                self.hidden += 1;
+            }
+            return;
+        }
+        if self.hidden > 0 {
+            return;
+        }
+        if is_block(name) {
+            self.newline();
+        }
+        match name {
+            "br" => self.out.push('\n'),
+            "hr" => self.out.push_str("——————\n"),
+            "img" => {
+                if let Some(src) = attributes.get("src").filter(|src| is_safe_url(src)) {
+                    self.images.push(src.trim().to_string());
+                }
+            }
+            "ul" => self.lists.push(List::Bullet),
+            "ol" => self.lists.push(List::Numbered(1)),
+            "li" => {
+                let depth = self.lists.len().saturating_sub(1);
+                self.out.push_str(&"  ".repeat(depth));
+                match self.lists.last_mut() {
+                    Some(List::Numbered(n)) => {
+                        self.out.push_str(&format!("{}. ", n));
+                        *n += 1;
+                    }
+                    _ => self.out.push_str("• "),
+                }
+            }
+            "tr" => self.cells = 0,
+            "td" | "th" => {
+                if self.cells > 0 {
+                    self.out.push_str(" | ");
+                }
+                self.pending_space = false;
+                self.cells += 1;
+                if name == "th" && !self.in_code() {
+                    self.open(name, "<b>".to_string());
+                }
+            }
+            "a" => {
+                let href = attributes.get("href").filter(|href| is_safe_url(href));
+                let nested = self.open.iter().any(|(open, _, _)| open == "a");
+                if let (Some(href), false, false) = (href, nested, self.in_code()) {
+                    let tag = format!("<a href=\"{}\">", escape_attribute(href.trim()));
+                    self.open(name, tag);
+                }
+            }
+            _ if is_heading(name) && !self.in_code() => self.open(name, "<b>".to_string()),
+            _ => {
+                // code may sit inside pre, nothing else may sit inside either
+                let Some(tag) = inline_tag(name) else {
+                    return;
+                };
+                if self.in_code() && !(tag == "code" && self.preformatted > 0) {
+                    return;
+                }
+                if name == "pre" {
+                    self.preformatted += 1;
+                }
+                self.open(name, format!("<{}>", tag));
+            }
+        }
+    }
+
+    fn end(&mut self, name: &str) {
+        if is_hidden(name) {
+            self.hidden = self.hidden.saturating_sub(1);
+            return;
+        }
+        if self.hidden > 0 {
+            return;
+        }
+        match name {
+            // browsers read a stray `</br>` as a line break too
+            "br" => self.out.push('\n'),
+            "ul" | "ol" => {
+                self.lists.pop();
+            }
+            "pre" => self.preformatted = self.preformatted.saturating_sub(1),
+            _ => {}
+        }
+        self.close(name);
+        if is_block(name) {
+            self.newline();
+        }
+        if matches!(name, "p" | "table" | "pre" | "blockquote") || is_heading(name) {
+            // a blank line after paragraphs, as browsers show them
+            if !self.out.ends_with("\n\n") && !self.out.is_empty() {
+                self.out.push('\n');
+            }
+        }
+    }
+
+    fn finish(mut self) -> Sanitized {
+        self.close_from(0);
+        // no more than one blank line in a row
+        let mut text = String::new();
+        for line in self.out.split('\n') {
+            let line = line.trim_end();
+            if line.is_empty() && (text.is_empty() || text.ends_with("\n\n")) {
+                continue;
+            }
+            text.push_str(line);
+            text.push('\n');
+        }
+        Sanitized {
+            text: text.trim_end().to_string(),
+            images: self.images,
+        }
+    }
+}
+
+/// Rewrites UCloud's rich text into the HTML subset Telegram accepts: formatting
+/// is mapped onto Telegram's tags, headings become bold lines, lists get bullets
+/// or numbers, table cells are separated by `|`, links keep a safe `href` and
+/// images are collected instead of shown. Text is escaped, whitespace collapsed
+/// and misnested or unclosed tags are repaired, so any input gives valid output.
+pub fn sanitize(html: &str) -> Sanitized {
+    let mut sanitizer = Sanitizer::default();
+    for token in NaiveParser::new(html).flatten() {
+        match token {
+            Token::StartTag(tag) => sanitizer.start(&tag.name, &tag.attributes, tag.self_closing),
+            Token::EndTag(tag) => sanitizer.end(&tag.name),
+            Token::Char(c) => sanitizer.text(c),
+            // doctypes and comments carry nothing worth sending
+            _ => {}
+        }
+    }
+    sanitizer.finish()
+}
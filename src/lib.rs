@@ -1,10 +1,23 @@
+pub mod actions;
 pub mod api;
+pub mod calendar;
+pub mod change;
+pub mod config;
+pub mod crypto;
 pub mod d1;
+pub mod digest;
+pub mod error;
+pub mod html;
 pub mod model;
+pub mod overview;
+pub mod pipeline;
+pub mod reminder;
+pub mod storage;
 pub mod ucloud;
+pub mod users;
 
-use api::Api;
-use tracing::{error, info};
+use config::Config;
+use tracing::error;
 use tracing_subscriber::{
     fmt::{format::Pretty, time::UtcTime},
     layer::SubscriberExt,
@@ -28,116 +41,237 @@ fn start() {
 }
 
 #[event(fetch)]
-async fn fetch(mut req: Request, env: Env, _ctx: Context) -> Result<Response> {
+async fn fetch(req: Request, env: Env, _ctx: Context) -> Result<Response> {
+    match route(req, &env).await {
+        Ok(response) => Ok(response),
+        Err(e) => {
+            error!("fetch error: {:?}", e);
+            Response::error(e.to_string(), e.status())
+        }
+    }
+}
+
+async fn route(mut req: Request, env: &Env) -> error::Result<Response> {
     if req.method() != Method::Get && req.method() != Method::Post {
-        return Response::error("Method Not Allowed", 405);
+        return Ok(Response::error("Method Not Allowed", 405)?);
     }
 
-    let kv = env.kv("KV").unwrap();
-
-    match req
-        .url()?
-        .path_segments()
-        .unwrap()
-        .collect::<Vec<_>>()
-        .first()
-    {
-        Some(&"ping") => Response::ok("pong"),
-        Some(&"telegram") => {
+    let kv = kv_binding(env)?;
+    let url = req.url()?;
+
+    match url.path_segments().and_then(|mut segments| segments.next()) {
+        Some("ping") => Ok(Response::ok("pong")?),
+        Some("telegram") => {
+            let config = Config::load(env, &kv).await?;
+            let Some(telegram) = &config.telegram else {
+                return Ok(Response::error("Telegram is not configured", 404)?);
+            };
             let body = req.text().await?;
-            let parsed: serde_json::Value = serde_json::from_str(&body)?;
+            let parsed: serde_json::Value = serde_json::from_str(&body)
+                .map_err(|e| error::Error::bad_request(format!("invalid update: {}", e)))?;
 
-            let bot_token = env.secret("TELEGRAM_TOKEN")?.to_string();
             match req.headers().get("X-Telegram-Bot-Api-Secret-Token")? {
-                Some(token) if token != bot_token => {
+                Some(token) if token != telegram.token => {
                     error!("Unauthorized: {}", token);
-                    return Response::error("Unauthorized", 401);
+                    return Err(error::Error::auth("invalid secret token"));
                 }
                 _ => {}
             }
 
             let allowed_id = env
-                .secret("TELEGRAM_ALLOWED_USER_ID")?
+                .secret("TELEGRAM_ALLOWED_USER_ID")
+                .map_err(|_| error::Error::config("TELEGRAM_ALLOWED_USER_ID is not set"))?
                 .to_string()
                 .parse::<i64>()
-                .unwrap();
-            if parsed.get("message").is_none() {
-                return Response::ok("Not a message");
-            }
-            let user_id = parsed["message"]["from"]["id"].as_i64().unwrap();
-            if user_id != allowed_id {
-                return Response::error("Unauthorized", 401);
+                .map_err(|_| error::Error::config("TELEGRAM_ALLOWED_USER_ID is not a number"))?;
+            let db = db_binding(env)?;
+
+            if let Some(query) = parsed.get("callback_query") {
+                let sender = query["from"]["id"].as_i64().unwrap_or_default();
+                let Some(config) = users::config_for(&config, sender, allowed_id, &db).await?
+                else {
+                    return Err(error::Error::auth(
+                        "callback from a user that is not allowed",
+                    ));
+                };
+                let bot = api::telegram::Telegram::from_config(
+                    config.telegram.as_ref().unwrap_or(telegram),
+                );
+                actions::handle_callback(query, &bot, &config.user_id, &db, chrono::Utc::now())
+                    .await?;
+                return Ok(Response::ok("Callback handled")?);
             }
+            let Some(message) = parsed.get("message") else {
+                return Ok(Response::ok("Not a message")?);
+            };
+            let sender = message["from"]["id"]
+                .as_i64()
+                .ok_or_else(|| error::Error::bad_request("message without sender"))?;
 
-            let message_text = match parsed["message"]["text"].as_str() {
+            let message_text = match message["text"].as_str() {
                 Some(text) => text,
-                None => return Response::ok("No text"),
+                None => return Ok(Response::ok("No text")?),
+            };
+
+            // commands may be addressed as `/list@bot` in groups
+            let (command, argument) = message_text
+                .split_once(char::is_whitespace)
+                .unwrap_or((message_text, ""));
+            let command = command.split('@').next().unwrap_or(command);
+
+            if command == "/register" {
+                if sender == allowed_id
+                    || !users::may_register(
+                        env_value(env, users::REGISTRATION_VAR).as_deref(),
+                        sender,
+                    )
+                {
+                    return Err(error::Error::auth(format!(
+                        "user {} may not register",
+                        sender
+                    )));
+                }
+                // answer in the chat the command came from, the owner's chat is someone else's
+                let bot = api::telegram::Telegram::from_config(&config::TelegramConfig {
+                    chat_id: message["chat"]["id"].to_string(),
+                    ..telegram.clone()
+                });
+                users::register(&config, &bot, message, argument, &db).await?;
+                return Ok(Response::ok("Registration handled")?);
+            }
+
+            let Some(config) = users::config_for(&config, sender, allowed_id, &db).await? else {
+                return Err(error::Error::auth(format!(
+                    "user {} is not allowed",
+                    sender
+                )));
             };
+            let bot =
+                api::telegram::Telegram::from_config(config.telegram.as_ref().unwrap_or(telegram));
 
-            match message_text {
+            match command {
                 "/ping" => {
-                    let bot = api::telegram::Telegram::new(
-                        env.secret("TELEGRAM_TOKEN").unwrap().to_string(),
-                        env.secret("TELEGRAM_CHAT_ID").unwrap().to_string(),
-                    );
-                    bot.send_message("呜，别敲啦!").await.unwrap();
-                    return Response::ok("pong");
+                    bot.send_message("呜，别敲啦!").await?;
+                    Ok(Response::ok("pong")?)
                 }
                 "/push" => {
-                    push(env).await?;
-                    Response::ok("Push triggered")
+                    // answering with an error would only make Telegram redeliver the command
+                    if let Err(e) = pipeline::push(&config, &db, &kv).await {
+                        error!("push error: {:?}", e);
+                        pipeline::report(&config, &e).await;
+                    }
+                    Ok(Response::ok("Push triggered")?)
                 }
                 "/clear" => {
-                    let db = env.d1("DB").unwrap();
-                    d1::cleanup_activities(&db).await.unwrap();
-                    api::telegram::Telegram::new(
-                        env.secret("TELEGRAM_TOKEN").unwrap().to_string(),
-                        env.secret("TELEGRAM_CHAT_ID").unwrap().to_string(),
-                    )
-                    .send_message("已经清理干净啦!")
-                    .await
-                    .unwrap();
-                    Response::ok("Database cleared")
+                    d1::cleanup_activities(&config.user_id, &db).await?;
+                    bot.send_message("已经清理干净啦!").await?;
+                    Ok(Response::ok("Database cleared")?)
+                }
+                "/unregister" => {
+                    if config.user_id == d1::OWNER {
+                        bot.send_message("部署配置的账号不能注销哦").await?;
+                        return Ok(Response::ok("Owner cannot unregister")?);
+                    }
+                    calendar::revoke(&config.user_id, config.cipher.as_ref(), &kv).await?;
+                    d1::delete_user(&config.user_id, &db).await?;
+                    bot.send_message("已经注销啦，你的作业记录也一并删除了")
+                        .await?;
+                    Ok(Response::ok("User unregistered")?)
                 }
                 "/refresh" => {
-                    let ticktick = api::ticktick::TickTick::new(
-                        env.secret("TICKTICK_CLIENT_ID").unwrap().to_string(),
-                        env.secret("TICKTICK_CLIENT_SECRET").unwrap().to_string(),
-                        env.secret("TICKTICK_PROJECT_ID").unwrap().to_string(),
-                        kv.clone(),
+                    let Some(ticktick_config) = &config.ticktick else {
+                        bot.send_message("滴答清单还没有配置哦").await?;
+                        return Ok(Response::ok("TickTick is not configured")?);
+                    };
+                    let ticktick = api::ticktick::TickTick::from_config(
+                        ticktick_config,
+                        config.cipher.as_ref(),
+                        &kv,
                     )
-                    .await;
-                    let bot = api::telegram::Telegram::new(
-                        env.secret("TELEGRAM_TOKEN").unwrap().to_string(),
-                        env.secret("TELEGRAM_CHAT_ID").unwrap().to_string(),
-                    );
+                    .await?;
 
                     ticktick
-                        .login(&bot, &env.secret("REDIRECT_URI").unwrap().to_string(), kv)
-                        .await
-                        .unwrap();
-                    Response::ok("Refresh triggered")
+                        .login(&bot, &ticktick_config.redirect_uri, &kv)
+                        .await?;
+                    Ok(Response::ok("Refresh triggered")?)
                 }
-                _ => Response::ok("Unknown command"),
+                "/list" => {
+                    let window = overview::Window::All;
+                    overview::send(&bot, window, &config.user_id, &db, chrono::Utc::now()).await?;
+                    Ok(Response::ok("List sent")?)
+                }
+                "/due" => {
+                    let Some(window) = overview::Window::parse(argument.trim())
+                        .filter(|window| *window != overview::Window::All)
+                    else {
+                        bot.send_message("用法：/due today 或 /due week").await?;
+                        return Ok(Response::ok("Unknown window")?);
+                    };
+                    overview::send(&bot, window, &config.user_id, &db, chrono::Utc::now()).await?;
+                    Ok(Response::ok("List sent")?)
+                }
+                "/calendar" => {
+                    let reset = argument.trim() == "reset";
+                    let token =
+                        calendar::token(&config.user_id, reset, config.cipher.as_ref(), &kv)
+                            .await?;
+                    let feed = format!(
+                        "{}/calendar/{}.ics",
+                        url.origin().ascii_serialization(),
+                        token
+                    );
+                    bot.send_message(&format!(
+                        "📅 在日历应用中订阅这个链接，就能看到所有未完成的作业：\n{}\n\n链接只属于你，泄露时发送 /calendar reset 换一个",
+                        api::telegram::escape_html(&feed)
+                    ))
+                    .await?;
+                    Ok(Response::ok("Calendar link sent")?)
+                }
+                _ => Ok(Response::ok("Unknown command")?),
+            }
+        }
+        Some("calendar") => {
+            let Some(token) = url
+                .path_segments()
+                .and_then(|mut segments| segments.nth(1))
+                .and_then(|file| file.strip_suffix(".ics"))
+            else {
+                return Ok(Response::error("Not Found", 404)?);
+            };
+            let kind = url
+                .query_pairs()
+                .find(|(key, _)| key == "type")
+                .map(|(_, value)| value.into_owned());
+            let component = calendar::Component::parse(kind.as_deref())
+                .ok_or_else(|| error::Error::bad_request("type must be event or todo"))?;
+
+            let db = db_binding(env)?;
+            match calendar::feed(token, component, &db, &kv, chrono::Utc::now()).await? {
+                Some(document) => {
+                    let mut response = Response::ok(document)?;
+                    response
+                        .headers_mut()
+                        .set("Content-Type", "text/calendar; charset=utf-8")?;
+                    Ok(response)
+                }
+                None => Ok(Response::error("Not Found", 404)?),
             }
         }
-        Some(&"auth") => {
-            let ticktick = api::ticktick::TickTick::new(
-                env.secret("TICKTICK_CLIENT_ID").unwrap().to_string(),
-                env.secret("TICKTICK_CLIENT_SECRET").unwrap().to_string(),
-                env.secret("TICKTICK_PROJECT_ID").unwrap().to_string(),
-                kv.clone(),
-            )
-            .await;
-            let url = req.url().unwrap();
+        Some("auth") => {
+            let config = Config::load(env, &kv).await?;
+            let Some(ticktick_config) = &config.ticktick else {
+                return Ok(Response::error("TickTick is not configured", 404)?);
+            };
+            let ticktick =
+                api::ticktick::TickTick::from_config(ticktick_config, config.cipher.as_ref(), &kv)
+                    .await?;
 
             ticktick
-                .auth(url, &env.secret("REDIRECT_URI").unwrap().to_string(), kv)
-                .await
-                .unwrap();
-            Response::ok("Success")
+                .auth(url, &ticktick_config.redirect_uri, &kv)
+                .await?;
+            Ok(Response::ok("Success")?)
         }
-        _ => Response::error("Not Found", 404),
+        _ => Ok(Response::error("Not Found", 404)?),
     }
 }
 
@@ -147,60 +281,41 @@ async fn scheduled(
     env: worker::Env,
     _ctx: worker::ScheduleContext,
 ) {
-    if let Err(e) = push(env).await {
+    if let Err(e) = push(&env).await {
         error!("push error: {:?}", e);
+        // without a config there is no chat to report to
+        if let Ok(config) = load_config(&env).await {
+            pipeline::report(&config, &e).await;
+        }
     }
 }
 
-async fn push(env: worker::Env) -> Result<()> {
-    let ucloud = ucloud::UCloud::new(
-        env.secret("USERNAME").unwrap().to_string(),
-        env.secret("PASSWORD").unwrap().to_string(),
-        env.secret("API_URL").unwrap().to_string(),
-    );
-    let db = env.d1("DB").unwrap();
-    let kv = env.kv("KV").unwrap();
-
-    let undone_list = ucloud.get_undone_list().await.unwrap();
-    info!("undone_list: {:?}", undone_list);
-
-    let unpushed_list = d1::filter_pushed_undone_list(&undone_list, &db)
-        .await
-        .unwrap();
-
-    // push to lark
-    let lark = api::lark::Lark::new(env.secret("LARK_COOKIE").unwrap().to_string());
-    lark.push(&undone_list).await.unwrap();
-
-    // push to telegram
-    let bot = api::telegram::Telegram::new(
-        env.secret("TELEGRAM_TOKEN").unwrap().to_string(),
-        env.secret("TELEGRAM_CHAT_ID").unwrap().to_string(),
-    );
-    bot.push(&unpushed_list).await.unwrap();
-
-    // push to ticktick
-    let ticktick = api::ticktick::TickTick::new(
-        env.secret("TICKTICK_CLIENT_ID").unwrap().to_string(),
-        env.secret("TICKTICK_CLIENT_SECRET").unwrap().to_string(),
-        env.secret("TICKTICK_PROJECT_ID").unwrap().to_string(),
-        kv.clone(),
-    )
-    .await;
-    if ticktick.access_token.is_none() {
-        ticktick
-            .login(&bot, &env.secret("REDIRECT_URI").unwrap().to_string(), kv)
-            .await
-            .unwrap();
-        info!("Sent login link to telegram");
-    } else {
-        ticktick.push(&unpushed_list).await.unwrap();
-    }
+fn kv_binding(env: &Env) -> error::Result<kv::KvStore> {
+    env.kv("KV")
+        .map_err(|e| error::Error::config(format!("KV binding is missing: {}", e)))
+}
+
+fn db_binding(env: &Env) -> error::Result<D1Database> {
+    env.d1("DB")
+        .map_err(|e| error::Error::config(format!("DB binding is missing: {}", e)))
+}
+
+async fn load_config(env: &Env) -> error::Result<Config> {
+    Config::load(env, &kv_binding(env)?).await
+}
+
+/// A secret, or a plain var of the same name.
+fn env_value(env: &Env, name: &str) -> Option<String> {
+    env.secret(name)
+        .map(|s| s.to_string())
+        .or_else(|_| env.var(name).map(|v| v.to_string()))
+        .ok()
+}
 
-    // save to database
-    d1::save_activities_batch(&unpushed_list.undone_list, &db)
-        .await
-        .unwrap();
+async fn push(env: &Env) -> error::Result<()> {
+    let db = db_binding(env)?;
+    let kv = kv_binding(env)?;
+    let config = Config::load(env, &kv).await?;
 
-    Ok(())
+    users::push_all(&config, &db, &kv).await
 }
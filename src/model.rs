@@ -1,5 +1,11 @@
+use chrono::{DateTime, FixedOffset, NaiveDateTime};
 use serde::{Deserialize, Serialize};
 
+/// UCloud reports every timestamp in Beijing time without an offset.
+pub fn ucloud_offset() -> FixedOffset {
+    FixedOffset::east_opt(8 * 3600).unwrap()
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Homework {
     pub id: String,
@@ -34,6 +40,29 @@ pub struct UndoneListItem {
     pub start_time: Option<String>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub is_overtime_commit: Option<bool>,
+    /// Files attached to the assignment, `None` until the detail is known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub attachments: Option<Vec<Attachment>>,
+    /// When the detail was fetched from UCloud, in UTC like SQLite's `CURRENT_TIMESTAMP`.
+    #[serde(skip)]
+    pub details_fetched_at: Option<String>,
+    /// The detail could not be fetched, only the undone list fields are known.
+    #[serde(skip)]
+    pub details_unavailable: bool,
+}
+
+/// Parses a UCloud timestamp such as `2025-03-08 23:59:00`.
+pub fn parse_ucloud_time(time: &str) -> Option<DateTime<FixedOffset>> {
+    NaiveDateTime::parse_from_str(time, "%Y-%m-%d %H:%M:%S")
+        .ok()?
+        .and_local_timezone(ucloud_offset())
+        .single()
+}
+
+impl UndoneListItem {
+    pub fn deadline(&self) -> Option<DateTime<FixedOffset>> {
+        parse_ucloud_time(&self.end_time)
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -43,14 +72,31 @@ pub struct CourseInfo {
     pub teachers: String,
 }
 
+/// An assignment attachment resolved to a download URL.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Attachment {
+    pub name: String,
+    pub url: String,
+}
+
+/// Answer of the UCloud API's resource endpoint.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ResourceUrl {
+    pub url: String,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug)]
 #[serde(rename_all = "camelCase")]
 pub struct Task {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
     pub title: String,
     pub project_id: String,
     pub start_date: Option<String>,
     pub due_date: Option<String>,
     pub content: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tags: Option<Vec<String>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -0,0 +1,186 @@
+use crate::api::telegram::{escape_html, Telegram};
+use crate::d1::{self, OpenActivity};
+use crate::error::Result;
+use crate::model::{parse_ucloud_time, ucloud_offset};
+use crate::reminder::format_remaining;
+use crate::storage::Database;
+use chrono::{DateTime, Duration, Utc};
+use serde_json::{json, Value};
+
+/// Assignments per message, further ones are reached through the keyboard.
+pub const PAGE_SIZE: usize = 10;
+
+/// Which deadlines `/list` and `/due` show.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Window {
+    All,
+    Today,
+    Week,
+}
+
+impl Window {
+    fn code(self) -> &'static str {
+        match self {
+            Window::All => "all",
+            Window::Today => "today",
+            Window::Week => "week",
+        }
+    }
+
+    /// Parses the argument of `/due`.
+    pub fn parse(code: &str) -> Option<Self> {
+        [Window::All, Window::Today, Window::Week]
+            .into_iter()
+            .find(|window| window.code() == code)
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Window::All => "📋 所有未完成的作业",
+            Window::Today => "📅 今天截止的作业",
+            Window::Week => "📅 七天内截止的作业",
+        }
+    }
+
+    /// `/list` also shows overdue assignments, `/due` only upcoming ones.
+    fn contains(self, deadline: DateTime<Utc>, now: DateTime<Utc>) -> bool {
+        let end = match self {
+            Window::All => return true,
+            Window::Today => {
+                let today = now.with_timezone(&ucloud_offset()).date_naive();
+                (today + Duration::days(1))
+                    .and_hms_opt(0, 0, 0)
+                    .and_then(|t| t.and_local_timezone(ucloud_offset()).single())
+                    .map(|t| t.to_utc())
+                    .unwrap_or(now)
+            }
+            Window::Week => now + Duration::days(7),
+        };
+        deadline > now && deadline < end
+    }
+
+    /// `callback_data` of the button leading to `page`, e.g. `page:week:1`.
+    pub fn page_data(self, page: usize) -> String {
+        format!("page:{}:{}", self.code(), page)
+    }
+
+    /// Splits the `callback_data` of a page button into the window and the page.
+    pub fn parse_page(data: &str) -> Option<(Self, usize)> {
+        let rest = data.strip_prefix("page:")?;
+        let (code, page) = rest.split_once(':')?;
+        Some((Window::parse(code)?, page.parse().ok()?))
+    }
+}
+
+/// A rendered page and the keyboard to turn it, if there is more than one.
+#[derive(Debug)]
+pub struct Page {
+    pub text: String,
+    pub keyboard: Option<Value>,
+}
+
+/// Renders page `page` (0-based, clamped) of the activities within `window`.
+pub fn render(
+    activities: &[OpenActivity],
+    window: Window,
+    page: usize,
+    now: DateTime<Utc>,
+) -> Page {
+    let activities: Vec<(&OpenActivity, Option<DateTime<Utc>>)> = activities
+        .iter()
+        .map(|a| (a, parse_ucloud_time(&a.end_time).map(|d| d.to_utc())))
+        .filter(|(_, deadline)| match deadline {
+            Some(deadline) => window.contains(*deadline, now),
+            None => window == Window::All,
+        })
+        .collect();
+    if activities.is_empty() {
+        return Page {
+            text: format!("<b>{}</b>\n\n没有需要完成的作业啦 🎉", window.title()),
+            keyboard: None,
+        };
+    }
+
+    let pages = activities.len().div_ceil(PAGE_SIZE);
+    let page = page.min(pages - 1);
+    let mut text = format!("<b>{}</b>", window.title());
+    if pages > 1 {
+        text.push_str(&format!("（第 {}/{} 页）", page + 1, pages));
+    }
+    text.push('\n');
+
+    for (i, (activity, deadline)) in activities
+        .iter()
+        .enumerate()
+        .skip(page * PAGE_SIZE)
+        .take(PAGE_SIZE)
+    {
+        text.push_str(&format!(
+            "\n{}. <b>{}</b>",
+            i + 1,
+            escape_html(&activity.activity_name)
+        ));
+        if let Some(course_name) = &activity.course_name {
+            text.push_str(&format!(" · {}", escape_html(course_name)));
+        }
+        let remaining = match deadline {
+            Some(deadline) if *deadline > now => {
+                format!(
+                    "⏳ 还剩 {}",
+                    format_remaining((*deadline - now).num_minutes())
+                )
+            }
+            Some(_) => "⌛ 已截止".to_string(),
+            None => "❓ 截止时间未知".to_string(),
+        };
+        text.push_str(&format!("\n    {}（{}）", remaining, activity.end_time));
+    }
+
+    let keyboard = (pages > 1).then(|| {
+        let button = |text: &str, page: usize| {
+            let callback_data = window.page_data(page);
+            json!({"text": text, "callback_data": callback_data})
+        };
+        let mut buttons = Vec::new();
+        if page > 0 {
+            buttons.push(button("« 上一页", page - 1));
+        }
+        if page + 1 < pages {
+            buttons.push(button("下一页 »", page + 1));
+        }
+        json!({ "inline_keyboard": [buttons] })
+    });
+    Page { text, keyboard }
+}
+
+/// Answers `/list` or `/due` with the first page of `user`'s assignments.
+pub async fn send(
+    bot: &Telegram,
+    window: Window,
+    user: &str,
+    db: &impl Database,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let activities = d1::get_open_activities(user, db).await?;
+    let page = render(&activities, window, 0, now);
+    match &page.keyboard {
+        Some(keyboard) => bot.send_message_with_keyboard(&page.text, keyboard).await,
+        None => bot.send_message(&page.text).await,
+    }
+}
+
+/// Replaces the message `message_id` with another page.
+pub async fn turn_page(
+    bot: &Telegram,
+    message_id: i64,
+    window: Window,
+    page: usize,
+    user: &str,
+    db: &impl Database,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    let activities = d1::get_open_activities(user, db).await?;
+    let page = render(&activities, window, page, now);
+    bot.edit_message_text(message_id, &page.text, page.keyboard.as_ref())
+        .await
+}
@@ -0,0 +1,152 @@
+use crate::api::{self, Api, Outcomes, Update};
+use crate::change::{self, Change};
+use crate::config::Config;
+use crate::d1;
+use crate::digest;
+use crate::error::{Error, Result};
+use crate::reminder;
+use crate::storage::{Database, KeyValue};
+use crate::ucloud;
+use chrono::Utc;
+use std::time::Duration;
+use tracing::{error, info};
+
+pub async fn push(config: &Config, db: &impl Database, kv: &impl KeyValue) -> Result<()> {
+    let user = config.user_id.as_str();
+    let ucloud = ucloud::UCloud::new(
+        config.username.clone(),
+        config.password.clone(),
+        config.api_url.clone(),
+    )
+    .with_timeout(Duration::from_secs(config.ucloud_timeout_secs));
+
+    let mut undone_list = ucloud.get_undone_list().await?;
+    let stored = d1::get_activities(&undone_list.undone_list, user, db).await?;
+    ucloud.fill_details(&mut undone_list, &stored).await?;
+    info!("undone_list: {:?}", undone_list);
+
+    let changes = change::detect_changes(&undone_list, &stored);
+    info!("changes: {:?}", changes);
+
+    let completed = d1::get_vanished_activities(&undone_list, user, db).await?;
+    info!("completed: {:?}", completed);
+
+    let notifiers = api::notifiers(config, kv).await?;
+    let sinks = api::configured_sinks(config);
+
+    // save to database, with changes and completions queued for every sink
+    // before the stored rows stop telling them apart, including sinks that
+    // could not be built this run
+    d1::queue_changes(&changes, &sinks, user, db).await?;
+    d1::queue_completions(&completed, &sinks, user, db).await?;
+    d1::save_activities_batch(&undone_list.undone_list, user, db).await?;
+    d1::mark_completed(&completed, user, db).await?;
+    for change in changes.iter().filter(|c| c.deadline_moved()) {
+        d1::clear_reminders(&change.item.activity_id, user, db).await?;
+    }
+
+    // every sink only sees what it has not received yet, so a failing sink
+    // is retried on the next run without duplicating the others
+    for notifier in &notifiers {
+        let pending = d1::filter_undelivered(&undone_list, notifier.name(), user, db).await?;
+        let update = Update {
+            undone: &undone_list,
+            new: &pending,
+        };
+
+        info!(
+            "pushing {} activities to {}",
+            pending.undone_list.len(),
+            notifier.name()
+        );
+        let ids = pending
+            .undone_list
+            .iter()
+            .map(|item| item.activity_id.as_str());
+        let outcomes = settle(ids, notifier.push(&update, db, kv).await, notifier.name());
+        d1::record_deliveries(&outcomes, notifier.name(), user, db).await?;
+
+        let changes: Vec<Change> = d1::get_pending_changes(notifier.name(), user, db)
+            .await?
+            .into_iter()
+            .filter_map(|pending| {
+                let item = undone_list
+                    .undone_list
+                    .iter()
+                    .find(|item| item.activity_id == pending.activity_id)?;
+                Some(Change {
+                    item: item.clone(),
+                    kinds: pending.kinds,
+                })
+            })
+            .collect();
+        if !changes.is_empty() {
+            let ids = changes.iter().map(|c| c.item.activity_id.as_str());
+            let result = notifier.push_changes(&changes, db, kv).await;
+            let outcomes = settle(ids, result, notifier.name());
+            d1::record_change_deliveries(&outcomes, notifier.name(), user, db).await?;
+        }
+
+        let completed = d1::get_pending_completions(notifier.name(), user, db).await?;
+        if !completed.is_empty() {
+            let ids = completed.iter().map(|record| record.activity_id.as_str());
+            let result = notifier.push_completed(&completed, db, kv).await;
+            let outcomes = settle(ids, result, notifier.name());
+            d1::record_completion_deliveries(&outcomes, notifier.name(), user, db).await?;
+        }
+    }
+
+    if let Some(telegram) = &config.telegram {
+        let bot = api::telegram::Telegram::from_config(telegram);
+        let now = Utc::now();
+        reminder::send_reminders(&undone_list, &bot, &config.reminder_offsets, user, db, now)
+            .await?;
+        digest::send_if_due(telegram, &bot, user, db, kv, now).await?;
+    }
+
+    Ok(())
+}
+
+/// How each of `ids` fared with `sink`: its own outcome, the error that stopped
+/// the whole batch, or delivered when the sink had nothing to send for it.
+fn settle<'a>(
+    ids: impl Iterator<Item = &'a str>,
+    result: Result<Outcomes>,
+    sink: &str,
+) -> Vec<(&'a str, std::result::Result<(), String>)> {
+    let mut reported = match result {
+        Ok(outcomes) => outcomes,
+        Err(e) => {
+            error!("{} push error: {:?}", sink, e);
+            let message = e.to_string();
+            return ids.map(|id| (id, Err(message.clone()))).collect();
+        }
+    };
+    ids.map(|id| {
+        let outcome = match reported.iter().position(|(reported, _)| reported == id) {
+            Some(i) => reported.swap_remove(i).1,
+            None => Ok(()),
+        };
+        let outcome = outcome.map_err(|e| {
+            error!("{} push error for {}: {:?}", sink, id, e);
+            e.to_string()
+        });
+        (id, outcome)
+    })
+    .collect()
+}
+
+/// Tells the Telegram chat that a run failed, if Telegram is configured.
+pub async fn report(config: &Config, failure: &Error) {
+    let Some(telegram) = &config.telegram else {
+        return;
+    };
+    let bot = api::telegram::Telegram::from_config(telegram);
+    let message = format!(
+        "<b>⚠️ 推送失败</b>\n\n{}",
+        api::telegram::escape_html(&failure.to_string())
+    );
+    if let Err(e) = bot.send_message(&message).await {
+        error!("could not report the failure to telegram: {:?}", e);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::api::telegram::{escape_html, Telegram};
+use crate::d1;
+use crate::error::{Error, Result};
+use crate::model::{UndoneList, UndoneListItem};
+use crate::storage::Database;
+use chrono::{DateTime, Utc};
+use tracing::{error, info};
+
+/// Parses offsets such as `"24h,3h,30m,1d"` into minutes, largest first.
+pub fn parse_offsets(spec: &str) -> Result<Vec<i64>> {
+    let mut offsets = Vec::new();
+    for part in spec.split(',').map(str::trim).filter(|p| !p.is_empty()) {
+        let invalid = || Error::config(format!("invalid reminder offset: {}", part));
+        let (number, unit) = match part.char_indices().last() {
+            Some((i, unit)) => (&part[..i], unit),
+            None => return Err(invalid()),
+        };
+        let number: i64 = number.trim().parse().map_err(|_| invalid())?;
+        let minutes = match unit {
+            'm' => Some(number),
+            'h' => number.checked_mul(60),
+            'd' => number.checked_mul(60 * 24),
+            _ => None,
+        }
+        .ok_or_else(invalid)?;
+        if minutes <= 0 {
+            return Err(invalid());
+        }
+        offsets.push(minutes);
+    }
+    offsets.sort_unstable_by(|a, b| b.cmp(a));
+    offsets.dedup();
+    Ok(offsets)
+}
+
+/// Sends one Telegram reminder per activity whose deadline has come within an
+/// offset that was not reminded about yet. When several offsets were crossed
+/// since the last run, only one message goes out and all of them are recorded.
+/// Activities marked done or snoozed from the keyboard are skipped, and an extra
+/// offset requested there is added to the configured ones.
+pub async fn send_reminders(
+    undone_list: &UndoneList,
+    bot: &Telegram,
+    offsets: &[i64],
+    user: &str,
+    db: &impl Database,
+    now: DateTime<Utc>,
+) -> Result<()> {
+    if undone_list.undone_list.is_empty() {
+        return Ok(());
+    }
+
+    let sent = d1::get_sent_reminders(&undone_list.undone_list, user, db).await?;
+    let preferences = d1::get_preferences(&undone_list.undone_list, user, db).await?;
+
+    for item in &undone_list.undone_list {
+        let preference = preferences.get(&item.activity_id);
+        if let Some(preference) = preference {
+            if preference.done_at.is_some() {
+                continue;
+            }
+            if preference
+                .snoozed_until
+                .is_some_and(|until| until > now.timestamp())
+            {
+                continue;
+            }
+        }
+
+        let Some(deadline) = item.deadline() else {
+            continue;
+        };
+        let remaining = (deadline.with_timezone(&Utc) - now).num_minutes();
+        if remaining <= 0 {
+            continue;
+        }
+
+        let due: Vec<i64> = offsets
+            .iter()
+            .copied()
+            .chain(preference.and_then(|p| p.remind_before))
+            .filter(|offset| remaining <= *offset)
+            .filter(|offset| !sent.contains(&(item.activity_id.clone(), *offset)))
+            .collect();
+        if due.is_empty() {
+            continue;
+        }
+
+        info!("reminding {} at T-{}m", item.activity_id, remaining);
+        let keyboard = bot.keyboard(&item.activity_id);
+        if let Err(e) = bot
+            .send_message_with_keyboard(&reminder_message(item, remaining), &keyboard)
+            .await
+        {
+            error!("reminder for {} failed: {:?}", item.activity_id, e);
+            continue;
+        }
+        d1::save_reminders(&item.activity_id, &due, user, db).await?;
+    }
+
+    Ok(())
+}
+
+fn reminder_message(item: &UndoneListItem, remaining: i64) -> String {
+    let mut msg = format!(
+        "<b>⏰ 作业还有 {} 就要截止啦！</b>\n\n",
+        format_remaining(remaining)
+    );
+    if let Some(course_info) = &item.course_info {
+        msg.push_str(&format!(
+            "<b>课程</b>：{}\n",
+            escape_html(&course_info.name)
+        ));
+    }
+    msg.push_str(&format!(
+        "<b>作业</b>：{}\n<b>结束时间</b>：{}",
+        escape_html(&item.activity_name),
+        item.end_time
+    ));
+    msg
+}
+
+/// Renders a duration in minutes the way the bot talks, e.g. `1 天 2 小时`.
+pub fn format_remaining(minutes: i64) -> String {
+    let days = minutes / (60 * 24);
+    let hours = minutes % (60 * 24) / 60;
+    let mins = minutes % 60;
+    if days > 0 {
+        if hours > 0 {
+            format!("{} 天 {} 小时", days, hours)
+        } else {
+            format!("{} 天", days)
+        }
+    } else if hours > 0 {
+        if mins > 0 {
+            format!("{} 小时 {} 分钟", hours, mins)
+        } else {
+            format!("{} 小时", hours)
+        }
+    } else {
+        format!("{} 分钟", mins)
+    }
+}
@@ -0,0 +1,120 @@
+use crate::error::{Error, Result};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+use worker::kv::KvStore;
+use worker::wasm_bindgen::JsValue;
+use worker::D1Database;
+
+/// A single SQL statement with its positional (`?1`, `?2`, ...) parameters.
+#[derive(Clone, Debug)]
+pub struct Statement {
+    pub sql: String,
+    pub params: Vec<Value>,
+}
+
+impl Statement {
+    pub fn new(sql: impl Into<String>, params: Vec<Value>) -> Self {
+        Self {
+            sql: sql.into(),
+            params,
+        }
+    }
+}
+
+/// SQLite-dialect database, backed by D1 in the worker and by plain SQLite in tests.
+pub trait Database {
+    #[allow(async_fn_in_trait)]
+    async fn query<T: DeserializeOwned>(&self, sql: &str, params: &[Value]) -> Result<Vec<T>>;
+
+    #[allow(async_fn_in_trait)]
+    async fn batch(&self, statements: Vec<Statement>) -> Result<()>;
+
+    #[allow(async_fn_in_trait)]
+    async fn exec(&self, sql: &str) -> Result<()>;
+}
+
+/// String key-value store, backed by Workers KV in the worker and by a map in tests.
+pub trait KeyValue {
+    #[allow(async_fn_in_trait)]
+    async fn get(&self, key: &str) -> Result<Option<String>>;
+
+    #[allow(async_fn_in_trait)]
+    async fn put(&self, key: &str, value: &str) -> Result<()>;
+
+    #[allow(async_fn_in_trait)]
+    async fn delete(&self, key: &str) -> Result<()>;
+}
+
+/// KV key of per-user state: `key` itself for [`crate::d1::OWNER`], suffixed
+/// with `:<user id>` for registered users.
+pub fn user_key(key: &str, user: &str) -> String {
+    if user == crate::d1::OWNER {
+        key.to_string()
+    } else {
+        format!("{}:{}", key, user)
+    }
+}
+
+fn to_js(value: &Value) -> JsValue {
+    match value {
+        Value::Null => JsValue::NULL,
+        Value::Bool(b) => JsValue::from_bool(*b),
+        Value::Number(n) => JsValue::from_f64(n.as_f64().unwrap_or_default()),
+        Value::String(s) => JsValue::from_str(s),
+        other => JsValue::from_str(&other.to_string()),
+    }
+}
+
+impl Database for D1Database {
+    async fn query<T: DeserializeOwned>(&self, sql: &str, params: &[Value]) -> Result<Vec<T>> {
+        let params: Vec<JsValue> = params.iter().map(to_js).collect();
+        let rows = self
+            .prepare(sql)
+            .bind(&params)?
+            .all()
+            .await?
+            .results::<T>()?;
+        Ok(rows)
+    }
+
+    async fn batch(&self, statements: Vec<Statement>) -> Result<()> {
+        if statements.is_empty() {
+            return Ok(());
+        }
+        let mut stmts = Vec::new();
+        for statement in statements {
+            let params: Vec<JsValue> = statement.params.iter().map(to_js).collect();
+            stmts.push(self.prepare(statement.sql).bind(&params)?);
+        }
+        D1Database::batch(self, stmts).await?;
+        Ok(())
+    }
+
+    async fn exec(&self, sql: &str) -> Result<()> {
+        D1Database::exec(self, sql).await?;
+        Ok(())
+    }
+}
+
+impl KeyValue for KvStore {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        KvStore::get(self, key)
+            .text()
+            .await
+            .map_err(|e| Error::storage(format!("kv get {} failed: {}", key, e)))
+    }
+
+    async fn put(&self, key: &str, value: &str) -> Result<()> {
+        KvStore::put(self, key, value)
+            .map_err(|e| Error::storage(format!("kv put {} failed: {}", key, e)))?
+            .execute()
+            .await
+            .map_err(|e| Error::storage(format!("kv put {} failed: {}", key, e)))
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        KvStore::delete(self, key)
+            .await
+            .map_err(|e| Error::storage(format!("kv delete {} failed: {}", key, e)))
+    }
+}
@@ -1,10 +1,32 @@
-use crate::model::{self, Detail, UndoneList};
-use anyhow::Result;
+use crate::d1::ActivityRecord;
+use crate::error::Result;
+use crate::model::{self, Attachment, Detail, ResourceUrl, UndoneList, UndoneListItem};
+use chrono::{NaiveDateTime, TimeDelta, Utc};
+use futures::stream::{self, StreamExt};
+use futures_timer::Delay;
+use serde::de::DeserializeOwned;
+use std::collections::HashMap;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Workers allow six simultaneous outgoing connections, more only queue up.
+const DETAIL_CONCURRENCY: usize = 6;
+const DEFAULT_TIMEOUT: Duration = Duration::from_secs(10);
+/// Transient failures are retried this many times in total, waiting
+/// `RETRY_BACKOFF`, then twice as long, and so on in between.
+const MAX_ATTEMPTS: u32 = 3;
+const RETRY_BACKOFF: Duration = Duration::from_millis(200);
+/// Stored details older than this are fetched again even if the deadline is
+/// unchanged, as the description and overtime setting can be edited on their own.
+const DETAILS_TTL_HOURS: i64 = 6;
+/// Format of SQLite's `CURRENT_TIMESTAMP`.
+const TIMESTAMP_FORMAT: &str = "%Y-%m-%d %H:%M:%S";
 
 pub struct UCloud {
     username: String,
     password: String,
     api_url: String,
+    timeout: Duration,
     client: reqwest::Client,
 }
 
@@ -14,38 +36,158 @@ impl UCloud {
             username,
             password,
             api_url,
+            timeout: DEFAULT_TIMEOUT,
             client: reqwest::Client::new(),
         }
     }
 
+    /// Limit for every single request, so one slow homework cannot use up the run.
+    pub fn with_timeout(mut self, timeout: Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// The undone list without details, see [`UCloud::fill_details`].
     pub async fn get_undone_list(&self) -> Result<model::UndoneList> {
-        let mut undone_list: UndoneList = self
-            .client
-            .get(&format!("{}/undoneList", self.api_url))
-            .basic_auth(&self.username, Some(&self.password))
-            .send()
-            .await?
-            .json()
-            .await?;
+        self.get(&format!("{}/undoneList", self.api_url)).await
+    }
 
+    /// Adds description, start time, overtime flag and attachments to every item. Items whose
+    /// stored copy in `known` has the same deadline and was fetched within
+    /// [`DETAILS_TTL_HOURS`] reuse it instead of fetching the detail again; the
+    /// rest are fetched a few at a time. Items whose detail
+    /// cannot be fetched are kept with `details_unavailable` set.
+    pub async fn fill_details(
+        &self,
+        undone_list: &mut UndoneList,
+        known: &[ActivityRecord],
+    ) -> Result<()> {
+        let known: HashMap<&str, &ActivityRecord> = known
+            .iter()
+            .map(|record| (record.activity_id.as_str(), record))
+            .collect();
+
+        let stale_before = Utc::now().naive_utc() - TimeDelta::hours(DETAILS_TTL_HOURS);
+        let mut missing = Vec::new();
         for item in &mut undone_list.undone_list {
-            let detail = self.get_detail(&item.activity_id).await?;
-            item.description = Some(detail.assignment_content);
-            item.start_time = Some(detail.assignment_begin_time);
-            item.is_overtime_commit = Some(detail.is_overtime_commit == 0)
+            match known.get(item.activity_id.as_str()) {
+                Some(record) if reuse(item, record, stale_before) => {}
+                _ => missing.push(item),
+            }
         }
-        Ok(undone_list)
+        info!(
+            "fetching {} of {} details",
+            missing.len(),
+            undone_list.undone_num
+        );
+
+        stream::iter(missing)
+            .for_each_concurrent(DETAIL_CONCURRENCY, |item| async move {
+                match self.get_detail(&item.activity_id).await {
+                    Ok(detail) => {
+                        item.details_fetched_at =
+                            Some(Utc::now().format(TIMESTAMP_FORMAT).to_string());
+                        item.attachments = Some(self.get_attachments(&detail).await);
+                        item.description = Some(detail.assignment_content);
+                        item.start_time = Some(detail.assignment_begin_time);
+                        item.is_overtime_commit = Some(detail.is_overtime_commit == 0);
+                    }
+                    Err(e) => {
+                        error!("detail of {} unavailable: {:?}", item.activity_id, e);
+                        item.details_unavailable = true;
+                    }
+                }
+            })
+            .await;
+        Ok(())
     }
 
     pub async fn get_detail(&self, id: &str) -> Result<Detail> {
-        let detail = self
+        self.get(&format!("{}/homework?id={}", self.api_url, id))
+            .await
+    }
+
+    /// Resolves the download URLs of the files attached to an assignment.
+    /// Attachments that cannot be resolved are left out.
+    pub async fn get_attachments(&self, detail: &Detail) -> Vec<Attachment> {
+        let mut attachments = Vec::new();
+        for resource in &detail.assignment_resource {
+            let url = format!(
+                "{}/resource?id={}",
+                self.api_url,
+                urlencoding::encode(&resource.resource_id)
+            );
+            match self.get::<ResourceUrl>(&url).await {
+                Ok(resource_url) => attachments.push(Attachment {
+                    name: resource.resource_name.clone(),
+                    url: resource_url.url,
+                }),
+                Err(e) => error!(
+                    "attachment {} of {} unavailable: {:?}",
+                    resource.resource_id, detail.id, e
+                ),
+            }
+        }
+        attachments
+    }
+
+    /// GETs `url`, retrying transient failures with exponential backoff.
+    async fn get<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        let mut attempt = 1;
+        loop {
+            match self.try_get(url).await {
+                Err(e) if e.is_transient() && attempt < MAX_ATTEMPTS => {
+                    let backoff = RETRY_BACKOFF * 2u32.pow(attempt - 1);
+                    info!("retrying {} in {:?} after {}", url, backoff, e);
+                    Delay::new(backoff).await;
+                    attempt += 1;
+                }
+                result => return result,
+            }
+        }
+    }
+
+    async fn try_get<T: DeserializeOwned>(&self, url: &str) -> Result<T> {
+        Ok(self
             .client
-            .get(&format!("{}/homework?id={}", self.api_url, id))
+            .get(url)
             .basic_auth(&self.username, Some(&self.password))
+            .timeout(self.timeout)
             .send()
             .await?
+            .error_for_status()?
             .json()
-            .await?;
-        Ok(detail)
+            .await?)
+    }
+}
+
+/// Copies the stored details onto `item` if they are complete and still current.
+fn reuse(item: &mut UndoneListItem, record: &ActivityRecord, stale_before: NaiveDateTime) -> bool {
+    if record.end_time != item.end_time {
+        return false;
+    }
+    let fetched_at = record
+        .details_fetched_at
+        .as_deref()
+        .and_then(|time| NaiveDateTime::parse_from_str(time, TIMESTAMP_FORMAT).ok());
+    if fetched_at.is_none_or(|time| time < stale_before) {
+        return false;
     }
+    let (Some(description), Some(start_time), Some(is_overtime_commit), Some(attachments)) = (
+        &record.description,
+        record.start_time.as_ref().filter(|s| !s.is_empty()),
+        record.is_overtime_commit,
+        record
+            .attachments
+            .as_deref()
+            .and_then(|json| serde_json::from_str(json).ok()),
+    ) else {
+        return false;
+    };
+    item.description = Some(description.clone());
+    item.start_time = Some(start_time.clone());
+    item.is_overtime_commit = Some(is_overtime_commit != 0);
+    item.attachments = Some(attachments);
+    item.details_fetched_at = record.details_fetched_at.clone();
+    true
 }
@@ -0,0 +1,154 @@
+use crate::api::telegram::{escape_html, Telegram};
+use crate::config::Config;
+use crate::crypto::{self, Cipher};
+use crate::d1::{self, User};
+use crate::error::{Error, Result};
+use crate::pipeline;
+use crate::storage::{Database, KeyValue};
+use crate::ucloud::UCloud;
+use serde_json::Value;
+use std::time::Duration;
+use tracing::{error, info};
+
+/// Env var listing the Telegram user ids that may `/register`, comma separated,
+/// or `*` for everyone. Registration is closed while it is unset.
+pub const REGISTRATION_VAR: &str = "TELEGRAM_REGISTRATION_USER_IDS";
+
+/// Whether `sender` is on the registration list.
+pub fn may_register(allowed: Option<&str>, sender: i64) -> bool {
+    allowed.is_some_and(|allowed| {
+        allowed
+            .split(',')
+            .map(str::trim)
+            .any(|id| id == "*" || id.parse() == Ok(sender))
+    })
+}
+
+/// The config to serve `sender` with: `config` itself for the owner, their own
+/// account for registered users and none for everyone else.
+pub async fn config_for(
+    config: &Config,
+    sender: i64,
+    owner: i64,
+    db: &impl Database,
+) -> Result<Option<Config>> {
+    if sender == owner {
+        return Ok(Some(config.clone()));
+    }
+    let Some(user) = d1::get_user(&sender.to_string(), db).await? else {
+        return Ok(None);
+    };
+    let password = password(config, &user, db).await?;
+    Ok(Some(config.for_user(&user, password)))
+}
+
+/// Handles the `/register <username> <password>` in `message`, with `bot`
+/// answering in its chat. The message is deleted right away since it carries
+/// the password, then the account is checked against UCloud before it is stored.
+pub async fn register(
+    config: &Config,
+    bot: &Telegram,
+    message: &Value,
+    argument: &str,
+    db: &impl Database,
+) -> Result<()> {
+    let (Some(sender), Some(chat_id)) = (
+        message["from"]["id"].as_i64(),
+        message["chat"]["id"].as_i64(),
+    ) else {
+        return Err(Error::bad_request("message without sender or chat"));
+    };
+    if let Some(message_id) = message["message_id"].as_i64() {
+        if let Err(e) = bot.delete_message(message_id).await {
+            error!("could not delete the registration message: {:?}", e);
+        }
+    }
+
+    let mut parts = argument.split_whitespace();
+    let (Some(username), Some(password), None) = (parts.next(), parts.next(), parts.next()) else {
+        return bot
+            .send_message("用法：/register 用户名 密码\n消息发送后会立即删除")
+            .await;
+    };
+
+    let ucloud = UCloud::new(
+        username.to_string(),
+        password.to_string(),
+        config.api_url.clone(),
+    )
+    .with_timeout(Duration::from_secs(config.ucloud_timeout_secs));
+    if let Err(e) = ucloud.get_undone_list().await {
+        info!("registration of {} failed: {:?}", sender, e);
+        return bot
+            .send_message(&format!(
+                "登录 UCloud 失败，请检查用户名和密码\n\n{}",
+                escape_html(&e.to_string())
+            ))
+            .await;
+    }
+
+    d1::save_user(
+        &User {
+            user_id: sender.to_string(),
+            chat_id: chat_id.to_string(),
+            ucloud_username: username.to_string(),
+            ucloud_password: require(config)?.encrypt(password)?,
+        },
+        db,
+    )
+    .await?;
+    info!("registered user {}", sender);
+    bot.send_message("注册成功啦！之后的作业会推送到这里，发送 /unregister 可以注销")
+        .await
+}
+
+/// Pushes the owner's homework, then every registered user's. A failing user is
+/// told about it in their own chat and does not hold up the others.
+pub async fn push_all(config: &Config, db: &impl Database, kv: &impl KeyValue) -> Result<()> {
+    if let Err(e) = pipeline::push(config, db, kv).await {
+        error!("push error: {:?}", e);
+        pipeline::report(config, &e).await;
+    }
+
+    if config.telegram.is_none() {
+        return Ok(());
+    }
+    for user in d1::get_users(db).await? {
+        let user_config = match password(config, &user, db).await {
+            Ok(password) => config.for_user(&user, password),
+            Err(e) => {
+                error!("credentials of user {} unusable: {:?}", user.user_id, e);
+                pipeline::report(&config.for_user(&user, String::new()), &e).await;
+                continue;
+            }
+        };
+        if let Err(e) = pipeline::push(&user_config, db, kv).await {
+            error!("push error for user {}: {:?}", user.user_id, e);
+            pipeline::report(&user_config, &e).await;
+        }
+    }
+    Ok(())
+}
+
+/// Decrypts `user`'s password, storing it again under the current key if it was
+/// encrypted with a previous one.
+async fn password(config: &Config, user: &User, db: &impl Database) -> Result<String> {
+    let cipher = require(config)?;
+    let password = cipher.decrypt(&user.ucloud_password)?;
+    if !cipher.is_current(&user.ucloud_password) {
+        info!("re-encrypting the credentials of user {}", user.user_id);
+        let user = User {
+            ucloud_password: cipher.encrypt(&password)?,
+            ..user.clone()
+        };
+        d1::save_user(&user, db).await?;
+    }
+    Ok(password)
+}
+
+fn require(config: &Config) -> Result<&Cipher> {
+    config
+        .cipher
+        .as_ref()
+        .ok_or_else(|| Error::config(format!("{} is not set", crypto::KEY_SECRET)))
+}
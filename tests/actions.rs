@@ -0,0 +1,170 @@
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::{undone_item, MemoryKv, Servers, SqliteDatabase, TELEGRAM_TOKEN};
+use serde_json::json;
+use ucloud_push::actions::{handle_callback, Action};
+use ucloud_push::api::telegram::Telegram;
+use ucloud_push::error::Error;
+use ucloud_push::model::UndoneList;
+use ucloud_push::{d1, pipeline, reminder};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn at(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+}
+
+fn undone_list() -> UndoneList {
+    serde_json::from_value(json!({
+        "siteNum": 1,
+        "undoneNum": 1,
+        "undoneList": [undone_item("a1", "Lab 1", "2025-03-08 23:59:00")],
+    }))
+    .unwrap()
+}
+
+async fn press(servers: &Servers, db: &SqliteDatabase, action: Action, now: &str) {
+    let bot = Telegram::from_config(servers.config().telegram.as_ref().unwrap());
+    let query = json!({
+        "id": "query-1",
+        "from": {"id": 1},
+        "data": action.callback_data("a1"),
+    });
+    handle_callback(&query, &bot, d1::OWNER, db, at(now))
+        .await
+        .unwrap();
+}
+
+async fn remind(servers: &Servers, db: &SqliteDatabase, offsets: &[i64], now: &str) -> usize {
+    let bot = Telegram::from_config(servers.config().telegram.as_ref().unwrap());
+    reminder::send_reminders(&undone_list(), &bot, offsets, d1::OWNER, db, at(now))
+        .await
+        .unwrap();
+    servers.telegram_messages().await.len()
+}
+
+#[tokio::test]
+async fn pushed_assignments_carry_the_action_keyboard() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let mut config = servers.config();
+    config.telegram.as_mut().unwrap().open_url = Some("https://ucloud.example/{id}".to_string());
+
+    pipeline::push(&config, &SqliteDatabase::new(), &MemoryKv::default())
+        .await
+        .unwrap();
+
+    let messages = servers.telegram_messages().await;
+    let pushed = messages
+        .iter()
+        .find(|m| m["text"].as_str().unwrap().contains("Lab 1"))
+        .unwrap();
+    let keyboard = &pushed["reply_markup"]["inline_keyboard"];
+    assert_eq!(keyboard[0][0]["callback_data"], "done:a1");
+    assert_eq!(keyboard[0][1]["callback_data"], "snooze:a1");
+    assert_eq!(keyboard[1][0]["url"], "https://ucloud.example/a1");
+    assert_eq!(keyboard[1][1]["callback_data"], "remind:a1");
+}
+
+#[tokio::test]
+async fn done_assignments_are_not_reminded() {
+    let servers = Servers::start().await;
+    let db = SqliteDatabase::new();
+    d1::save_activities_batch(&undone_list().undone_list, d1::OWNER, &db)
+        .await
+        .unwrap();
+
+    press(&servers, &db, Action::Done, "2025-03-07T00:00:00Z").await;
+
+    let answers = Servers::bodies(
+        &servers.telegram,
+        &format!("/bot{}/answerCallbackQuery", TELEGRAM_TOKEN),
+    )
+    .await;
+    assert_eq!(answers[0]["callback_query_id"], "query-1");
+    assert!(answers[0]["text"].as_str().unwrap().contains("已标记完成"));
+    assert_eq!(
+        remind(&servers, &db, &[1440, 60], "2025-03-08T15:30:00Z").await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn snoozed_assignments_are_reminded_a_day_later() {
+    let servers = Servers::start().await;
+    let db = SqliteDatabase::new();
+
+    press(&servers, &db, Action::Snooze, "2025-03-07T12:00:00Z").await;
+
+    assert_eq!(
+        remind(&servers, &db, &[1440], "2025-03-08T00:00:00Z").await,
+        0
+    );
+    assert_eq!(
+        remind(&servers, &db, &[1440], "2025-03-08T12:01:00Z").await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn remind_before_adds_an_offset() {
+    let servers = Servers::start().await;
+    let db = SqliteDatabase::new();
+
+    press(&servers, &db, Action::RemindBefore, "2025-03-07T12:00:00Z").await;
+
+    // deadline is 15:59 UTC, so 14:00 is within two hours but not one
+    assert_eq!(
+        remind(&servers, &db, &[60], "2025-03-08T14:00:00Z").await,
+        1
+    );
+    let sent = db.query_json("SELECT offset_minutes FROM reminders");
+    assert_eq!(sent[0]["offset_minutes"], 120);
+    assert!(servers.telegram_messages().await[0]["reply_markup"].is_object());
+}
+
+#[tokio::test]
+async fn rejected_callback_answers_reach_the_caller() {
+    let servers = Servers::start().await;
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{}/answerCallbackQuery", TELEGRAM_TOKEN)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(
+            json!({"ok": false, "error_code": 400, "description": "Bad Request: query is too old"}),
+        ))
+        .with_priority(1)
+        .mount(&servers.telegram)
+        .await;
+    let bot = Telegram::from_config(servers.config().telegram.as_ref().unwrap());
+    let query = json!({
+        "id": "query-1",
+        "from": {"id": 1},
+        "data": Action::Done.callback_data("a1"),
+    });
+
+    let result = handle_callback(
+        &query,
+        &bot,
+        d1::OWNER,
+        &SqliteDatabase::new(),
+        at("2025-03-07T00:00:00Z"),
+    )
+    .await;
+
+    assert!(matches!(
+        result,
+        Err(Error::Upstream {
+            status: Some(400),
+            ..
+        })
+    ));
+}
+
+#[test]
+fn parses_callback_data() {
+    assert_eq!(Action::parse("snooze:a1"), Some((Action::Snooze, "a1")));
+    assert_eq!(Action::parse("done"), None);
+    assert_eq!(Action::parse("unknown:a1"), None);
+}
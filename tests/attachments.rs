@@ -0,0 +1,190 @@
+mod common;
+
+use common::{detail, undone_item, MemoryKv, Servers, SqliteDatabase};
+use serde_json::{json, Value};
+use ucloud_push::pipeline;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, ResponseTemplate};
+
+/// Serves one homework with the given `(resource id, file name)` attachments,
+/// each resolving to a file on the UCloud mock.
+async fn serve_attachments(servers: &Servers, files: &[(&str, &str)]) {
+    let mut homework = detail("a1");
+    homework["assignmentResource"] = files
+        .iter()
+        .map(|(id, name)| json!({"resourceId": id, "resourceName": name, "resourceType": "file"}))
+        .collect::<Value>();
+    servers
+        .serve(
+            &[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")],
+            &[homework],
+        )
+        .await;
+
+    for (id, name) in files {
+        Mock::given(method("GET"))
+            .and(path("/resource"))
+            .and(query_param("id", *id))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "url": format!("{}/files/{}", servers.ucloud.uri(), name),
+            })))
+            .mount(&servers.ucloud)
+            .await;
+        Mock::given(method("GET"))
+            .and(path(format!("/files/{}", name)))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_string(format!("contents of {}", name)),
+            )
+            .mount(&servers.ucloud)
+            .await;
+    }
+}
+
+#[tokio::test]
+async fn forwards_attachments_to_telegram_and_links_them_in_ticktick() {
+    let servers = Servers::start().await;
+    serve_attachments(&servers, &[("r1", "lab1.pdf"), ("r2", "data.zip")]).await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let messages = servers.telegram_messages().await;
+    let text = messages[0]["text"].as_str().unwrap();
+    assert!(text.contains(&format!(
+        "<a href=\"{}/files/lab1.pdf\">lab1.pdf</a>",
+        servers.ucloud.uri()
+    )));
+
+    let groups = servers.telegram_uploads("sendMediaGroup").await;
+    assert_eq!(groups.len(), 1);
+    assert!(groups[0].contains("attach://file1"));
+    assert!(groups[0].contains("contents of lab1.pdf"));
+    assert!(groups[0].contains("contents of data.zip"));
+
+    let tasks = servers.ticktick_tasks().await;
+    let content = tasks[0]["content"].as_str().unwrap();
+    assert!(content.contains(&format!(
+        "[data.zip]({}/files/data.zip)",
+        servers.ucloud.uri()
+    )));
+}
+
+#[tokio::test]
+async fn skips_attachments_that_cannot_be_resolved() {
+    let servers = Servers::start().await;
+    serve_attachments(&servers, &[("r1", "lab1.pdf")]).await;
+    // r2 has no resource mock, so resolving it fails
+    let mut homework = detail("a1");
+    homework["assignmentResource"] = json!([
+        {"resourceId": "r1", "resourceName": "lab1.pdf", "resourceType": "file"},
+        {"resourceId": "r2", "resourceName": "missing.pdf", "resourceType": "file"},
+    ]);
+    Mock::given(method("GET"))
+        .and(path("/homework"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(homework))
+        .with_priority(1)
+        .mount(&servers.ucloud)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let documents = servers.telegram_uploads("sendDocument").await;
+    assert_eq!(documents.len(), 1);
+    assert!(documents[0].contains("filename=\"lab1.pdf\""));
+    let text = servers.telegram_messages().await[0]["text"]
+        .as_str()
+        .unwrap()
+        .to_string();
+    assert!(!text.contains("missing.pdf"));
+
+    // attachments are stored with the other details and not resolved again
+    let rows = db.query_json("SELECT attachments FROM activities");
+    assert!(rows[0]["attachments"]
+        .as_str()
+        .unwrap()
+        .contains("lab1.pdf"));
+    let resolved = servers
+        .ucloud
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.url.path() == "/resource")
+        .count();
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+    let resolved_again = servers
+        .ucloud
+        .received_requests()
+        .await
+        .unwrap()
+        .iter()
+        .filter(|r| r.url.path() == "/resource")
+        .count();
+    assert_eq!(resolved, resolved_again);
+}
+
+#[tokio::test]
+async fn rejected_uploads_do_not_repeat_the_announcement() {
+    let servers = Servers::start().await;
+    serve_attachments(&servers, &[("r1", "lab1.pdf")]).await;
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{}/sendDocument", common::TELEGRAM_TOKEN)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ok": false,
+            "error_code": 413,
+            "description": "Request Entity Too Large",
+        })))
+        .with_priority(1)
+        .mount(&servers.telegram)
+        .await;
+    let mut config = servers.config();
+    config.ticktick = None;
+    config.reminder_offsets = Vec::new();
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    // the message links the file, so it is not sent again for the upload
+    let deliveries = db.query_json("SELECT status FROM deliveries WHERE sink = 'telegram'");
+    assert_eq!(deliveries[0]["status"], "delivered");
+    assert_eq!(servers.telegram_messages().await.len(), 1);
+    assert_eq!(servers.telegram_uploads("sendDocument").await.len(), 1);
+}
+
+#[tokio::test]
+async fn large_attachments_are_uploaded_one_at_a_time() {
+    let servers = Servers::start().await;
+    serve_attachments(
+        &servers,
+        &[("r1", "a.zip"), ("r2", "b.zip"), ("r3", "c.pdf")],
+    )
+    .await;
+    for name in ["a.zip", "b.zip"] {
+        Mock::given(method("GET"))
+            .and(path(format!("/files/{}", name)))
+            .respond_with(ResponseTemplate::new(200).set_body_bytes(vec![b'x'; 12 << 20]))
+            .with_priority(1)
+            .mount(&servers.ucloud)
+            .await;
+    }
+    let mut config = servers.config();
+    config.ticktick = None;
+
+    pipeline::push(&config, &SqliteDatabase::new(), &MemoryKv::default())
+        .await
+        .unwrap();
+
+    // a.zip and b.zip do not fit in one album together, c.pdf joins b.zip
+    let documents = servers.telegram_uploads("sendDocument").await;
+    assert_eq!(documents.len(), 1);
+    assert!(documents[0].contains("filename=\"a.zip\""));
+    let groups = servers.telegram_uploads("sendMediaGroup").await;
+    assert_eq!(groups.len(), 1);
+    assert!(groups[0].contains("filename=\"b.zip\""));
+    assert!(groups[0].contains("contents of c.pdf"));
+}
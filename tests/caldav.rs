@@ -0,0 +1,115 @@
+mod common;
+
+use common::{detail, undone_item, MemoryKv, Servers, SqliteDatabase};
+use serde_json::json;
+use ucloud_push::config::Config;
+use ucloud_push::pipeline;
+use wiremock::matchers::method;
+use wiremock::{Mock, ResponseTemplate};
+
+/// The test config with CalDAV as the only task list.
+fn config(servers: &Servers) -> Config {
+    Config {
+        ticktick: None,
+        lark: None,
+        ..servers.config()
+    }
+}
+
+/// Unfolded content lines.
+fn lines(document: &str) -> Vec<String> {
+    document
+        .replace("\r\n ", "")
+        .split("\r\n")
+        .map(str::to_string)
+        .collect()
+}
+
+#[tokio::test]
+async fn changes_and_completion_replace_the_same_resource() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let config = config(&servers);
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let requests = servers.caldav.received_requests().await.unwrap();
+    assert_eq!(requests.len(), 1);
+    assert_eq!(
+        requests[0].headers["authorization"],
+        "Basic c3R1ZGVudDpkYXYtc2VjcmV0"
+    );
+    assert_eq!(
+        requests[0].headers["content-type"],
+        "text/calendar; charset=utf-8"
+    );
+    let puts = servers.caldav_puts().await;
+    assert_eq!(puts[0].0, "a1.ics");
+    let created = lines(&puts[0].1);
+    let has = |lines: &[String], line: &str| lines.iter().any(|l| l == line);
+    assert!(has(&created, "BEGIN:VTODO"));
+    assert!(has(&created, "UID:a1@ucloud-push"));
+    assert!(has(&created, "SUMMARY:Lab 1"));
+    assert!(has(&created, "DUE:20250308T155900Z"));
+    assert!(has(&created, "STATUS:NEEDS-ACTION"));
+    assert!(has(&created, "CATEGORIES:Operating Systems"));
+    // a resource must not carry a METHOD
+    assert!(!puts[0].1.contains("METHOD"));
+
+    servers.ucloud.reset().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-10 23:59:00")])
+        .await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    servers.ucloud.reset().await;
+    servers.serve(&[], &[detail("a1")]).await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let puts = servers.caldav_puts().await;
+    assert_eq!(puts.len(), 3);
+    assert!(puts.iter().all(|(name, _)| name == "a1.ics"));
+    let changed = lines(&puts[1].1);
+    assert!(has(&changed, "DUE:20250310T155900Z"));
+    assert!(has(&changed, "STATUS:NEEDS-ACTION"));
+    let completed = lines(&puts[2].1);
+    assert!(has(&completed, "UID:a1@ucloud-push"));
+    assert!(has(&completed, "DUE:20250310T155900Z"));
+    assert!(has(&completed, "STATUS:COMPLETED"));
+    assert!(has(&completed, "PERCENT-COMPLETE:100"));
+    assert!(completed.iter().any(|l| l.starts_with("COMPLETED:")));
+    assert!(has(&completed, "CATEGORIES:Operating Systems"));
+}
+
+#[tokio::test]
+async fn rejected_puts_are_retried() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    Mock::given(method("PUT"))
+        .respond_with(ResponseTemplate::new(507))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&servers.caldav)
+        .await;
+    let config = config(&servers);
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+    let failed = db.query_json("SELECT status, last_error FROM deliveries WHERE sink = 'caldav'");
+    assert_eq!(failed[0]["status"], "failed");
+    assert!(failed[0]["last_error"].as_str().unwrap().contains("507"));
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+    let delivered = db.query_json("SELECT status FROM deliveries WHERE sink = 'caldav'");
+    assert_eq!(delivered, [json!({"status": "delivered"})]);
+    assert_eq!(servers.caldav_puts().await.len(), 2);
+    // the other sinks are not held back
+    assert_eq!(servers.telegram_messages().await.len(), 1);
+}
@@ -0,0 +1,177 @@
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::{undone_item, MemoryKv, SqliteDatabase};
+use ucloud_push::calendar::{self, Component, TOKEN_KEY};
+use ucloud_push::crypto::Cipher;
+use ucloud_push::d1::{self, OpenActivity};
+use ucloud_push::model::UndoneListItem;
+
+const KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+
+fn at(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+}
+
+fn activity(id: &str, start_time: &str, end_time: &str) -> OpenActivity {
+    OpenActivity {
+        activity_id: id.to_string(),
+        activity_name: "Lab 1; part A, B".to_string(),
+        start_time: start_time.to_string(),
+        end_time: end_time.to_string(),
+        course_name: Some("Compilers, 2025".to_string()),
+        description: Some("<p>Write a <b>parser</b>.</p><p>Submit as PDF.</p>".to_string()),
+        pushed_at: None,
+    }
+}
+
+/// Unfolded content lines.
+fn lines(document: &str) -> Vec<String> {
+    document
+        .replace("\r\n ", "")
+        .split("\r\n")
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn renders_events_from_start_to_deadline() {
+    let document = calendar::render(
+        &[
+            activity("a1", "2025-03-01 08:00", "2025-03-08 23:59:00"),
+            activity("a2", "", "2025-03-09 12:00:00"),
+        ],
+        Component::Event,
+        at("2025-03-05T00:00:00Z"),
+    );
+    assert!(document.starts_with("BEGIN:VCALENDAR\r\n"));
+    assert!(document.ends_with("END:VCALENDAR\r\n"));
+
+    let lines = lines(&document);
+    let has = |line: &str| lines.iter().any(|l| l == line);
+    assert!(has("UID:a1@ucloud-push"));
+    assert!(has("DTSTAMP:20250305T000000Z"));
+    // Beijing time in UTC
+    assert!(has("DTSTART:20250301T000000Z"));
+    assert!(has("DTEND:20250308T155900Z"));
+    assert!(has("SUMMARY:Lab 1\\; part A\\, B"));
+    assert!(has("CATEGORIES:Compilers\\, 2025"));
+    assert!(has("DESCRIPTION:Write a **parser**.\\n\\nSubmit as PDF."));
+    // without a start time the event sits at the deadline
+    assert!(has("DTSTART:20250309T040000Z"));
+    assert!(has("DTEND:20250309T040000Z"));
+    assert_eq!(lines.iter().filter(|l| *l == "BEGIN:VEVENT").count(), 2);
+    assert!(!document.contains("VTODO"));
+}
+
+#[test]
+fn renders_todos_due_at_the_deadline() {
+    let document = calendar::render(
+        &[activity("a1", "2025-03-01 08:00", "2025-03-08 23:59:00")],
+        Component::Todo,
+        at("2025-03-05T00:00:00Z"),
+    );
+    let lines = lines(&document);
+    let has = |line: &str| lines.iter().any(|l| l == line);
+    assert!(has("BEGIN:VTODO"));
+    assert!(has("DTSTART:20250301T000000Z"));
+    assert!(has("DUE:20250308T155900Z"));
+    assert!(!document.contains("VEVENT") && !document.contains("DTEND"));
+
+    assert_eq!(Component::parse(None), Some(Component::Event));
+    assert_eq!(Component::parse(Some("todo")), Some(Component::Todo));
+    assert_eq!(Component::parse(Some("journal")), None);
+}
+
+#[test]
+fn folds_long_lines_without_splitting_characters() {
+    let mut long = activity("a1", "", "2025-03-08 23:59:00");
+    long.activity_name = "很长的作业标题".repeat(10);
+    let document = calendar::render(&[long], Component::Event, at("2025-03-05T00:00:00Z"));
+
+    for line in document.split("\r\n") {
+        assert!(line.len() <= 75, "{} octets: {}", line.len(), line);
+    }
+    assert!(lines(&document).contains(&format!("SUMMARY:{}", "很长的作业标题".repeat(10))));
+}
+
+#[tokio::test]
+async fn feed_serves_only_the_token_owners_open_assignments() {
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+    let item = |id, name| -> UndoneListItem {
+        serde_json::from_value(undone_item(id, name, "2025-03-08 23:59:00")).unwrap()
+    };
+    d1::save_activities_batch(&[item("a1", "Mine"), item("a2", "Done")], d1::OWNER, &db)
+        .await
+        .unwrap();
+    d1::save_activities_batch(&[item("b1", "Theirs")], "7", &db)
+        .await
+        .unwrap();
+    d1::mark_done("a2", d1::OWNER, &db).await.unwrap();
+
+    let token = calendar::token(d1::OWNER, false, None, &kv).await.unwrap();
+    assert_eq!(token.len(), 32);
+    assert_eq!(
+        calendar::token(d1::OWNER, false, None, &kv).await.unwrap(),
+        token
+    );
+
+    let now = at("2025-03-05T00:00:00Z");
+    let feed = calendar::feed(&token, Component::Event, &db, &kv, now)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(feed.contains("SUMMARY:Mine"));
+    assert!(!feed.contains("SUMMARY:Done"));
+    assert!(!feed.contains("SUMMARY:Theirs"));
+
+    let other = calendar::token("7", false, None, &kv).await.unwrap();
+    assert_ne!(other, token);
+    let feed = calendar::feed(&other, Component::Event, &db, &kv, now)
+        .await
+        .unwrap()
+        .unwrap();
+    assert!(feed.contains("SUMMARY:Theirs") && !feed.contains("SUMMARY:Mine"));
+
+    assert!(calendar::feed("guess", Component::Event, &db, &kv, now)
+        .await
+        .unwrap()
+        .is_none());
+    // tokens are never part of a key
+    assert!(kv.keys().iter().all(|key| !key.contains(&token)));
+}
+
+#[tokio::test]
+async fn reset_and_revoke_stop_the_old_feed() {
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+    let now = at("2025-03-05T00:00:00Z");
+    let cipher = Cipher::new(KEY).unwrap();
+
+    let old = calendar::token("7", false, Some(&cipher), &kv)
+        .await
+        .unwrap();
+    let stored = kv.value(&format!("{}:7", TOKEN_KEY)).unwrap();
+    assert!(Cipher::is_encrypted(&stored) && !stored.contains(&old));
+
+    let new = calendar::token("7", true, Some(&cipher), &kv)
+        .await
+        .unwrap();
+    assert_ne!(new, old);
+    assert!(calendar::feed(&old, Component::Event, &db, &kv, now)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(calendar::feed(&new, Component::Event, &db, &kv, now)
+        .await
+        .unwrap()
+        .is_some());
+
+    calendar::revoke("7", Some(&cipher), &kv).await.unwrap();
+    assert!(calendar::feed(&new, Component::Event, &db, &kv, now)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(kv.keys().is_empty());
+}
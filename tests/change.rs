@@ -0,0 +1,107 @@
+use serde_json::json;
+use ucloud_push::change::{detect_changes, merge, ChangeKind};
+use ucloud_push::d1::ActivityRecord;
+use ucloud_push::model::UndoneList;
+
+fn undone_list(end_time: &str, description: Option<&str>) -> UndoneList {
+    serde_json::from_value(json!({
+        "siteNum": 1,
+        "undoneNum": 1,
+        "undoneList": [{
+            "siteId": 1,
+            "siteName": "Site",
+            "activityName": "Lab 1",
+            "activityId": "a1",
+            "type": 4,
+            "endTime": end_time,
+            "assignmentType": 0,
+            "evaluationStatus": 0,
+            "isOpenEvaluation": 0,
+            "courseInfo": null,
+            "description": description,
+            "isOvertimeCommit": true,
+        }],
+    }))
+    .unwrap()
+}
+
+fn record(description: Option<&str>, is_overtime_commit: Option<i32>) -> ActivityRecord {
+    ActivityRecord {
+        activity_id: "a1".to_string(),
+        activity_name: "Lab 1".to_string(),
+        end_time: "2025-03-08 23:59:00".to_string(),
+        start_time: Some("2025-03-01 08:00".to_string()),
+        description: description.map(str::to_string),
+        is_overtime_commit,
+        attachments: Some("[]".to_string()),
+        course_name: None,
+        details_fetched_at: None,
+    }
+}
+
+#[test]
+fn unknown_activities_are_not_changes() {
+    let list = undone_list("2025-03-10 23:59:00", Some("new"));
+    assert!(detect_changes(&list, &[]).is_empty());
+}
+
+#[test]
+fn detects_each_changed_field() {
+    let list = undone_list("2025-03-10 23:59:00", Some("new"));
+    let changes = detect_changes(&list, &[record(Some("old"), Some(0))]);
+
+    assert_eq!(
+        changes[0].kinds,
+        [
+            ChangeKind::Deadline {
+                from: "2025-03-08 23:59:00".to_string(),
+                to: "2025-03-10 23:59:00".to_string(),
+            },
+            ChangeKind::Description,
+            ChangeKind::OvertimeCommit {
+                from: false,
+                to: true
+            },
+        ]
+    );
+    assert!(changes[0].deadline_moved());
+}
+
+#[test]
+fn missing_values_are_not_changes() {
+    let list = undone_list("2025-03-08 23:59:00", None);
+    assert!(detect_changes(&list, &[record(Some("old"), None)]).is_empty());
+
+    let list = undone_list("2025-03-08 23:59:00", Some("new"));
+    assert!(detect_changes(&list, &[record(Some(""), None)]).is_empty());
+}
+
+#[test]
+fn merged_changes_span_from_the_first_to_the_last_value() {
+    let deadline = |from: &str, to: &str| ChangeKind::Deadline {
+        from: from.to_string(),
+        to: to.to_string(),
+    };
+    let older = [
+        deadline("03-08", "03-10"),
+        ChangeKind::OvertimeCommit {
+            from: false,
+            to: true,
+        },
+    ];
+    let newer = [
+        deadline("03-10", "03-12"),
+        ChangeKind::Description,
+        ChangeKind::OvertimeCommit {
+            from: true,
+            to: false,
+        },
+    ];
+
+    assert_eq!(
+        merge(&older, &newer),
+        [deadline("03-08", "03-12"), ChangeKind::Description]
+    );
+    assert!(merge(&[deadline("03-08", "03-10")], &[deadline("03-10", "03-08")]).is_empty());
+    assert_eq!(merge(&[], &older), older);
+}
@@ -0,0 +1,460 @@
+#![allow(dead_code)]
+
+use rusqlite::types::{Value as SqlValue, ValueRef};
+use rusqlite::{params_from_iter, Connection};
+use serde::de::DeserializeOwned;
+use serde_json::{json, Map, Value};
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Mutex;
+use ucloud_push::config::{
+    CalDavConfig, Config, CourseMapping, LarkConfig, TelegramConfig, TickTickConfig, WebhookConfig,
+};
+use ucloud_push::d1;
+use ucloud_push::error::{Error, Result};
+use ucloud_push::storage::{Database, KeyValue, Statement};
+use wiremock::matchers::{method, path, path_regex, query_param};
+use wiremock::{Mock, MockServer, Request, Respond, ResponseTemplate};
+
+pub const TELEGRAM_TOKEN: &str = "bot-token";
+pub const TELEGRAM_CHAT_ID: &str = "42";
+pub const WEBHOOK_SECRET: &str = "hook-secret";
+
+/// In-memory SQLite standing in for D1, initialised from `database.sql`.
+pub struct SqliteDatabase {
+    conn: Mutex<Connection>,
+}
+
+impl SqliteDatabase {
+    pub fn new() -> Self {
+        Self::with_schema(include_str!("../../database.sql"))
+    }
+
+    /// Starts from an older schema, to test the migrations on top of it.
+    pub fn with_schema(schema: &str) -> Self {
+        let conn = Connection::open_in_memory().unwrap();
+        conn.execute_batch(schema).unwrap();
+        Self {
+            conn: Mutex::new(conn),
+        }
+    }
+
+    pub fn query_json(&self, sql: &str) -> Vec<Value> {
+        query_rows(&self.conn.lock().unwrap(), sql, &[]).unwrap()
+    }
+}
+
+fn storage(e: rusqlite::Error) -> Error {
+    Error::storage(e.to_string())
+}
+
+fn to_sql(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Number(n) => match n.as_i64() {
+            Some(i) => SqlValue::Integer(i),
+            None => SqlValue::Real(n.as_f64().unwrap_or_default()),
+        },
+        Value::String(s) => SqlValue::Text(s.clone()),
+        other => SqlValue::Text(other.to_string()),
+    }
+}
+
+fn query_rows(conn: &Connection, sql: &str, params: &[Value]) -> rusqlite::Result<Vec<Value>> {
+    let mut stmt = conn.prepare(sql)?;
+    let columns: Vec<String> = stmt.column_names().iter().map(|c| c.to_string()).collect();
+    let mut rows = stmt.query(params_from_iter(params.iter().map(to_sql)))?;
+    let mut out = Vec::new();
+    while let Some(row) = rows.next()? {
+        let mut object = Map::new();
+        for (i, name) in columns.iter().enumerate() {
+            let value = match row.get_ref(i)? {
+                ValueRef::Null => Value::Null,
+                ValueRef::Integer(i) => json!(i),
+                ValueRef::Real(f) => json!(f),
+                ValueRef::Text(t) => Value::String(String::from_utf8_lossy(t).into_owned()),
+                ValueRef::Blob(b) => json!(b),
+            };
+            object.insert(name.clone(), value);
+        }
+        out.push(Value::Object(object));
+    }
+    Ok(out)
+}
+
+impl Database for SqliteDatabase {
+    async fn query<T: DeserializeOwned>(&self, sql: &str, params: &[Value]) -> Result<Vec<T>> {
+        let rows = query_rows(&self.conn.lock().unwrap(), sql, params).map_err(storage)?;
+        rows.into_iter()
+            .map(|row| Ok(serde_json::from_value(row)?))
+            .collect()
+    }
+
+    async fn batch(&self, statements: Vec<Statement>) -> Result<()> {
+        let mut conn = self.conn.lock().unwrap();
+        let tx = conn.transaction().map_err(storage)?;
+        for statement in statements {
+            tx.execute(
+                &statement.sql,
+                params_from_iter(statement.params.iter().map(to_sql)),
+            )
+            .map_err(storage)?;
+        }
+        tx.commit().map_err(storage)
+    }
+
+    async fn exec(&self, sql: &str) -> Result<()> {
+        self.conn
+            .lock()
+            .unwrap()
+            .execute_batch(sql)
+            .map_err(storage)
+    }
+}
+
+/// In-memory stand-in for Workers KV.
+#[derive(Default)]
+pub struct MemoryKv {
+    entries: RefCell<HashMap<String, String>>,
+}
+
+impl MemoryKv {
+    pub fn with(entries: &[(&str, &str)]) -> Self {
+        let kv = Self::default();
+        for (key, value) in entries {
+            kv.entries
+                .borrow_mut()
+                .insert(key.to_string(), value.to_string());
+        }
+        kv
+    }
+
+    pub fn value(&self, key: &str) -> Option<String> {
+        self.entries.borrow().get(key).cloned()
+    }
+
+    pub fn keys(&self) -> Vec<String> {
+        self.entries.borrow().keys().cloned().collect()
+    }
+}
+
+impl KeyValue for MemoryKv {
+    async fn get(&self, key: &str) -> Result<Option<String>> {
+        Ok(self.entries.borrow().get(key).cloned())
+    }
+
+    async fn put(&self, key: &str, value: &str) -> Result<()> {
+        self.entries
+            .borrow_mut()
+            .insert(key.to_string(), value.to_string());
+        Ok(())
+    }
+
+    async fn delete(&self, key: &str) -> Result<()> {
+        self.entries.borrow_mut().remove(key);
+        Ok(())
+    }
+}
+
+/// Answers TickTick task creation with sequential ids `task-1`, `task-2`, ...
+#[derive(Default)]
+pub struct CreatedTask(AtomicUsize);
+
+impl Respond for CreatedTask {
+    fn respond(&self, request: &Request) -> ResponseTemplate {
+        let mut task: Value = serde_json::from_slice(&request.body).unwrap_or_default();
+        task["id"] = json!(format!(
+            "task-{}",
+            self.0.fetch_add(1, Ordering::SeqCst) + 1
+        ));
+        ResponseTemplate::new(200).set_body_json(task)
+    }
+}
+
+/// Local stand-ins for every upstream the worker talks to.
+pub struct Servers {
+    pub ucloud: MockServer,
+    pub telegram: MockServer,
+    pub ticktick: MockServer,
+    pub lark: MockServer,
+    pub caldav: MockServer,
+    pub webhook: MockServer,
+}
+
+impl Servers {
+    pub async fn start() -> Self {
+        let servers = Self {
+            ucloud: MockServer::start().await,
+            telegram: MockServer::start().await,
+            ticktick: MockServer::start().await,
+            lark: MockServer::start().await,
+            caldav: MockServer::start().await,
+            webhook: MockServer::start().await,
+        };
+
+        Mock::given(method("POST"))
+            .and(path(format!("/bot{}/sendMessage", TELEGRAM_TOKEN)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ok": true})))
+            .mount(&servers.telegram)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/bot{}/sendMediaGroup", TELEGRAM_TOKEN)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ok": true})))
+            .mount(&servers.telegram)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/bot{}/sendDocument", TELEGRAM_TOKEN)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ok": true})))
+            .mount(&servers.telegram)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/bot{}/answerCallbackQuery", TELEGRAM_TOKEN)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ok": true})))
+            .mount(&servers.telegram)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/bot{}/editMessageText", TELEGRAM_TOKEN)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ok": true})))
+            .mount(&servers.telegram)
+            .await;
+        Mock::given(method("POST"))
+            .and(path(format!("/bot{}/deleteMessage", TELEGRAM_TOKEN)))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"ok": true})))
+            .mount(&servers.telegram)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/open/v1/task"))
+            .respond_with(CreatedTask::default())
+            .with_priority(10)
+            .mount(&servers.ticktick)
+            .await;
+        Mock::given(method("GET"))
+            .and(path_regex("^/open/v1/project/[^/]+/data$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"tasks": []})))
+            .with_priority(10)
+            .mount(&servers.ticktick)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path("/passport/users/details/"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({"code": 0})))
+            .mount(&servers.lark)
+            .await;
+        Mock::given(method("PUT"))
+            .and(path_regex(r"^/calendars/student/homework/[^/]+\.ics$"))
+            .respond_with(ResponseTemplate::new(201))
+            .mount(&servers.caldav)
+            .await;
+        Mock::given(method("POST"))
+            .and(path("/hook"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&servers.webhook)
+            .await;
+
+        servers
+    }
+
+    pub fn config(&self) -> Config {
+        Config {
+            username: "student".to_string(),
+            password: "secret".to_string(),
+            api_url: self.ucloud.uri(),
+            ucloud_timeout_secs: 10,
+
+            telegram: Some(TelegramConfig {
+                token: TELEGRAM_TOKEN.to_string(),
+                chat_id: TELEGRAM_CHAT_ID.to_string(),
+                api_url: Some(self.telegram.uri()),
+                notify_completed: false,
+                open_url: None,
+                digest_time: None,
+                digest_only: false,
+            }),
+            ticktick: Some(TickTickConfig {
+                client_id: "client-id".to_string(),
+                client_secret: "client-secret".to_string(),
+                project_id: Some("project-id".to_string()),
+                project_name: "UCloud".to_string(),
+                course_mapping: CourseMapping::None,
+                redirect_uri: "https://worker.example/auth".to_string(),
+                api_url: Some(self.ticktick.uri()),
+            }),
+            lark: Some(LarkConfig {
+                cookie: "session=abc".to_string(),
+                status_template: None,
+                api_url: Some(self.lark.uri()),
+            }),
+            feishu: None,
+            caldav: Some(CalDavConfig {
+                url: format!("{}/calendars/student/homework/", self.caldav.uri()),
+                username: "student".to_string(),
+                password: "dav-secret".to_string(),
+            }),
+            webhook: Some(WebhookConfig {
+                url: format!("{}/hook", self.webhook.uri()),
+                secret: WEBHOOK_SECRET.to_string(),
+                template: None,
+            }),
+
+            reminder_offsets: vec![24 * 60, 3 * 60, 60],
+
+            cipher: None,
+
+            user_id: d1::OWNER.to_string(),
+        }
+    }
+
+    /// Serves `items` from `/undoneList` and a matching `/homework?id=` detail for each.
+    pub async fn serve_undone_list(&self, items: &[Value]) {
+        let details: Vec<Value> = items
+            .iter()
+            .map(|item| detail(item["activityId"].as_str().unwrap()))
+            .collect();
+        self.serve(items, &details).await;
+    }
+
+    /// Serves `items` from `/undoneList` and each of `details` from `/homework?id=`.
+    pub async fn serve(&self, items: &[Value], details: &[Value]) {
+        Mock::given(method("GET"))
+            .and(path("/undoneList"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+                "siteNum": 1,
+                "undoneNum": items.len(),
+                "undoneList": items,
+            })))
+            .mount(&self.ucloud)
+            .await;
+
+        for detail in details {
+            Mock::given(method("GET"))
+                .and(path("/homework"))
+                .and(query_param("id", detail["id"].as_str().unwrap()))
+                .respond_with(ResponseTemplate::new(200).set_body_json(detail))
+                .mount(&self.ucloud)
+                .await;
+        }
+    }
+
+    /// JSON bodies of every request received on `path` by `server`.
+    pub async fn bodies(server: &MockServer, request_path: &str) -> Vec<Value> {
+        server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|r| r.url.path() == request_path)
+            .map(|r| serde_json::from_slice(&r.body).unwrap_or(Value::Null))
+            .collect()
+    }
+
+    pub async fn telegram_messages(&self) -> Vec<Value> {
+        Self::bodies(
+            &self.telegram,
+            &format!("/bot{}/sendMessage", TELEGRAM_TOKEN),
+        )
+        .await
+    }
+
+    /// Every VTODO PUT to the CalDAV collection, as (file name, body) in request order.
+    pub async fn caldav_puts(&self) -> Vec<(String, String)> {
+        self.caldav
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|r| r.method.as_str() == "PUT")
+            .map(|r| {
+                (
+                    r.url
+                        .path()
+                        .rsplit('/')
+                        .next()
+                        .unwrap_or_default()
+                        .to_string(),
+                    String::from_utf8(r.body).unwrap(),
+                )
+            })
+            .collect()
+    }
+
+    /// Ids of the `/homework` details fetched so far, in request order.
+    pub async fn detail_requests(&self) -> Vec<String> {
+        self.ucloud
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|r| r.url.path() == "/homework")
+            .filter_map(|r| {
+                r.url
+                    .query_pairs()
+                    .find(|(key, _)| key == "id")
+                    .map(|(_, id)| id.into_owned())
+            })
+            .collect()
+    }
+
+    /// Raw bodies of the multipart uploads sent to the Telegram `method`.
+    pub async fn telegram_uploads(&self, telegram_method: &str) -> Vec<String> {
+        let upload_path = format!("/bot{}/{}", TELEGRAM_TOKEN, telegram_method);
+        self.telegram
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .filter(|r| r.url.path() == upload_path)
+            .map(|r| String::from_utf8_lossy(&r.body).into_owned())
+            .collect()
+    }
+
+    pub async fn ticktick_tasks(&self) -> Vec<Value> {
+        Self::bodies(&self.ticktick, "/open/v1/task").await
+    }
+}
+
+pub fn undone_item(id: &str, name: &str, end_time: &str) -> Value {
+    json!({
+        "siteId": 1,
+        "siteName": "Site",
+        "activityName": name,
+        "activityId": id,
+        "type": 4,
+        "endTime": end_time,
+        "assignmentType": 0,
+        "evaluationStatus": 0,
+        "isOpenEvaluation": 0,
+        "courseInfo": {"id": "c1", "name": "Operating Systems", "teachers": "Prof. Li"},
+    })
+}
+
+pub fn detail(id: &str) -> Value {
+    json!({
+        "id": id,
+        "assignmentTitle": "Homework",
+        "assignmentContent": "<p>Read chapter <b>3</b></p>",
+        "assignmentComment": "",
+        "className": "",
+        "chapterName": "",
+        "assignmentType": 0,
+        "noSubmitNum": 0,
+        "totalNum": 0,
+        "stayReadNum": 0,
+        "alreadyReadNum": 0,
+        "isGroupExcellent": 0,
+        "assignmentBeginTime": "2025-03-01 08:00",
+        "assignmentEndTime": "2025-03-08 23:59",
+        "isOvertimeCommit": 0,
+        "assignmentStatus": 0,
+        "teamId": 0,
+        "isOpenEvaluation": 0,
+        "status": 0,
+        "groupScore": 0.0,
+        "assignmentScore": 100.0,
+        "assignmentResource": [],
+        "assignmentMutualEvaluation": null,
+        "courseInfo": null,
+        "key": null,
+        "resource": null,
+    })
+}
@@ -0,0 +1,266 @@
+mod common;
+
+use common::MemoryKv;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use ucloud_push::config::{self, Config, CourseMapping, FeishuBot, CONFIG_KEY};
+use ucloud_push::crypto::Cipher;
+
+fn env(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+    let vars: HashMap<String, String> = vars
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .collect();
+    move |name| vars.get(name).cloned()
+}
+
+const UCLOUD: [(&str, &str); 3] = [
+    ("USERNAME", "student"),
+    ("PASSWORD", "secret"),
+    ("API_URL", "http://ucloud"),
+];
+
+#[test]
+fn sinks_without_settings_are_disabled() {
+    let config = Config::from_sources(env(&UCLOUD), None).unwrap();
+
+    assert!(config.telegram.is_none());
+    assert!(config.ticktick.is_none());
+    assert!(config.lark.is_none());
+    assert_eq!(config.reminder_offsets, [1440, 180, 60]);
+}
+
+#[test]
+fn reminder_offsets_are_configurable() {
+    let mut vars = UCLOUD.to_vec();
+    vars.push(("REMINDER_OFFSETS", "2h"));
+    let config = Config::from_sources(env(&vars), None).unwrap();
+    assert_eq!(config.reminder_offsets, [120]);
+
+    let document = json!({"reminder_offsets": ""});
+    let config = Config::from_sources(env(&vars), Some(&document)).unwrap();
+    assert!(config.reminder_offsets.is_empty());
+}
+
+#[test]
+fn sinks_are_enabled_from_env() {
+    let mut vars = UCLOUD.to_vec();
+    vars.extend([
+        ("TELEGRAM_TOKEN", "token"),
+        ("TELEGRAM_CHAT_ID", "1"),
+        ("TELEGRAM_NOTIFY_COMPLETED", "true"),
+        ("LARK_COOKIE", "session=abc"),
+    ]);
+    let config = Config::from_sources(env(&vars), None).unwrap();
+
+    let telegram = config.telegram.unwrap();
+    assert_eq!(telegram.token, "token");
+    assert_eq!(telegram.api_url, None);
+    assert!(telegram.notify_completed);
+    assert_eq!(config.lark.unwrap().cookie, "session=abc");
+    assert!(config.ticktick.is_none());
+}
+
+#[test]
+fn document_overrides_and_disables_env() {
+    let mut vars = UCLOUD.to_vec();
+    vars.extend([
+        ("TELEGRAM_TOKEN", "token"),
+        ("TELEGRAM_CHAT_ID", "1"),
+        ("LARK_COOKIE", "session=abc"),
+    ]);
+    let document = json!({
+        "telegram": {"chat_id": "2"},
+        "lark": {"enabled": false},
+        "ticktick": {
+            "client_id": "id",
+            "client_secret": "secret",
+            "project_id": "inbox",
+            "redirect_uri": "https://worker.example/auth",
+        },
+    });
+    let config = Config::from_sources(env(&vars), Some(&document)).unwrap();
+
+    assert_eq!(config.telegram.unwrap().chat_id, "2");
+    assert!(config.lark.is_none());
+    assert_eq!(
+        config.ticktick.unwrap().project_id.as_deref(),
+        Some("inbox")
+    );
+}
+
+#[test]
+fn env_flag_disables_sink() {
+    let mut vars = UCLOUD.to_vec();
+    vars.extend([("LARK_COOKIE", "session=abc"), ("LARK_ENABLED", "false")]);
+    let config = Config::from_sources(env(&vars), None).unwrap();
+
+    assert!(config.lark.is_none());
+}
+
+#[test]
+fn missing_ucloud_credentials_are_an_error() {
+    assert!(Config::from_sources(env(&[]), None).is_err());
+}
+
+#[test]
+fn ticktick_project_defaults_to_a_name() {
+    let mut vars = UCLOUD.to_vec();
+    vars.extend([
+        ("TICKTICK_CLIENT_ID", "id"),
+        ("TICKTICK_CLIENT_SECRET", "secret"),
+        ("REDIRECT_URI", "https://worker.example/auth"),
+        ("TICKTICK_COURSE_MAPPING", "tag"),
+    ]);
+    let ticktick = Config::from_sources(env(&vars), None)
+        .unwrap()
+        .ticktick
+        .unwrap();
+
+    assert!(ticktick.project_id.is_none());
+    assert_eq!(ticktick.project_name, "UCloud");
+    assert_eq!(ticktick.course_mapping, CourseMapping::Tag);
+}
+
+#[test]
+fn digest_time_is_parsed() {
+    let mut vars = UCLOUD.to_vec();
+    vars.extend([
+        ("TELEGRAM_TOKEN", "token"),
+        ("TELEGRAM_CHAT_ID", "1"),
+        ("TELEGRAM_DIGEST_TIME", "21:30"),
+        ("TELEGRAM_DIGEST_ONLY", "1"),
+    ]);
+    let telegram = Config::from_sources(env(&vars), None)
+        .unwrap()
+        .telegram
+        .unwrap();
+    assert_eq!(
+        telegram.digest_time,
+        chrono::NaiveTime::from_hms_opt(21, 30, 0)
+    );
+    assert!(telegram.digest_only);
+
+    let document = json!({"telegram": {"digest_time": "9pm"}});
+    let error = Config::from_sources(env(&vars), Some(&document)).unwrap_err();
+    assert!(error.to_string().contains("invalid time of day"));
+}
+
+#[test]
+fn webhook_template_is_json_from_env_or_document() {
+    let mut vars = UCLOUD.to_vec();
+    vars.extend([
+        ("WEBHOOK_URL", "https://hooks.example/ucloud"),
+        ("WEBHOOK_SECRET", "secret"),
+        ("WEBHOOK_TEMPLATE", r#"{"title": "{{name}}"}"#),
+    ]);
+    let webhook = Config::from_sources(env(&vars), None)
+        .unwrap()
+        .webhook
+        .unwrap();
+    assert_eq!(webhook.template, Some(json!({"title": "{{name}}"})));
+
+    let document = json!({"webhook": {"template": {"text": "{{name}} is due"}}});
+    let webhook = Config::from_sources(env(&vars), Some(&document))
+        .unwrap()
+        .webhook
+        .unwrap();
+    assert_eq!(webhook.template, Some(json!({"text": "{{name}} is due"})));
+
+    let document = json!({"webhook": {"template": "{not json"}});
+    let error = Config::from_sources(env(&vars), Some(&document)).unwrap_err();
+    assert!(error.to_string().contains("invalid template"));
+}
+
+#[test]
+fn feishu_bot_kind_follows_the_settings() {
+    let config = Config::from_sources(env(&UCLOUD), None).unwrap();
+    assert!(config.feishu.is_none());
+
+    let mut vars = UCLOUD.to_vec();
+    vars.extend([
+        (
+            "FEISHU_WEBHOOK_URL",
+            "https://open.feishu.cn/open-apis/bot/v2/hook/abc",
+        ),
+        ("FEISHU_SECRET", "secret"),
+    ]);
+    let feishu = Config::from_sources(env(&vars), None)
+        .unwrap()
+        .feishu
+        .unwrap();
+    assert!(matches!(
+        feishu.bot,
+        FeishuBot::Webhook {
+            secret: Some(_),
+            ..
+        }
+    ));
+
+    let document = json!({"feishu": {
+        "app_id": "cli_1",
+        "app_secret": "app-secret",
+        "chat_id": "oc_1",
+        "notify_completed": true,
+    }});
+    let vars = UCLOUD.to_vec();
+    let feishu = Config::from_sources(env(&vars), Some(&document))
+        .unwrap()
+        .feishu
+        .unwrap();
+    assert!(matches!(feishu.bot, FeishuBot::App { .. }));
+    assert!(feishu.notify_completed);
+
+    // half a configuration leaves the sink off instead of failing every run
+    let document = json!({"feishu": {"app_id": "cli_1"}});
+    let config = Config::from_sources(env(&vars), Some(&document)).unwrap();
+    assert!(config.feishu.is_none());
+    let mut vars = UCLOUD.to_vec();
+    vars.extend([
+        ("FEISHU_SECRET", "secret"),
+        ("FEISHU_NOTIFY_COMPLETED", "true"),
+    ]);
+    let config = Config::from_sources(env(&vars), None).unwrap();
+    assert!(config.feishu.is_none());
+}
+
+const KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+
+#[tokio::test]
+async fn credentials_in_the_config_document_are_encrypted() {
+    let cipher = Cipher::new(KEY).unwrap();
+    let document = json!({
+        "telegram": {"token": "bot-token", "chat_id": "1"},
+        "caldav": {"password": cipher.encrypt("dav-password").unwrap()},
+        "reminder_offsets": "3h",
+    });
+    let kv = MemoryKv::with(&[(CONFIG_KEY, &document.to_string())]);
+
+    let loaded = config::load_document(&kv, Some(&cipher))
+        .await
+        .unwrap()
+        .unwrap();
+
+    assert_eq!(loaded["telegram"]["token"], "bot-token");
+    assert_eq!(loaded["caldav"]["password"], "dav-password");
+    let stored: Value = serde_json::from_str(&kv.value(CONFIG_KEY).unwrap()).unwrap();
+    let token = stored["telegram"]["token"].as_str().unwrap();
+    assert!(Cipher::is_encrypted(token));
+    assert_eq!(cipher.decrypt(token).unwrap(), "bot-token");
+    assert_eq!(stored["telegram"]["chat_id"], "1");
+    assert_eq!(stored["reminder_offsets"], "3h");
+
+    // encrypted values cannot be read without the key
+    assert!(config::load_document(&kv, None).await.is_err());
+}
+
+#[tokio::test]
+async fn plaintext_documents_are_left_alone_without_a_key() {
+    let document = json!({"webhook": {"url": "http://hook", "secret": "hook-secret"}});
+    let kv = MemoryKv::with(&[(CONFIG_KEY, &document.to_string())]);
+
+    let loaded = config::load_document(&kv, None).await.unwrap().unwrap();
+
+    assert_eq!(loaded, document);
+    assert_eq!(kv.value(CONFIG_KEY).unwrap(), document.to_string());
+}
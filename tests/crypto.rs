@@ -0,0 +1,57 @@
+use ucloud_push::crypto::{Cipher, KEY_SECRET, PREVIOUS_KEYS_SECRET};
+
+const KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+const OLD_KEY: &str = "CQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQk=";
+
+#[test]
+fn credentials_round_trip_only_with_the_same_key() {
+    let cipher = Cipher::new(KEY).unwrap();
+    let first = cipher.encrypt("hunter2").unwrap();
+    let second = cipher.encrypt("hunter2").unwrap();
+    assert!(Cipher::is_encrypted(&first));
+    assert!(!first.contains("hunter2"));
+    assert_ne!(first, second, "every encryption uses a fresh data key");
+    assert_eq!(cipher.decrypt(&first).unwrap(), "hunter2");
+    assert!(cipher.is_current(&first));
+
+    let other = Cipher::new(OLD_KEY).unwrap();
+    assert!(other.decrypt(&first).is_err());
+    assert!(cipher.decrypt("hunter2").is_err());
+    assert!(!Cipher::is_encrypted("hunter2"));
+    assert!(Cipher::new("c2hvcnQ=").is_err());
+}
+
+#[test]
+fn previous_keys_still_decrypt_but_are_not_current() {
+    let old = Cipher::new(OLD_KEY).unwrap().encrypt("hunter2").unwrap();
+
+    let rotated = Cipher::new(KEY)
+        .unwrap()
+        .with_previous_keys(&format!(" {} ,", OLD_KEY))
+        .unwrap();
+    assert_eq!(rotated.decrypt(&old).unwrap(), "hunter2");
+    assert!(!rotated.is_current(&old));
+    assert!(rotated.is_current(&rotated.encrypt("hunter2").unwrap()));
+
+    assert!(Cipher::new(KEY).unwrap().decrypt(&old).is_err());
+}
+
+#[test]
+fn cipher_is_configured_from_the_env() {
+    assert!(Cipher::from_env(|_| None).unwrap().is_none());
+
+    let cipher = Cipher::from_env(|name| match name {
+        KEY_SECRET => Some(KEY.to_string()),
+        PREVIOUS_KEYS_SECRET => Some(OLD_KEY.to_string()),
+        _ => None,
+    })
+    .unwrap()
+    .unwrap();
+    let old = Cipher::new(OLD_KEY).unwrap().encrypt("hunter2").unwrap();
+    assert_eq!(cipher.decrypt(&old).unwrap(), "hunter2");
+    // the debug output is safe to log
+    assert!(!format!("{:?}", cipher).contains(KEY));
+
+    let invalid = |name: &str| (name == KEY_SECRET).then(|| "not base64!".to_string());
+    assert!(Cipher::from_env(invalid).is_err());
+}
@@ -0,0 +1,153 @@
+mod common;
+
+use chrono::{DateTime, NaiveTime, Utc};
+use common::{undone_item, MemoryKv, Servers, SqliteDatabase};
+use serde_json::json;
+use ucloud_push::api::telegram::Telegram;
+use ucloud_push::d1::OpenActivity;
+use ucloud_push::digest::{is_due, render, send_if_due, DIGEST_KEY};
+use ucloud_push::model::UndoneList;
+use ucloud_push::storage::{Database, KeyValue};
+use ucloud_push::{d1, pipeline};
+
+fn at(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+}
+
+fn activity(name: &str, course: Option<&str>, end_time: &str, pushed_at: &str) -> OpenActivity {
+    OpenActivity {
+        activity_id: name.to_string(),
+        activity_name: name.to_string(),
+        start_time: String::new(),
+        end_time: end_time.to_string(),
+        course_name: course.map(str::to_string),
+        description: None,
+        pushed_at: Some(pushed_at.to_string()),
+    }
+}
+
+#[test]
+fn digest_is_due_once_a_day_after_the_configured_time() {
+    let nine_pm = NaiveTime::from_hms_opt(21, 0, 0).unwrap();
+
+    // 12:59 UTC is 20:59 in Beijing
+    assert!(!is_due(nine_pm, None, at("2025-03-08T12:59:00Z")));
+    assert!(is_due(nine_pm, None, at("2025-03-08T13:05:00Z")));
+    assert!(!is_due(
+        nine_pm,
+        Some(at("2025-03-08T13:05:00Z")),
+        at("2025-03-08T15:00:00Z")
+    ));
+    assert!(is_due(
+        nine_pm,
+        Some(at("2025-03-08T13:05:00Z")),
+        at("2025-03-09T13:00:00Z")
+    ));
+}
+
+#[test]
+fn groups_new_due_soon_and_overdue_by_course() {
+    let now = at("2025-03-08T13:00:00Z");
+    let since = at("2025-03-07T13:00:00Z");
+    let activities = [
+        activity(
+            "Late",
+            Some("Algebra"),
+            "2025-03-08 12:00:00",
+            "2025-03-01 00:00:00",
+        ),
+        activity(
+            "Lab 1",
+            Some("Operating Systems"),
+            "2025-03-08 23:59:00",
+            "2025-03-01 00:00:00",
+        ),
+        activity(
+            "Lab 2",
+            Some("Operating Systems"),
+            "2025-03-20 23:59:00",
+            "2025-03-08 02:00:00",
+        ),
+        activity(
+            "Old",
+            Some("Operating Systems"),
+            "2025-03-20 23:59:00",
+            "2025-03-01 00:00:00",
+        ),
+        activity("Essay", None, "2025-03-21 23:59:00", "2025-03-08 02:00:00"),
+    ];
+
+    let text = render(&activities, since, now);
+
+    assert!(text.contains("🆕 新作业 2 · ⏰ 即将截止 1 · ⌛ 已逾期 1"));
+    assert!(text.contains(
+        "<b>Operating Systems</b>\n⏰ Lab 1（2025-03-08 23:59:00，还剩 2 小时 59 分钟）\n🆕 Lab 2"
+    ));
+    assert!(text.contains("<b>Algebra</b>\n⌛ Late（2025-03-08 12:00:00，已截止）"));
+    assert!(text.contains("<b>其他</b>\n🆕 Essay"));
+    assert!(!text.contains("Old"));
+    assert!(text.find("Algebra") < text.find("Operating Systems"));
+
+    let quiet = render(&activities[3..4], since, now);
+    assert!(quiet.contains("今天没有需要关注的作业"));
+}
+
+#[tokio::test]
+async fn sends_the_digest_once() {
+    let servers = Servers::start().await;
+    let mut config = servers.config();
+    let telegram = config.telegram.as_mut().unwrap();
+    telegram.digest_time = NaiveTime::from_hms_opt(21, 0, 0);
+    let bot = Telegram::from_config(telegram);
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+    let list: UndoneList = serde_json::from_value(json!({
+        "siteNum": 1,
+        "undoneNum": 1,
+        "undoneList": [undone_item("a1", "Lab 1", "2025-03-09 23:59:00")],
+    }))
+    .unwrap();
+    d1::save_activities_batch(&list.undone_list, d1::OWNER, &db)
+        .await
+        .unwrap();
+    db.exec("UPDATE activities SET pushed_at = '2025-03-08 02:00:00'")
+        .await
+        .unwrap();
+
+    for now in [
+        "2025-03-08T12:00:00Z",
+        "2025-03-08T13:00:00Z",
+        "2025-03-08T14:00:00Z",
+    ] {
+        send_if_due(telegram, &bot, d1::OWNER, &db, &kv, at(now))
+            .await
+            .unwrap();
+    }
+
+    let messages = servers.telegram_messages().await;
+    assert_eq!(messages.len(), 1);
+    let text = messages[0]["text"].as_str().unwrap();
+    assert!(text.contains("<b>Operating Systems</b>\n⏰ Lab 1"));
+    assert_eq!(
+        kv.get(DIGEST_KEY).await.unwrap(),
+        Some(at("2025-03-08T13:00:00Z").timestamp().to_string())
+    );
+}
+
+#[tokio::test]
+async fn digest_only_skips_the_per_item_messages() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let mut config = servers.config();
+    config.telegram.as_mut().unwrap().digest_only = true;
+    config.ticktick = None;
+    config.reminder_offsets = Vec::new();
+
+    pipeline::push(&config, &SqliteDatabase::new(), &MemoryKv::default())
+        .await
+        .unwrap();
+
+    assert!(servers.telegram_messages().await.is_empty());
+}
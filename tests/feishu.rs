@@ -0,0 +1,163 @@
+mod common;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use common::{undone_item, MemoryKv, Servers, SqliteDatabase};
+use hmac::{Hmac, Mac};
+use serde_json::{json, Value};
+use sha2::Sha256;
+use ucloud_push::config::{Config, FeishuBot, FeishuConfig};
+use ucloud_push::pipeline;
+use wiremock::matchers::{body_json, header, method, path, query_param};
+use wiremock::{Mock, MockServer, ResponseTemplate};
+
+const SECRET: &str = "bot-secret";
+
+/// The test config with Feishu as the only sink besides Telegram.
+fn config(servers: &Servers, bot: FeishuBot) -> Config {
+    Config {
+        ticktick: None,
+        lark: None,
+        caldav: None,
+        webhook: None,
+        feishu: Some(FeishuConfig {
+            bot,
+            notify_completed: false,
+        }),
+        ..servers.config()
+    }
+}
+
+async fn feishu_server(response: Value) -> MockServer {
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/open-apis/bot/v2/hook/abc"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(response.clone()))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/open-apis/im/v1/messages"))
+        .and(query_param("receive_id_type", "chat_id"))
+        .and(header("authorization", "Bearer t-token"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(response))
+        .mount(&server)
+        .await;
+    server
+}
+
+#[tokio::test]
+async fn custom_bot_gets_a_signed_card_per_assignment() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[
+            undone_item("a1", "Lab 1", "2025-03-08 23:59:00"),
+            undone_item("a2", "Lab 2", "2025-03-09 23:59:00"),
+        ])
+        .await;
+    let feishu = feishu_server(json!({"code": 0, "msg": "success", "data": {}})).await;
+    let config = config(
+        &servers,
+        FeishuBot::Webhook {
+            webhook_url: format!("{}/open-apis/bot/v2/hook/abc", feishu.uri()),
+            secret: Some(SECRET.to_string()),
+        },
+    );
+
+    pipeline::push(&config, &SqliteDatabase::new(), &MemoryKv::default())
+        .await
+        .unwrap();
+
+    let cards = Servers::bodies(&feishu, "/open-apis/bot/v2/hook/abc").await;
+    assert_eq!(cards.len(), 2);
+    let body = &cards[0];
+    assert_eq!(body["msg_type"], "interactive");
+    assert_eq!(body["card"]["header"]["title"]["content"], "📚 Lab 1");
+    let text = body["card"]["elements"][0]["content"].as_str().unwrap();
+    assert!(text.contains("**课程**：Operating Systems"));
+    assert!(text.contains("**截止时间**：2025-03-08 23:59:00"));
+    assert!(text.contains("Read chapter **3**"));
+
+    let timestamp = body["timestamp"].as_str().unwrap();
+    let mac =
+        Hmac::<Sha256>::new_from_slice(format!("{}\n{}", timestamp, SECRET).as_bytes()).unwrap();
+    assert_eq!(
+        body["sign"].as_str().unwrap(),
+        STANDARD.encode(mac.finalize().into_bytes())
+    );
+}
+
+#[tokio::test]
+async fn app_bot_fetches_one_tenant_token_per_run() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[
+            undone_item("a1", "Lab 1", "2025-03-08 23:59:00"),
+            undone_item("a2", "Lab 2", "2025-03-09 23:59:00"),
+        ])
+        .await;
+    let feishu = feishu_server(json!({"code": 0, "msg": "success", "data": {}})).await;
+    Mock::given(method("POST"))
+        .and(path("/open-apis/auth/v3/tenant_access_token/internal"))
+        .and(body_json(
+            json!({"app_id": "cli_1", "app_secret": "app-secret"}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "code": 0,
+            "msg": "ok",
+            "tenant_access_token": "t-token",
+            "expire": 7200,
+        })))
+        .expect(1)
+        .mount(&feishu)
+        .await;
+    let config = config(
+        &servers,
+        FeishuBot::App {
+            app_id: "cli_1".to_string(),
+            app_secret: "app-secret".to_string(),
+            chat_id: "oc_1".to_string(),
+            api_url: Some(feishu.uri()),
+        },
+    );
+
+    pipeline::push(&config, &SqliteDatabase::new(), &MemoryKv::default())
+        .await
+        .unwrap();
+
+    let messages = Servers::bodies(&feishu, "/open-apis/im/v1/messages").await;
+    assert_eq!(messages.len(), 2);
+    assert_eq!(messages[1]["receive_id"], "oc_1");
+    assert_eq!(messages[1]["msg_type"], "interactive");
+    let card: Value = serde_json::from_str(messages[1]["content"].as_str().unwrap()).unwrap();
+    assert_eq!(card["header"]["title"]["content"], "📚 Lab 2");
+}
+
+#[tokio::test]
+async fn rejected_cards_are_recorded_as_failed() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let feishu = feishu_server(json!({"code": 19021, "msg": "sign match fail"})).await;
+    let config = config(
+        &servers,
+        FeishuBot::Webhook {
+            webhook_url: format!("{}/open-apis/bot/v2/hook/abc", feishu.uri()),
+            secret: None,
+        },
+    );
+    let db = SqliteDatabase::new();
+
+    pipeline::push(&config, &db, &MemoryKv::default())
+        .await
+        .unwrap();
+
+    let deliveries =
+        db.query_json("SELECT status, last_error FROM deliveries WHERE sink = 'feishu'");
+    assert_eq!(deliveries[0]["status"], "failed");
+    assert!(deliveries[0]["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("sign match fail"));
+    assert_eq!(servers.telegram_messages().await.len(), 1);
+}
@@ -0,0 +1,41 @@
+CREATE TABLE IF NOT EXISTS activities (
+    activity_id TEXT PRIMARY KEY,
+    pushed_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    activity_name TEXT NOT NULL,
+    type INTEGER NOT NULL,
+    start_time TEXT NOT NULL,
+    end_time TEXT NOT NULL,
+    assignment_type INTEGER NOT NULL,
+    evaluation_status INTEGER NOT NULL,
+    is_open_evaluation INTEGER NOT NULL,
+    course_info TEXT,
+    description TEXT,
+    is_overtime_commit INTEGER,
+    completed_at TIMESTAMP,
+    ticktick_task_id TEXT,
+    ticktick_project_id TEXT,
+    attachments TEXT
+);
+CREATE TABLE IF NOT EXISTS deliveries (
+    activity_id TEXT NOT NULL,
+    sink TEXT NOT NULL,
+    status TEXT NOT NULL,
+    attempts INTEGER NOT NULL DEFAULT 0,
+    last_error TEXT,
+    delivered_at TIMESTAMP,
+    PRIMARY KEY (activity_id, sink)
+);
+
+CREATE TABLE IF NOT EXISTS reminders (
+    activity_id TEXT NOT NULL,
+    offset_minutes INTEGER NOT NULL,
+    sent_at TIMESTAMP DEFAULT CURRENT_TIMESTAMP,
+    PRIMARY KEY (activity_id, offset_minutes)
+);
+
+CREATE TABLE IF NOT EXISTS preferences (
+    activity_id TEXT PRIMARY KEY,
+    done_at TIMESTAMP,
+    snoozed_until INTEGER,
+    remind_before INTEGER
+);
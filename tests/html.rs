@@ -0,0 +1,69 @@
+use ucloud_push::html::{split_html, CAPTION_LIMIT, MESSAGE_LIMIT};
+
+fn utf16(text: &str) -> usize {
+    text.encode_utf16().count()
+}
+
+#[test]
+fn short_messages_are_kept_whole() {
+    assert_eq!(
+        split_html("<b>hi</b> there", MESSAGE_LIMIT),
+        ["<b>hi</b> there"]
+    );
+    assert!(split_html("  \n", MESSAGE_LIMIT).is_empty());
+}
+
+#[test]
+fn splits_at_line_breaks_first() {
+    let html = format!("{}\n{}", "a".repeat(30), "b".repeat(30));
+    assert_eq!(split_html(&html, 40), ["a".repeat(30), "b".repeat(30)]);
+}
+
+#[test]
+fn splits_at_spaces_when_lines_are_too_long() {
+    let html = "lorem ipsum dolor sit amet";
+    let chunks = split_html(html, 12);
+    assert_eq!(chunks, ["lorem ipsum", "dolor sit", "amet"]);
+}
+
+#[test]
+fn reopens_tags_across_chunks() {
+    let html = format!("<b>{} <i>{}</i></b> tail", "x".repeat(10), "y".repeat(10));
+    let chunks = split_html(&html, 24);
+    assert_eq!(
+        chunks,
+        [
+            format!("<b>{}</b>", "x".repeat(10)),
+            format!("<b><i>{}</i></b>", "y".repeat(10)),
+            "tail".to_string(),
+        ]
+    );
+}
+
+#[test]
+fn never_cuts_entities_or_links() {
+    let link = "<a href=\"https://example.com/a?x=1&amp;y=2\">";
+    let html = format!("{}{}</a>{}", link, "&amp;".repeat(20), "&lt;".repeat(20));
+    for limit in [60, 70, 90] {
+        let chunks = split_html(&html, limit);
+        for chunk in &chunks {
+            assert!(utf16(chunk) <= limit, "{} is over {}", chunk, limit);
+            assert_eq!(chunk.matches("<a ").count(), chunk.matches("</a>").count());
+            let stripped = chunk.replace("&amp;", "").replace("&lt;", "");
+            assert!(!stripped.contains('&'), "entity cut in {}", chunk);
+        }
+        let joined: String = chunks.concat().replace("</a>", "").replace(link, "");
+        assert_eq!(
+            joined,
+            format!("{}{}", "&amp;".repeat(20), "&lt;".repeat(20))
+        );
+    }
+}
+
+#[test]
+fn counts_utf16_code_units() {
+    let html = "作业😀".repeat(CAPTION_LIMIT);
+    let chunks = split_html(&html, CAPTION_LIMIT);
+    assert!(chunks.iter().all(|chunk| utf16(chunk) <= CAPTION_LIMIT));
+    assert_eq!(chunks.concat(), html);
+}
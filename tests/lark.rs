@@ -0,0 +1,110 @@
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::{undone_item, MemoryKv, Servers, SqliteDatabase};
+use ucloud_push::api::lark::{self, STATUS_KEY};
+use ucloud_push::config::Config;
+use ucloud_push::d1::OpenActivity;
+use ucloud_push::pipeline;
+
+fn at(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+}
+
+fn activity(name: &str, course: Option<&str>, end_time: &str) -> OpenActivity {
+    OpenActivity {
+        activity_id: name.to_string(),
+        activity_name: name.to_string(),
+        start_time: String::new(),
+        end_time: end_time.to_string(),
+        course_name: course.map(str::to_string),
+        description: None,
+        pushed_at: None,
+    }
+}
+
+const TEMPLATE: &str =
+    "{count} 个DDL，{due_soon} 个今天截止，下一个：{next_course}《{next_name}》{next_deadline}";
+
+#[test]
+fn status_shows_counts_and_the_next_deadline() {
+    let activities = [
+        activity("Overdue", None, "2025-03-01 23:59:00"),
+        activity("Essay", None, "2025-03-09 12:00:00"),
+        activity("Lab 1", Some("Compilers"), "2025-03-08 23:59:00"),
+        activity("Unknown", None, "soon"),
+    ];
+    // 2025-03-08 08:00 in Beijing
+    let now = at("2025-03-08T00:00:00Z");
+
+    assert_eq!(
+        lark::render_status(TEMPLATE, &activities, now),
+        "4 个DDL，1 个今天截止，下一个：Compilers《Lab 1》03-08 23:59"
+    );
+    assert_eq!(
+        lark::render_status(lark::DEFAULT_STATUS_TEMPLATE, &activities, now),
+        "拼尽全力仍有 4 个DDL"
+    );
+    assert_eq!(
+        lark::render_status(TEMPLATE, &activities[..1], now),
+        "1 个DDL，0 个今天截止，下一个：无《无》无"
+    );
+}
+
+#[test]
+fn placeholders_in_names_are_not_filled() {
+    let activities = [activity(
+        "Lab {count}",
+        Some("{next_name}"),
+        "2025-03-08 23:59:00",
+    )];
+    let now = at("2025-03-08T00:00:00Z");
+
+    assert_eq!(
+        lark::render_status(TEMPLATE, &activities, now),
+        "1 个DDL，1 个今天截止，下一个：{next_name}《Lab {count}》03-08 23:59"
+    );
+    assert_eq!(
+        lark::render_status("{{count}} {unknown} {", &activities, now),
+        "{1} {unknown} {"
+    );
+}
+
+#[tokio::test]
+async fn unchanged_status_is_not_written_again() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2099-03-08 23:59:00")])
+        .await;
+    let mut config = Config {
+        telegram: None,
+        ticktick: None,
+        caldav: None,
+        webhook: None,
+        ..servers.config()
+    };
+    config.lark.as_mut().unwrap().status_template = Some("{count} 个DDL：{next_name}".to_string());
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let puts = Servers::bodies(&servers.lark, "/passport/users/details/").await;
+    assert_eq!(puts.len(), 1);
+    assert_eq!(puts[0]["description"], "1 个DDL：Lab 1");
+    assert_eq!(kv.value(STATUS_KEY).unwrap(), "1 个DDL：Lab 1");
+
+    servers.ucloud.reset().await;
+    servers
+        .serve_undone_list(&[
+            undone_item("a1", "Lab 1", "2099-03-08 23:59:00"),
+            undone_item("a2", "Lab 0", "2099-03-01 23:59:00"),
+        ])
+        .await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let puts = Servers::bodies(&servers.lark, "/passport/users/details/").await;
+    assert_eq!(puts.len(), 2);
+    assert_eq!(puts[1]["description"], "2 个DDL：Lab 0");
+}
@@ -0,0 +1,111 @@
+mod common;
+
+use common::SqliteDatabase;
+use ucloud_push::storage::Database;
+
+#[tokio::test]
+async fn deliveries_migration_backfills_pushed_activities() {
+    let db = SqliteDatabase::new();
+    db.exec(
+        "INSERT INTO activities (activity_id, activity_name, type, start_time, end_time,
+            assignment_type, evaluation_status, is_open_evaluation)
+        VALUES ('a1', 'Lab 1', 4, '', '2025-03-08 23:59:00', 0, 0, 0)",
+    )
+    .await
+    .unwrap();
+
+    db.exec(include_str!("../migrations/0001_deliveries.sql"))
+        .await
+        .unwrap();
+
+    let rows = db.query_json("SELECT sink, status FROM deliveries ORDER BY sink");
+    let sinks: Vec<&str> = rows.iter().map(|r| r["sink"].as_str().unwrap()).collect();
+    assert_eq!(sinks, ["lark", "telegram", "ticktick"]);
+    assert!(rows.iter().all(|r| r["status"] == "delivered"));
+}
+
+#[tokio::test]
+async fn completed_at_migration_does_not_count_history_as_new_completions() {
+    let db = SqliteDatabase::with_schema(
+        "CREATE TABLE activities (activity_id TEXT PRIMARY KEY, activity_name TEXT NOT NULL);
+        INSERT INTO activities VALUES ('a1', 'Lab 1'), ('a2', 'Lab 2');",
+    );
+
+    db.exec(include_str!("../migrations/0004_completed_at.sql"))
+        .await
+        .unwrap();
+
+    let rows = db.query_json("SELECT completed_at FROM activities");
+    assert_eq!(rows.len(), 2);
+    assert!(rows.iter().all(|r| r["completed_at"].is_string()));
+}
+
+#[tokio::test]
+async fn users_migration_keeps_existing_rows_for_the_owner() {
+    let db = SqliteDatabase::with_schema(include_str!("fixtures/schema_0008.sql"));
+    db.exec(
+        "INSERT INTO activities (activity_id, activity_name, type, start_time, end_time,
+            assignment_type, evaluation_status, is_open_evaluation, ticktick_task_id)
+        VALUES ('a1', 'Lab 1', 4, '', '2025-03-08 23:59:00', 0, 0, 0, 't1');
+        INSERT INTO deliveries (activity_id, sink, status) VALUES ('a1', 'telegram', 'delivered');
+        INSERT INTO reminders (activity_id, offset_minutes) VALUES ('a1', 60);
+        INSERT INTO preferences (activity_id, snoozed_until) VALUES ('a1', 1741478400);",
+    )
+    .await
+    .unwrap();
+
+    db.exec(include_str!("../migrations/0009_users.sql"))
+        .await
+        .unwrap();
+
+    for table in ["activities", "deliveries", "reminders", "preferences"] {
+        let rows = db.query_json(&format!("SELECT user_id, activity_id FROM {}", table));
+        assert_eq!(rows.len(), 1, "{}", table);
+        assert_eq!(rows[0]["user_id"], "", "{}", table);
+        assert_eq!(rows[0]["activity_id"], "a1", "{}", table);
+    }
+    let activity = &db.query_json("SELECT ticktick_task_id FROM activities")[0];
+    assert_eq!(activity["ticktick_task_id"], "t1");
+
+    // the same activity can now be stored for another user
+    db.exec(
+        "INSERT INTO activities (user_id, activity_id, activity_name, type, start_time, end_time,
+            assignment_type, evaluation_status, is_open_evaluation)
+        VALUES ('7', 'a1', 'Lab 1', 4, '', '2025-03-08 23:59:00', 0, 0, 0)",
+    )
+    .await
+    .unwrap();
+    assert!(db.query_json("SELECT * FROM users").is_empty());
+}
+
+#[tokio::test]
+async fn pending_delivery_migrations_apply_to_the_users_schema() {
+    let db = SqliteDatabase::with_schema(include_str!("fixtures/schema_0008.sql"));
+    db.exec(include_str!("../migrations/0009_users.sql"))
+        .await
+        .unwrap();
+
+    db.exec(include_str!("../migrations/0010_change_deliveries.sql"))
+        .await
+        .unwrap();
+    db.exec(include_str!("../migrations/0011_completion_deliveries.sql"))
+        .await
+        .unwrap();
+
+    db.exec(
+        "INSERT INTO change_deliveries (activity_id, sink, kinds)
+        VALUES ('a1', 'telegram', '[\"description\"]')",
+    )
+    .await
+    .unwrap();
+    let rows = db.query_json("SELECT user_id, attempts FROM change_deliveries");
+    assert_eq!(rows[0]["user_id"], "");
+    assert_eq!(rows[0]["attempts"], 0);
+
+    db.exec("INSERT INTO completion_deliveries (activity_id, sink) VALUES ('a1', 'caldav')")
+        .await
+        .unwrap();
+    let rows = db.query_json("SELECT user_id, attempts FROM completion_deliveries");
+    assert_eq!(rows[0]["user_id"], "");
+    assert_eq!(rows[0]["attempts"], 0);
+}
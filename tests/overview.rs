@@ -0,0 +1,203 @@
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::{undone_item, Servers, SqliteDatabase, TELEGRAM_TOKEN};
+use serde_json::json;
+use ucloud_push::actions::handle_callback;
+use ucloud_push::api::telegram::Telegram;
+use ucloud_push::model::{UndoneList, UndoneListItem};
+use ucloud_push::overview::{self, Window, PAGE_SIZE};
+use ucloud_push::{d1, storage::Database};
+use wiremock::matchers::{method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+fn at(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+}
+
+fn items(items: Vec<serde_json::Value>) -> Vec<UndoneListItem> {
+    serde_json::from_value::<UndoneList>(json!({
+        "siteNum": 1,
+        "undoneNum": items.len(),
+        "undoneList": items,
+    }))
+    .unwrap()
+    .undone_list
+}
+
+async fn text(window: Window, db: &SqliteDatabase, now: &str) -> String {
+    let activities = d1::get_open_activities(d1::OWNER, db).await.unwrap();
+    overview::render(&activities, window, 0, at(now)).text
+}
+
+#[tokio::test]
+async fn lists_open_assignments_by_deadline() {
+    let db = SqliteDatabase::new();
+    d1::save_activities_batch(
+        &items(vec![
+            undone_item("a1", "Lab 2", "2025-03-09 23:59:00"),
+            undone_item("a2", "Lab 1", "2025-03-08 23:59:00"),
+            undone_item("a3", "Essay", "2025-03-10 12:00:00"),
+            undone_item("a4", "Quiz", "2025-03-10 12:00:00"),
+        ]),
+        d1::OWNER,
+        &db,
+    )
+    .await
+    .unwrap();
+    d1::mark_done("a3", d1::OWNER, &db).await.unwrap();
+    db.exec("UPDATE activities SET completed_at = CURRENT_TIMESTAMP WHERE activity_id = 'a4'")
+        .await
+        .unwrap();
+
+    // 2025-03-08 13:59 UTC is 21:59 in Beijing
+    let text = text(Window::All, &db, "2025-03-08T13:59:00Z").await;
+    let lab1 = text.find("Lab 1").unwrap();
+    let lab2 = text.find("Lab 2").unwrap();
+    assert!(lab1 < lab2);
+    assert!(text.contains(
+        "1. <b>Lab 1</b> · Operating Systems\n    ⏳ 还剩 2 小时（2025-03-08 23:59:00）"
+    ));
+    assert!(text.contains("还剩 1 天 2 小时"));
+    assert!(!text.contains("Essay"));
+    assert!(!text.contains("Quiz"));
+
+    let overdue = self::text(Window::All, &db, "2025-03-09T00:00:00Z").await;
+    assert!(overdue.contains("⌛ 已截止"));
+}
+
+#[tokio::test]
+async fn due_filters_by_window() {
+    let db = SqliteDatabase::new();
+    d1::save_activities_batch(
+        &items(vec![
+            undone_item("a1", "Tonight", "2025-03-08 23:59:00"),
+            undone_item("a2", "Friday", "2025-03-14 12:00:00"),
+            undone_item("a3", "Later", "2025-03-20 12:00:00"),
+            undone_item("a4", "Overdue", "2025-03-08 08:00:00"),
+        ]),
+        d1::OWNER,
+        &db,
+    )
+    .await
+    .unwrap();
+
+    let today = text(Window::Today, &db, "2025-03-08T04:00:00Z").await;
+    assert!(today.contains("Tonight"));
+    assert!(!today.contains("Friday"));
+    assert!(!today.contains("Overdue"));
+
+    let week = text(Window::Week, &db, "2025-03-08T04:00:00Z").await;
+    assert!(week.contains("Tonight") && week.contains("Friday"));
+    assert!(!week.contains("Later"));
+
+    let empty = text(Window::Today, &db, "2025-03-09T04:00:00Z").await;
+    assert!(empty.contains("没有需要完成的作业啦"));
+}
+
+#[tokio::test]
+async fn long_lists_are_paginated() {
+    let servers = Servers::start().await;
+    let bot = Telegram::from_config(servers.config().telegram.as_ref().unwrap());
+    let db = SqliteDatabase::new();
+    let assignments = (0..PAGE_SIZE + 2)
+        .map(|i| {
+            undone_item(
+                &format!("a{:02}", i),
+                &format!("Lab {:02}", i),
+                &format!("2025-03-{:02} 23:59:00", 10 + i),
+            )
+        })
+        .collect();
+    d1::save_activities_batch(&items(assignments), d1::OWNER, &db)
+        .await
+        .unwrap();
+
+    overview::send(
+        &bot,
+        Window::All,
+        d1::OWNER,
+        &db,
+        at("2025-03-08T00:00:00Z"),
+    )
+    .await
+    .unwrap();
+    let first = &servers.telegram_messages().await[0];
+    assert!(first["text"].as_str().unwrap().contains("（第 1/2 页）"));
+    assert!(!first["text"].as_str().unwrap().contains("Lab 10"));
+    let buttons = &first["reply_markup"]["inline_keyboard"][0];
+    assert_eq!(buttons.as_array().unwrap().len(), 1);
+    assert_eq!(buttons[0]["callback_data"], "page:all:1");
+
+    let query = json!({
+        "id": "query-1",
+        "from": {"id": 1},
+        "message": {"message_id": 7},
+        "data": "page:all:1",
+    });
+    handle_callback(&query, &bot, d1::OWNER, &db, at("2025-03-08T00:00:00Z"))
+        .await
+        .unwrap();
+
+    let edits = Servers::bodies(
+        &servers.telegram,
+        &format!("/bot{}/editMessageText", TELEGRAM_TOKEN),
+    )
+    .await;
+    assert_eq!(edits[0]["message_id"], 7);
+    let second = edits[0]["text"].as_str().unwrap();
+    assert!(second.contains("（第 2/2 页）"));
+    assert!(second.contains("11. <b>Lab 10</b>"));
+    assert!(!second.contains("Lab 09"));
+    assert_eq!(
+        edits[0]["reply_markup"]["inline_keyboard"][0][0]["callback_data"],
+        "page:all:0"
+    );
+}
+
+#[tokio::test]
+async fn pressing_the_shown_page_again_is_answered() {
+    let servers = Servers::start().await;
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{}/editMessageText", TELEGRAM_TOKEN)))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: message is not modified",
+        })))
+        .with_priority(1)
+        .mount(&servers.telegram)
+        .await;
+    let bot = Telegram::from_config(servers.config().telegram.as_ref().unwrap());
+    let query = json!({
+        "id": "query-1",
+        "from": {"id": 1},
+        "message": {"message_id": 7},
+        "data": "page:all:0",
+    });
+
+    handle_callback(
+        &query,
+        &bot,
+        d1::OWNER,
+        &SqliteDatabase::new(),
+        at("2025-03-08T00:00:00Z"),
+    )
+    .await
+    .unwrap();
+
+    let answers = Servers::bodies(
+        &servers.telegram,
+        &format!("/bot{}/answerCallbackQuery", TELEGRAM_TOKEN),
+    )
+    .await;
+    assert_eq!(answers.len(), 1);
+}
+
+#[test]
+fn parses_page_buttons() {
+    assert_eq!(Window::parse_page("page:week:2"), Some((Window::Week, 2)));
+    assert_eq!(Window::parse_page("page:month:2"), None);
+    assert_eq!(Window::parse_page("done:a1"), None);
+    assert_eq!(Window::parse("today"), Some(Window::Today));
+}
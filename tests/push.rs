@@ -0,0 +1,755 @@
+mod common;
+
+use common::{detail, undone_item, MemoryKv, Servers, SqliteDatabase};
+use serde_json::json;
+use ucloud_push::error::Error;
+use ucloud_push::storage::{Database, KeyValue};
+use ucloud_push::{d1, pipeline};
+use wiremock::matchers::{body_partial_json, body_string_contains, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn pushes_new_activities_to_every_sink() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let messages = servers.telegram_messages().await;
+    assert_eq!(messages.len(), 1);
+    let text = messages[0]["text"].as_str().unwrap();
+    assert!(text.contains("Lab 1"));
+    assert!(text.contains("Operating Systems"));
+
+    let tasks = servers.ticktick_tasks().await;
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0]["title"], "Lab 1");
+    assert_eq!(tasks[0]["projectId"], "project-id");
+    assert_eq!(tasks[0]["dueDate"], "2025-03-08T23:59:00+0800");
+
+    let lark = Servers::bodies(&servers.lark, "/passport/users/details/").await;
+    assert_eq!(lark[0]["description"], "拼尽全力仍有 1 个DDL");
+
+    let rows = db.query_json("SELECT activity_id, start_time FROM activities");
+    assert_eq!(rows.len(), 1);
+    assert_eq!(rows[0]["activity_id"], "a1");
+    assert_eq!(rows[0]["start_time"], "2025-03-01 08:00");
+}
+
+#[tokio::test]
+async fn does_not_push_the_same_activity_twice() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    assert_eq!(servers.telegram_messages().await.len(), 1);
+    assert_eq!(servers.ticktick_tasks().await.len(), 1);
+}
+
+#[tokio::test]
+async fn sends_login_link_without_ticktick_token() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    assert!(servers.ticktick_tasks().await.is_empty());
+    let state = kv.value("state").unwrap();
+    let messages = servers.telegram_messages().await;
+    let login = messages
+        .iter()
+        .filter_map(|m| m["text"].as_str())
+        .find(|text| text.contains("/oauth/authorize"))
+        .unwrap();
+    assert!(login.contains(&format!("state={}", state)));
+
+    // the link already sent stays valid and is not sent again
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+    let logins = servers
+        .telegram_messages()
+        .await
+        .iter()
+        .filter(|m| m["text"].as_str().unwrap().contains("/oauth/authorize"))
+        .count();
+    assert_eq!(logins, 1);
+    assert_eq!(kv.value("state").unwrap(), state);
+}
+
+#[tokio::test]
+async fn ticktick_problems_do_not_stop_other_sinks() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let db = SqliteDatabase::new();
+    // encrypted, but no key is configured to read it
+    let kv = MemoryKv::with(&[("ticktick_token", "v2:0badc0de:AAAA:AAAA")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    assert_eq!(servers.telegram_messages().await.len(), 1);
+    assert!(servers.ticktick_tasks().await.is_empty());
+    assert_eq!(servers.caldav_puts().await.len(), 1);
+}
+
+#[tokio::test]
+async fn skips_disabled_sinks() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+    let mut config = servers.config();
+    config.lark = None;
+    config.ticktick = None;
+    config.caldav = None;
+    config.webhook = None;
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    assert_eq!(servers.telegram_messages().await.len(), 1);
+    assert!(servers.ticktick_tasks().await.is_empty());
+    assert!(servers.lark.received_requests().await.unwrap().is_empty());
+    assert!(servers.caldav_puts().await.is_empty());
+    assert!(servers
+        .webhook
+        .received_requests()
+        .await
+        .unwrap()
+        .is_empty());
+}
+
+#[tokio::test]
+async fn retries_only_the_sink_that_failed() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/open/v1/task"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&servers.ticktick)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let failed = db
+        .query_json("SELECT status, attempts, last_error FROM deliveries WHERE sink = 'ticktick'");
+    assert_eq!(failed[0]["status"], "failed");
+    assert_eq!(failed[0]["attempts"], 1);
+    assert!(failed[0]["last_error"].as_str().unwrap().contains("500"));
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    assert_eq!(servers.telegram_messages().await.len(), 1);
+    assert_eq!(servers.ticktick_tasks().await.len(), 2);
+    let retried = db.query_json(
+        "SELECT status, attempts, delivered_at FROM deliveries WHERE sink = 'ticktick'",
+    );
+    assert_eq!(retried[0]["status"], "delivered");
+    assert_eq!(retried[0]["attempts"], 2);
+    assert!(retried[0]["delivered_at"].is_string());
+}
+
+#[tokio::test]
+async fn retries_only_the_activities_that_failed() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[
+            undone_item("a1", "Lab 1", "2025-03-08 23:59:00"),
+            undone_item("a2", "Lab 2", "2025-03-09 23:59:00"),
+            undone_item("a3", "Lab 3", "2025-03-10 23:59:00"),
+        ])
+        .await;
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{}/sendMessage", common::TELEGRAM_TOKEN)))
+        .and(body_string_contains("Lab 2"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&servers.telegram)
+        .await;
+    let mut config = servers.config();
+    config.ticktick = None;
+    config.reminder_offsets = Vec::new();
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let deliveries = db.query_json(
+        "SELECT activity_id, status FROM deliveries WHERE sink = 'telegram' ORDER BY activity_id",
+    );
+    let statuses: Vec<&str> = deliveries
+        .iter()
+        .map(|d| d["status"].as_str().unwrap())
+        .collect();
+    assert_eq!(statuses, ["delivered", "failed", "delivered"]);
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let messages = servers.telegram_messages().await;
+    let sent = |name: &str| {
+        messages
+            .iter()
+            .filter(|m| m["text"].as_str().unwrap().contains(name))
+            .count()
+    };
+    assert_eq!(sent("Lab 1"), 1);
+    assert_eq!(sent("Lab 2"), 2);
+    assert_eq!(sent("Lab 3"), 1);
+    let retried = db.query_json(
+        "SELECT status, attempts FROM deliveries WHERE sink = 'telegram' AND activity_id = 'a2'",
+    );
+    assert_eq!(retried[0]["status"], "delivered");
+    assert_eq!(retried[0]["attempts"], 2);
+}
+
+#[tokio::test]
+async fn announces_changes_to_pushed_activities() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/open/v1/task/task-1"))
+        .and(body_partial_json(
+            json!({"id": "task-1", "dueDate": "2025-03-10T23:59:00+0800"}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&servers.ticktick)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    servers.ucloud.reset().await;
+    let mut edited = detail("a1");
+    edited["assignmentContent"] = json!("<p>Read chapter <b>4</b></p>");
+    edited["isOvertimeCommit"] = json!(1);
+    servers
+        .serve(
+            &[undone_item("a1", "Lab 1", "2025-03-10 23:59:00")],
+            &[edited],
+        )
+        .await;
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let messages = servers.telegram_messages().await;
+    assert_eq!(messages.len(), 2);
+    let text = messages[1]["text"].as_str().unwrap();
+    assert!(text.contains("2025-03-08 23:59:00 → 2025-03-10 23:59:00"));
+    assert!(text.contains("能否补交</b>：能 → 否"));
+    assert!(text.contains("chapter <b>4</b>"));
+    assert_eq!(servers.ticktick_tasks().await.len(), 1);
+
+    let rows = db.query_json("SELECT end_time, description, is_overtime_commit FROM activities");
+    assert_eq!(rows[0]["end_time"], "2025-03-10 23:59:00");
+    assert_eq!(rows[0]["description"], "<p>Read chapter <b>4</b></p>");
+    assert_eq!(rows[0]["is_overtime_commit"], 0);
+
+    // nothing changed since, so nothing is announced again
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+    assert_eq!(servers.telegram_messages().await.len(), 2);
+}
+
+#[tokio::test]
+async fn announces_a_description_edited_without_a_new_deadline() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+    let mut config = servers.config();
+    config.ticktick = None;
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    servers.ucloud.reset().await;
+    let mut edited = detail("a1");
+    edited["assignmentContent"] = json!("<p>Read chapter <b>4</b></p>");
+    servers
+        .serve(
+            &[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")],
+            &[edited],
+        )
+        .await;
+
+    // the stored detail is recent, so the edit is not seen yet
+    pipeline::push(&config, &db, &kv).await.unwrap();
+    assert_eq!(servers.telegram_messages().await.len(), 1);
+
+    db.exec("UPDATE activities SET details_fetched_at = datetime('now', '-1 day')")
+        .await
+        .unwrap();
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let messages = servers.telegram_messages().await;
+    assert_eq!(messages.len(), 2);
+    let text = messages[1]["text"].as_str().unwrap();
+    assert!(text.contains("chapter <b>4</b>"));
+    assert!(!text.contains("→"));
+    let rows = db.query_json("SELECT description FROM activities");
+    assert_eq!(rows[0]["description"], "<p>Read chapter <b>4</b></p>");
+}
+
+#[tokio::test]
+async fn changes_stay_pending_for_the_sink_that_failed() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let mut config = servers.config();
+    config.ticktick = None;
+    config.reminder_offsets = Vec::new();
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    servers.ucloud.reset().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-10 23:59:00")])
+        .await;
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{}/sendMessage", common::TELEGRAM_TOKEN)))
+        .and(body_string_contains("作业有变动"))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&servers.telegram)
+        .await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let pending = db.query_json("SELECT sink, attempts, last_error FROM change_deliveries");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0]["sink"], "telegram");
+    assert_eq!(pending[0]["attempts"], 1);
+    assert!(pending[0]["last_error"].is_string());
+
+    // the deadline moves again before the retry, which announces both moves at once
+    servers.ucloud.reset().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-12 23:59:00")])
+        .await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let messages = servers.telegram_messages().await;
+    let text = messages.last().unwrap()["text"].as_str().unwrap();
+    assert!(text.contains("2025-03-08 23:59:00 → 2025-03-12 23:59:00"));
+    assert_eq!(servers.caldav_puts().await.len(), 3);
+    assert!(db.query_json("SELECT * FROM change_deliveries").is_empty());
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+    assert_eq!(servers.telegram_messages().await.len(), messages.len());
+}
+
+#[tokio::test]
+async fn completes_activities_that_leave_the_undone_list() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[
+            undone_item("a1", "Lab 1", "2025-03-08 23:59:00"),
+            undone_item("a2", "Lab 2", "2025-03-09 23:59:00"),
+        ])
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/open/v1/project/project-id/task/task-1/complete"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&servers.ticktick)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+    let mut config = servers.config();
+    config.telegram.as_mut().unwrap().notify_completed = true;
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    servers.ucloud.reset().await;
+    servers
+        .serve_undone_list(&[undone_item("a2", "Lab 2", "2025-03-09 23:59:00")])
+        .await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let messages = servers.telegram_messages().await;
+    assert_eq!(messages.len(), 3);
+    assert!(messages[2]["text"].as_str().unwrap().contains("作业已完成"));
+
+    let rows =
+        db.query_json("SELECT activity_id, completed_at FROM activities ORDER BY activity_id");
+    assert!(rows[0]["completed_at"].is_string());
+    assert!(rows[1]["completed_at"].is_null());
+}
+
+#[tokio::test]
+async fn changes_wait_for_a_sink_that_was_skipped() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/open/v1/task/task-1"))
+        .and(body_partial_json(
+            json!({"id": "task-1", "dueDate": "2025-03-10T23:59:00+0800"}),
+        ))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(1)
+        .mount(&servers.ticktick)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    // logged out, so ticktick is skipped while the deadline moves
+    for key in kv.keys() {
+        kv.delete(&key).await.unwrap();
+    }
+    servers.ucloud.reset().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-10 23:59:00")])
+        .await;
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let pending = db.query_json("SELECT sink FROM change_deliveries");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0]["sink"], "ticktick");
+
+    kv.put("access_token", "token").await.unwrap();
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    assert!(db.query_json("SELECT * FROM change_deliveries").is_empty());
+}
+
+#[tokio::test]
+async fn retries_completions_the_sink_did_not_take() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let complete = "/open/v1/project/project-id/task/task-1/complete";
+    Mock::given(method("POST"))
+        .and(path(complete))
+        .respond_with(ResponseTemplate::new(500))
+        .up_to_n_times(1)
+        .with_priority(1)
+        .mount(&servers.ticktick)
+        .await;
+    Mock::given(method("POST"))
+        .and(path(complete))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&servers.ticktick)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+    let mut config = servers.config();
+    config.telegram.as_mut().unwrap().notify_completed = true;
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    servers.ucloud.reset().await;
+    servers.serve_undone_list(&[]).await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let pending = db.query_json("SELECT sink, attempts FROM completion_deliveries");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0]["sink"], "ticktick");
+    assert_eq!(pending[0]["attempts"], 1);
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    assert!(db
+        .query_json("SELECT * FROM completion_deliveries")
+        .is_empty());
+    let completions = servers
+        .telegram_messages()
+        .await
+        .iter()
+        .filter(|m| m["text"].as_str().unwrap().contains("作业已完成"))
+        .count();
+    assert_eq!(completions, 1);
+}
+
+#[tokio::test]
+async fn completions_wait_for_a_sink_that_was_skipped() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/open/v1/project/project-id/task/task-1/complete"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&servers.ticktick)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    // logged out, so ticktick is skipped while the activity is submitted
+    for key in kv.keys() {
+        kv.delete(&key).await.unwrap();
+    }
+    servers.ucloud.reset().await;
+    servers.serve_undone_list(&[]).await;
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let pending = db.query_json("SELECT sink FROM completion_deliveries");
+    assert_eq!(pending.len(), 1);
+    assert_eq!(pending[0]["sink"], "ticktick");
+
+    kv.put("access_token", "token").await.unwrap();
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    assert!(db
+        .query_json("SELECT * FROM completion_deliveries")
+        .is_empty());
+}
+
+#[tokio::test]
+async fn stores_ticktick_task_ids_and_updates_after_clear() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let rows = db.query_json("SELECT ticktick_task_id FROM activities");
+    assert_eq!(rows[0]["ticktick_task_id"], "task-1");
+
+    Mock::given(method("GET"))
+        .and(path("/open/v1/project/project-id/data"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "tasks": [
+                {"id": "task-0", "title": "Lab 1", "content": "UCloud ID：a10"},
+                {"id": "task-1", "title": "Lab 1", "content": "Read chapter 3\n\nUCloud ID：a1\n"},
+            ],
+        })))
+        .mount(&servers.ticktick)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/open/v1/task/task-1"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({"id": "task-1"})))
+        .expect(1)
+        .mount(&servers.ticktick)
+        .await;
+
+    d1::cleanup_activities(d1::OWNER, &db).await.unwrap();
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    assert_eq!(servers.ticktick_tasks().await.len(), 1);
+    let rows = db.query_json("SELECT ticktick_task_id FROM activities");
+    assert_eq!(rows[0]["ticktick_task_id"], "task-1");
+}
+
+#[tokio::test]
+async fn tasks_of_other_activities_with_the_same_title_are_left_alone() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a2", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/open/v1/project/project-id/data"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "tasks": [{"id": "task-9", "title": "Lab 1", "content": "课程：Compilers\n\nUCloud ID：a1"}],
+        })))
+        .mount(&servers.ticktick)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/open/v1/task/task-9"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({})))
+        .expect(0)
+        .mount(&servers.ticktick)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let tasks = servers.ticktick_tasks().await;
+    assert_eq!(tasks.len(), 1);
+    assert!(tasks[0]["content"]
+        .as_str()
+        .unwrap()
+        .ends_with("UCloud ID：a2\n"));
+    let rows = db.query_json("SELECT ticktick_task_id FROM activities");
+    assert_eq!(rows[0]["ticktick_task_id"], "task-1");
+}
+
+#[tokio::test]
+async fn reports_upstream_failures_to_telegram() {
+    let servers = Servers::start().await;
+    Mock::given(method("GET"))
+        .and(path("/undoneList"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&servers.ucloud)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+    let config = servers.config();
+
+    let e = pipeline::push(&config, &db, &kv).await.unwrap_err();
+    assert!(matches!(
+        e,
+        Error::Upstream {
+            status: Some(500),
+            ..
+        }
+    ));
+    assert_eq!(e.status(), 502);
+
+    pipeline::report(&config, &e).await;
+    let messages = servers.telegram_messages().await;
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0]["text"].as_str().unwrap().contains("推送失败"));
+}
+
+#[tokio::test]
+async fn malformed_upstream_responses_are_parse_errors() {
+    let servers = Servers::start().await;
+    Mock::given(method("GET"))
+        .and(path("/undoneList"))
+        .respond_with(ResponseTemplate::new(200).set_body_string("<html>maintenance</html>"))
+        .mount(&servers.ucloud)
+        .await;
+
+    let e = pipeline::push(
+        &servers.config(),
+        &SqliteDatabase::new(),
+        &MemoryKv::default(),
+    )
+    .await
+    .unwrap_err();
+    assert!(matches!(e, Error::Parse(_)));
+}
+
+#[tokio::test]
+async fn long_descriptions_are_split_into_several_messages() {
+    let servers = Servers::start().await;
+    let mut long = detail("a1");
+    long["assignmentContent"] = json!(format!("<p>{}</p>", "<b>段落</b>内容。".repeat(800)));
+    servers
+        .serve(
+            &[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")],
+            &[long],
+        )
+        .await;
+    let mut config = servers.config();
+    config.ticktick = None;
+
+    pipeline::push(&config, &SqliteDatabase::new(), &MemoryKv::default())
+        .await
+        .unwrap();
+
+    let messages = servers.telegram_messages().await;
+    assert!(messages.len() > 1);
+    for message in &messages {
+        let text = message["text"].as_str().unwrap();
+        assert!(text.encode_utf16().count() <= 4096);
+        assert_eq!(text.matches("<b>").count(), text.matches("</b>").count());
+    }
+    assert!(messages[0]["reply_markup"].is_null());
+    assert!(messages.last().unwrap()["reply_markup"].is_object());
+}
+
+#[tokio::test]
+async fn long_captions_continue_in_a_message() {
+    let servers = Servers::start().await;
+    let mut with_image = detail("a1");
+    with_image["assignmentContent"] = json!(format!(
+        "<p><img src=\"https://img.example/1.png\">{}</p>",
+        "说明".repeat(600)
+    ));
+    servers
+        .serve(
+            &[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")],
+            &[with_image],
+        )
+        .await;
+    let mut config = servers.config();
+    config.ticktick = None;
+
+    pipeline::push(&config, &SqliteDatabase::new(), &MemoryKv::default())
+        .await
+        .unwrap();
+
+    let albums = Servers::bodies(
+        &servers.telegram,
+        &format!("/bot{}/sendMediaGroup", common::TELEGRAM_TOKEN),
+    )
+    .await;
+    let caption = albums[0]["media"][0]["caption"].as_str().unwrap();
+    assert!(caption.encode_utf16().count() <= 1024);
+    assert!(caption.contains("Lab 1"));
+
+    let messages = servers.telegram_messages().await;
+    assert!(caption.contains("<b>详细：</b>\n\n说明"));
+    let rest = messages[0]["text"].as_str().unwrap();
+    assert!(rest.starts_with('说') || rest.starts_with('明'));
+    assert!(messages.last().unwrap()["reply_markup"].is_object());
+}
+
+#[tokio::test]
+async fn rejected_messages_are_recorded_as_failed() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    Mock::given(method("POST"))
+        .and(path(format!("/bot{}/sendMessage", common::TELEGRAM_TOKEN)))
+        .respond_with(ResponseTemplate::new(400).set_body_json(json!({
+            "ok": false,
+            "error_code": 400,
+            "description": "Bad Request: can't parse entities",
+        })))
+        .with_priority(1)
+        .mount(&servers.telegram)
+        .await;
+    let mut config = servers.config();
+    config.ticktick = None;
+    config.reminder_offsets = Vec::new();
+    let db = SqliteDatabase::new();
+
+    pipeline::push(&config, &db, &MemoryKv::default())
+        .await
+        .unwrap();
+
+    let deliveries =
+        db.query_json("SELECT status, last_error FROM deliveries WHERE sink = 'telegram'");
+    assert_eq!(deliveries[0]["status"], "failed");
+    assert!(deliveries[0]["last_error"]
+        .as_str()
+        .unwrap()
+        .contains("can't parse entities"));
+}
@@ -0,0 +1,124 @@
+mod common;
+
+use chrono::{DateTime, Utc};
+use common::{undone_item, Servers, SqliteDatabase};
+use ucloud_push::api::telegram::Telegram;
+use ucloud_push::d1;
+use ucloud_push::model::UndoneList;
+use ucloud_push::reminder::{format_remaining, parse_offsets, send_reminders};
+
+fn at(time: &str) -> DateTime<Utc> {
+    DateTime::parse_from_rfc3339(time).unwrap().to_utc()
+}
+
+fn undone_list() -> UndoneList {
+    serde_json::from_value(serde_json::json!({
+        "siteNum": 1,
+        "undoneNum": 1,
+        "undoneList": [undone_item("a1", "Lab 1", "2025-03-08 23:59:00")],
+    }))
+    .unwrap()
+}
+
+#[test]
+fn parses_offsets_largest_first() {
+    assert_eq!(parse_offsets("1h, 24h,30m,1d").unwrap(), [1440, 60, 30]);
+    assert!(parse_offsets("").unwrap().is_empty());
+    assert!(parse_offsets("3x").is_err());
+    assert!(parse_offsets("0h").is_err());
+    // multibyte units and overflowing numbers are errors, not panics
+    assert!(parse_offsets("3时").is_err());
+    assert!(parse_offsets("时").is_err());
+    assert!(parse_offsets(&format!("{}d", i64::MAX / 60)).is_err());
+}
+
+#[test]
+fn formats_remaining_time() {
+    assert_eq!(format_remaining(26 * 60), "1 天 2 小时");
+    assert_eq!(format_remaining(24 * 60), "1 天");
+    assert_eq!(format_remaining(170), "2 小时 50 分钟");
+    assert_eq!(format_remaining(45), "45 分钟");
+}
+
+#[tokio::test]
+async fn escalates_once_per_offset() {
+    let servers = Servers::start().await;
+    let config = servers.config();
+    let bot = Telegram::from_config(config.telegram.as_ref().unwrap());
+    let db = SqliteDatabase::new();
+    let list = undone_list();
+    let offsets = &config.reminder_offsets;
+
+    // deadline is 2025-03-08 23:59 +08:00, i.e. 15:59 UTC
+    send_reminders(
+        &list,
+        &bot,
+        offsets,
+        d1::OWNER,
+        &db,
+        at("2025-03-06T12:00:00Z"),
+    )
+    .await
+    .unwrap();
+    assert!(servers.telegram_messages().await.is_empty());
+
+    // T-2h50m crosses both 24h and 3h, but only one message goes out
+    send_reminders(
+        &list,
+        &bot,
+        offsets,
+        d1::OWNER,
+        &db,
+        at("2025-03-08T13:09:00Z"),
+    )
+    .await
+    .unwrap();
+    send_reminders(
+        &list,
+        &bot,
+        offsets,
+        d1::OWNER,
+        &db,
+        at("2025-03-08T13:30:00Z"),
+    )
+    .await
+    .unwrap();
+    let messages = servers.telegram_messages().await;
+    assert_eq!(messages.len(), 1);
+    assert!(messages[0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("2 小时 50 分钟"));
+
+    send_reminders(
+        &list,
+        &bot,
+        offsets,
+        d1::OWNER,
+        &db,
+        at("2025-03-08T15:20:00Z"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(servers.telegram_messages().await.len(), 2);
+
+    // overdue activities are left alone
+    send_reminders(
+        &list,
+        &bot,
+        offsets,
+        d1::OWNER,
+        &db,
+        at("2025-03-08T17:00:00Z"),
+    )
+    .await
+    .unwrap();
+    assert_eq!(servers.telegram_messages().await.len(), 2);
+
+    let rows = db.query_json("SELECT offset_minutes FROM reminders ORDER BY offset_minutes");
+    let offsets: Vec<i64> = rows
+        .iter()
+        .map(|r| r["offset_minutes"].as_i64().unwrap())
+        .collect();
+    assert_eq!(offsets, [60, 180, 1440]);
+}
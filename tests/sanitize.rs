@@ -0,0 +1,67 @@
+use std::fs;
+use std::path::Path;
+use ucloud_push::html::{sanitize, split_html};
+
+/// Every `tests/fixtures/html/<name>.html` must sanitize to `<name>.txt`. Run with
+/// `UPDATE_FIXTURES=1` to write the current output instead, then review the diff.
+#[test]
+fn sanitizes_fixtures() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/html");
+    let update = std::env::var_os("UPDATE_FIXTURES").is_some();
+    let mut checked = 0;
+    for entry in fs::read_dir(&dir).unwrap() {
+        let input = entry.unwrap().path();
+        if input.extension().is_none_or(|ext| ext != "html") {
+            continue;
+        }
+        let expected = input.with_extension("txt");
+        let actual = sanitize(&fs::read_to_string(&input).unwrap()).text;
+        if update {
+            fs::write(&expected, format!("{}\n", actual)).unwrap();
+        } else {
+            let expected = fs::read_to_string(&expected).unwrap();
+            assert_eq!(actual, expected.trim_end(), "{}", input.display());
+        }
+        checked += 1;
+    }
+    assert!(checked > 0);
+}
+
+#[test]
+fn collects_safe_images_only() {
+    let sanitized = sanitize(
+        "<p>a<img src=\"https://img.example/1.png\">b<img src=\"data:image/png;base64,xx\"><img></p>",
+    );
+    assert_eq!(sanitized.images, ["https://img.example/1.png"]);
+    assert_eq!(sanitized.text, "ab");
+}
+
+#[test]
+fn output_is_always_balanced() {
+    let inputs = [
+        "",
+        "<",
+        "<<>>&&;",
+        "</p></p><p",
+        "<b><i><u><s><code>deep",
+        "<a href=\"https://x\"><b>link</a> bold?</b>",
+        "<table><td>cell<tr><li>orphan item",
+        "<!-- unterminated comment",
+        "<pre><pre>twice</pre>",
+        "<script>never closed",
+        "&#0; &#x110000; &bogus; &amp",
+    ];
+    for input in inputs {
+        let text = sanitize(input).text;
+        for tag in ["b", "i", "u", "s", "code", "pre", "a", "blockquote"] {
+            let opened = text.matches(&format!("<{}>", tag)).count()
+                + text.matches(&format!("<{} ", tag)).count();
+            let closed = text.matches(&format!("</{}>", tag)).count();
+            assert_eq!(opened, closed, "{:?} gave {:?}", input, text);
+        }
+        // and it survives splitting
+        for chunk in split_html(&text, 8) {
+            assert!(!chunk.contains("<>"), "{:?} gave {:?}", input, chunk);
+        }
+    }
+}
@@ -0,0 +1,342 @@
+mod common;
+
+use chrono::Utc;
+use common::{undone_item, MemoryKv, Servers, SqliteDatabase};
+use serde_json::{json, Value};
+use ucloud_push::api::ticktick::TickTick;
+use ucloud_push::config::{Config, CourseMapping};
+use ucloud_push::crypto::Cipher;
+use ucloud_push::pipeline;
+use wiremock::matchers::{body_string_contains, header, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+
+fn token_set(access_token: &str, expires_in: Option<i64>) -> String {
+    json!({
+        "access_token": access_token,
+        "refresh_token": "refresh-1",
+        "expires_at": expires_in.map(|secs| Utc::now().timestamp() + secs),
+    })
+    .to_string()
+}
+
+fn stored_token(kv: &MemoryKv) -> Value {
+    serde_json::from_str(&kv.value("ticktick_token").unwrap()).unwrap()
+}
+
+async fn serve_refresh(servers: &Servers, response: ResponseTemplate) {
+    Mock::given(method("POST"))
+        .and(path("/oauth/token"))
+        .and(body_string_contains("grant_type=refresh_token"))
+        .and(body_string_contains("refresh_token=refresh-1"))
+        .respond_with(response)
+        .mount(&servers.ticktick)
+        .await;
+}
+
+async fn reject_token(servers: &Servers, token: &str) {
+    Mock::given(method("POST"))
+        .and(path("/open/v1/task"))
+        .and(header(
+            "Authorization",
+            format!("Bearer {}", token).as_str(),
+        ))
+        .respond_with(ResponseTemplate::new(401))
+        .with_priority(1)
+        .mount(&servers.ticktick)
+        .await;
+}
+
+fn fresh_token() -> ResponseTemplate {
+    ResponseTemplate::new(200).set_body_json(json!({
+        "access_token": "fresh",
+        "refresh_token": "refresh-2",
+        "expires_in": 180 * 24 * 3600,
+    }))
+}
+
+fn login_links(messages: &[Value]) -> usize {
+    messages
+        .iter()
+        .filter_map(|m| m["text"].as_str())
+        .filter(|text| text.contains("/oauth/authorize"))
+        .count()
+}
+
+#[tokio::test]
+async fn refreshes_a_token_that_is_about_to_expire() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    serve_refresh(&servers, fresh_token()).await;
+    reject_token(&servers, "old").await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("ticktick_token", &token_set("old", Some(3600)))]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let requests = servers.ticktick.received_requests().await.unwrap();
+    assert!(requests.iter().all(
+        |r| r.headers.get("Authorization").map(|h| h.to_str().unwrap()) != Some("Bearer old")
+    ));
+    assert_eq!(servers.ticktick_tasks().await.len(), 1);
+
+    let token = stored_token(&kv);
+    assert_eq!(token["access_token"], "fresh");
+    assert_eq!(token["refresh_token"], "refresh-2");
+    assert!(token["expires_at"].as_i64().unwrap() > Utc::now().timestamp() + 24 * 3600);
+}
+
+#[tokio::test]
+async fn refreshes_and_retries_when_the_token_is_rejected() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    serve_refresh(&servers, fresh_token()).await;
+    reject_token(&servers, "stale").await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("ticktick_token", &token_set("stale", None))]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let delivered = db.query_json("SELECT status FROM deliveries WHERE sink = 'ticktick'");
+    assert_eq!(delivered[0]["status"], "delivered");
+    assert_eq!(stored_token(&kv)["access_token"], "fresh");
+    assert_eq!(login_links(&servers.telegram_messages().await), 0);
+}
+
+#[tokio::test]
+async fn asks_for_a_login_only_when_the_refresh_fails() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    serve_refresh(&servers, ResponseTemplate::new(400)).await;
+    reject_token(&servers, "stale").await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[
+        ("ticktick_token", &token_set("stale", None)),
+        ("access_token", "stale"),
+    ]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let failed = db.query_json("SELECT status FROM deliveries WHERE sink = 'ticktick'");
+    assert_eq!(failed[0]["status"], "failed");
+    assert_eq!(login_links(&servers.telegram_messages().await), 1);
+    assert!(kv.value("ticktick_token").is_none());
+    assert!(kv.value("access_token").is_none());
+}
+
+#[tokio::test]
+async fn keeps_the_tokens_when_the_refresh_is_unavailable() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    serve_refresh(&servers, ResponseTemplate::new(503)).await;
+    reject_token(&servers, "stale").await;
+    let db = SqliteDatabase::new();
+    let stale = token_set("stale", None);
+    let kv = MemoryKv::with(&[("ticktick_token", &stale)]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    let failed = db.query_json("SELECT status FROM deliveries WHERE sink = 'ticktick'");
+    assert_eq!(failed[0]["status"], "failed");
+    assert_eq!(login_links(&servers.telegram_messages().await), 0);
+    assert_eq!(kv.value("ticktick_token").unwrap(), stale);
+}
+
+#[tokio::test]
+async fn an_expired_token_waits_for_the_refresh_to_come_back() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    serve_refresh(&servers, ResponseTemplate::new(503)).await;
+    let db = SqliteDatabase::new();
+    let expired = token_set("expired", Some(-60));
+    let kv = MemoryKv::with(&[("ticktick_token", &expired)]);
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    assert!(servers.ticktick_tasks().await.is_empty());
+    assert_eq!(login_links(&servers.telegram_messages().await), 0);
+    assert_eq!(kv.value("ticktick_token").unwrap(), expired);
+}
+
+async fn serve_projects(servers: &Servers, projects: Value) {
+    Mock::given(method("GET"))
+        .and(path("/open/v1/project"))
+        .respond_with(ResponseTemplate::new(200).set_body_json(projects))
+        .expect(1)
+        .mount(&servers.ticktick)
+        .await;
+}
+
+#[tokio::test]
+async fn creates_the_named_project_once_and_caches_its_id() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    serve_projects(&servers, json!([{"id": "other", "name": "Other"}])).await;
+    Mock::given(method("POST"))
+        .and(path("/open/v1/project"))
+        .and(body_string_contains("UCloud"))
+        .respond_with(
+            ResponseTemplate::new(200).set_body_json(json!({"id": "ucloud", "name": "UCloud"})),
+        )
+        .expect(1)
+        .mount(&servers.ticktick)
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+    let mut config = servers.config();
+    config.ticktick.as_mut().unwrap().project_id = None;
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    servers.ucloud.reset().await;
+    servers
+        .serve_undone_list(&[
+            undone_item("a1", "Lab 1", "2025-03-08 23:59:00"),
+            undone_item("a2", "Lab 2", "2025-03-09 23:59:00"),
+        ])
+        .await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let tasks = servers.ticktick_tasks().await;
+    assert_eq!(tasks.len(), 2);
+    assert!(tasks.iter().all(|task| task["projectId"] == "ucloud"));
+    assert_eq!(kv.value("ticktick_project:UCloud").unwrap(), "ucloud");
+    let rows = db.query_json("SELECT ticktick_project_id FROM activities");
+    assert!(rows
+        .iter()
+        .all(|row| row["ticktick_project_id"] == "ucloud"));
+}
+
+#[tokio::test]
+async fn resolves_a_cached_project_again_when_it_is_gone() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/open/v1/project/deleted/data"))
+        .respond_with(ResponseTemplate::new(404))
+        .with_priority(1)
+        .mount(&servers.ticktick)
+        .await;
+    serve_projects(&servers, json!([{"id": "ucloud", "name": "UCloud"}])).await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[
+        ("access_token", "token"),
+        ("ticktick_project:UCloud", "deleted"),
+    ]);
+    let mut config = servers.config();
+    config.ticktick.as_mut().unwrap().project_id = None;
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let delivered = db.query_json("SELECT status FROM deliveries WHERE sink = 'ticktick'");
+    assert_eq!(delivered[0]["status"], "delivered");
+    let tasks = servers.ticktick_tasks().await;
+    assert_eq!(tasks.len(), 1);
+    assert_eq!(tasks[0]["projectId"], "ucloud");
+    assert_eq!(kv.value("ticktick_project:UCloud").unwrap(), "ucloud");
+}
+
+#[tokio::test]
+async fn maps_courses_to_projects_or_tags() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    serve_projects(&servers, json!([{"id": "os", "name": "Operating Systems"}])).await;
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+    let mut config = servers.config();
+    config.ticktick.as_mut().unwrap().course_mapping = CourseMapping::Project;
+
+    pipeline::push(&config, &SqliteDatabase::new(), &kv)
+        .await
+        .unwrap();
+
+    config.ticktick.as_mut().unwrap().course_mapping = CourseMapping::Tag;
+    pipeline::push(&config, &SqliteDatabase::new(), &kv)
+        .await
+        .unwrap();
+
+    let tasks = servers.ticktick_tasks().await;
+    assert_eq!(tasks[0]["projectId"], "os");
+    assert!(tasks[0].get("tags").is_none());
+    assert_eq!(tasks[1]["projectId"], "project-id");
+    assert_eq!(tasks[1]["tags"], json!(["Operating Systems"]));
+}
+
+fn encrypted_config(servers: &Servers) -> Config {
+    Config {
+        cipher: Some(Cipher::new(KEY).unwrap()),
+        ..servers.config()
+    }
+}
+
+fn decrypted_token(kv: &MemoryKv) -> Value {
+    let stored = kv.value("ticktick_token").unwrap();
+    assert!(
+        Cipher::is_encrypted(&stored),
+        "stored in plaintext: {}",
+        stored
+    );
+    serde_json::from_str(&Cipher::new(KEY).unwrap().decrypt(&stored).unwrap()).unwrap()
+}
+
+#[tokio::test]
+async fn legacy_token_is_moved_and_encrypted() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::with(&[("access_token", "token")]);
+
+    pipeline::push(&encrypted_config(&servers), &db, &kv)
+        .await
+        .unwrap();
+
+    assert_eq!(servers.ticktick_tasks().await.len(), 1);
+    assert!(kv.value("access_token").is_none());
+    assert_eq!(decrypted_token(&kv)["access_token"], "token");
+
+    // later runs read the encrypted token
+    pipeline::push(&encrypted_config(&servers), &db, &kv)
+        .await
+        .unwrap();
+    assert_eq!(login_links(&servers.telegram_messages().await), 0);
+}
+
+#[tokio::test]
+async fn plaintext_and_refreshed_tokens_are_stored_encrypted() {
+    let servers = Servers::start().await;
+    serve_refresh(&servers, fresh_token()).await;
+    let kv = MemoryKv::with(&[("ticktick_token", &token_set("old", None))]);
+
+    let config = encrypted_config(&servers);
+    let ticktick = TickTick::from_config(
+        config.ticktick.as_ref().unwrap(),
+        config.cipher.as_ref(),
+        &kv,
+    )
+    .await
+    .unwrap();
+    assert_eq!(decrypted_token(&kv)["access_token"], "old");
+
+    ticktick.refresh(&kv).await.unwrap();
+    let token = decrypted_token(&kv);
+    assert_eq!(token["access_token"], "fresh");
+    assert_eq!(token["refresh_token"], "refresh-2");
+}
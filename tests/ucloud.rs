@@ -0,0 +1,174 @@
+mod common;
+
+use common::{detail, undone_item, MemoryKv, Servers, SqliteDatabase};
+use serde_json::Value;
+use std::time::{Duration, Instant};
+use ucloud_push::error::Error;
+use ucloud_push::pipeline;
+use wiremock::matchers::{method, path, query_param};
+use wiremock::{Mock, ResponseTemplate};
+
+#[tokio::test]
+async fn reuses_stored_details_while_the_deadline_is_unchanged() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[
+            undone_item("a1", "Lab 1", "2025-03-08 23:59:00"),
+            undone_item("a2", "Lab 2", "2025-03-09 23:59:00"),
+        ])
+        .await;
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+    assert_eq!(servers.detail_requests().await.len(), 2);
+
+    servers.ucloud.reset().await;
+    servers
+        .serve_undone_list(&[
+            undone_item("a1", "Lab 1", "2025-03-10 23:59:00"),
+            undone_item("a2", "Lab 2", "2025-03-09 23:59:00"),
+        ])
+        .await;
+    pipeline::push(&servers.config(), &db, &kv).await.unwrap();
+
+    assert_eq!(servers.detail_requests().await, ["a1"]);
+    let rows = db.query_json("SELECT description, start_time FROM activities");
+    assert!(rows
+        .iter()
+        .all(|row| row["description"] == "<p>Read chapter <b>3</b></p>"
+            && row["start_time"] == "2025-03-01 08:00"));
+}
+
+#[tokio::test]
+async fn fetches_details_concurrently() {
+    let servers = Servers::start().await;
+    let items: Vec<Value> = (1..=6)
+        .map(|i| {
+            undone_item(
+                &format!("a{}", i),
+                &format!("Lab {}", i),
+                "2025-03-08 23:59:00",
+            )
+        })
+        .collect();
+    servers.serve(&items, &[]).await;
+    for i in 1..=6 {
+        let id = format!("a{}", i);
+        Mock::given(method("GET"))
+            .and(path("/homework"))
+            .and(query_param("id", id.as_str()))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .set_body_json(detail(&id))
+                    .set_delay(Duration::from_millis(300)),
+            )
+            .mount(&servers.ucloud)
+            .await;
+    }
+    let mut config = servers.config();
+    config.telegram = None;
+
+    let started = Instant::now();
+    pipeline::push(&config, &SqliteDatabase::new(), &MemoryKv::default())
+        .await
+        .unwrap();
+
+    assert_eq!(servers.detail_requests().await.len(), 6);
+    assert!(started.elapsed() < Duration::from_millis(1200));
+}
+
+#[tokio::test]
+async fn retries_transient_detail_failures() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/homework"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(2)
+        .with_priority(1)
+        .mount(&servers.ucloud)
+        .await;
+    let db = SqliteDatabase::new();
+
+    pipeline::push(&servers.config(), &db, &MemoryKv::default())
+        .await
+        .unwrap();
+
+    assert_eq!(servers.detail_requests().await.len(), 3);
+    let rows = db.query_json("SELECT description FROM activities");
+    assert_eq!(rows[0]["description"], "<p>Read chapter <b>3</b></p>");
+}
+
+#[tokio::test]
+async fn pushes_basic_info_when_a_detail_stays_unavailable() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[
+            undone_item("a1", "Lab 1", "2025-03-08 23:59:00"),
+            undone_item("a2", "Lab 2", "2025-03-09 23:59:00"),
+        ])
+        .await;
+    Mock::given(method("GET"))
+        .and(path("/homework"))
+        .and(query_param("id", "a1"))
+        .respond_with(
+            ResponseTemplate::new(200)
+                .set_body_json(detail("a1"))
+                .set_delay(Duration::from_secs(2)),
+        )
+        .with_priority(1)
+        .mount(&servers.ucloud)
+        .await;
+    let db = SqliteDatabase::new();
+    let mut config = servers.config();
+    config.ucloud_timeout_secs = 1;
+
+    pipeline::push(&config, &db, &MemoryKv::default())
+        .await
+        .unwrap();
+
+    assert_eq!(servers.detail_requests().await.len(), 4);
+    let messages = servers.telegram_messages().await;
+    let flagged: Vec<&str> = messages
+        .iter()
+        .filter_map(|m| m["text"].as_str())
+        .filter(|text| text.contains("作业详情暂时无法获取"))
+        .collect();
+    assert_eq!(flagged.len(), 1);
+    assert!(flagged[0].contains("Lab 1"));
+
+    let rows = db.query_json("SELECT description FROM activities ORDER BY activity_id");
+    assert!(rows[0]["description"].is_null());
+    assert_eq!(rows[1]["description"], "<p>Read chapter <b>3</b></p>");
+}
+
+#[tokio::test]
+async fn gives_up_when_the_undone_list_keeps_failing() {
+    let servers = Servers::start().await;
+    Mock::given(method("GET"))
+        .and(path("/undoneList"))
+        .respond_with(ResponseTemplate::new(502))
+        .mount(&servers.ucloud)
+        .await;
+
+    let e = pipeline::push(
+        &servers.config(),
+        &SqliteDatabase::new(),
+        &MemoryKv::default(),
+    )
+    .await
+    .unwrap_err();
+
+    assert!(matches!(
+        e,
+        Error::Upstream {
+            status: Some(502),
+            ..
+        }
+    ));
+    assert_eq!(servers.ucloud.received_requests().await.unwrap().len(), 3);
+}
@@ -0,0 +1,298 @@
+mod common;
+
+use common::{detail, undone_item, MemoryKv, Servers, SqliteDatabase, TELEGRAM_TOKEN};
+use serde_json::{json, Value};
+use ucloud_push::api::telegram::Telegram;
+use ucloud_push::config::{Config, TelegramConfig};
+use ucloud_push::crypto::Cipher;
+use ucloud_push::d1::{self, User};
+use ucloud_push::users;
+use wiremock::matchers::{basic_auth, method, path};
+use wiremock::{Mock, ResponseTemplate};
+
+const KEY: &str = "BwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwcHBwc=";
+const OLD_KEY: &str = "CQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQkJCQk=";
+const OWNER_ID: i64 = 1;
+
+/// Serves `items` from `/undoneList` to `username` only, everyone else is turned away.
+async fn serve_for(servers: &Servers, username: &str, password: &str, items: &[Value]) {
+    Mock::given(method("GET"))
+        .and(path("/undoneList"))
+        .and(basic_auth(username, password))
+        .respond_with(ResponseTemplate::new(200).set_body_json(json!({
+            "siteNum": 1,
+            "undoneNum": items.len(),
+            "undoneList": items,
+        })))
+        .with_priority(1)
+        .mount(&servers.ucloud)
+        .await;
+    for item in items {
+        servers
+            .serve(&[], &[detail(item["activityId"].as_str().unwrap())])
+            .await;
+    }
+}
+
+async fn reject_unknown_accounts(servers: &Servers) {
+    Mock::given(method("GET"))
+        .and(path("/undoneList"))
+        .respond_with(ResponseTemplate::new(401))
+        .with_priority(2)
+        .mount(&servers.ucloud)
+        .await;
+}
+
+fn bot_for_chat(config: &Config, chat_id: &str) -> Telegram {
+    Telegram::from_config(&TelegramConfig {
+        chat_id: chat_id.to_string(),
+        ..config.telegram.clone().unwrap()
+    })
+}
+
+fn register_message(argument: &str) -> Value {
+    json!({
+        "message_id": 5,
+        "from": {"id": 7},
+        "chat": {"id": 700},
+        "text": format!("/register {}", argument),
+    })
+}
+
+/// The test config with only Telegram and a credentials key.
+fn config(servers: &Servers) -> Config {
+    Config {
+        ticktick: None,
+        lark: None,
+        cipher: Some(Cipher::new(KEY).unwrap()),
+        ..servers.config()
+    }
+}
+
+async fn save_user(
+    user_id: &str,
+    chat_id: &str,
+    username: &str,
+    password: &str,
+    key: &str,
+    db: &SqliteDatabase,
+) {
+    let cipher = Cipher::new(key).unwrap();
+    d1::save_user(
+        &User {
+            user_id: user_id.to_string(),
+            chat_id: chat_id.to_string(),
+            ucloud_username: username.to_string(),
+            ucloud_password: cipher.encrypt(password).unwrap(),
+        },
+        db,
+    )
+    .await
+    .unwrap();
+}
+
+#[test]
+fn registration_list_accepts_ids_and_wildcard() {
+    assert!(!users::may_register(None, 7));
+    assert!(users::may_register(Some("3, 7"), 7));
+    assert!(!users::may_register(Some("3,70"), 7));
+    assert!(users::may_register(Some("*"), 7));
+}
+
+#[tokio::test]
+async fn register_checks_and_stores_encrypted_credentials() {
+    let servers = Servers::start().await;
+    serve_for(&servers, "alice", "hunter2", &[]).await;
+    reject_unknown_accounts(&servers).await;
+    let config = config(&servers);
+    let db = SqliteDatabase::new();
+    let bot = bot_for_chat(&config, "700");
+
+    let message = register_message("alice wrong");
+    users::register(&config, &bot, &message, "alice wrong", &db)
+        .await
+        .unwrap();
+    assert!(d1::get_user("7", &db).await.unwrap().is_none());
+
+    let message = register_message("alice hunter2");
+    users::register(&config, &bot, &message, "alice hunter2", &db)
+        .await
+        .unwrap();
+
+    // both messages carried a password and are gone
+    let deleted = Servers::bodies(
+        &servers.telegram,
+        &format!("/bot{}/deleteMessage", TELEGRAM_TOKEN),
+    )
+    .await;
+    assert_eq!(deleted.len(), 2);
+    assert!(deleted
+        .iter()
+        .all(|body| body["chat_id"] == "700" && body["message_id"] == 5));
+
+    let replies = servers.telegram_messages().await;
+    assert!(replies.iter().all(|m| m["chat_id"] == "700"));
+    assert!(replies[0]["text"]
+        .as_str()
+        .unwrap()
+        .contains("登录 UCloud 失败"));
+    assert!(replies[1]["text"].as_str().unwrap().contains("注册成功"));
+
+    let stored = &db.query_json("SELECT * FROM users")[0];
+    assert_eq!(stored["user_id"], "7");
+    assert_eq!(stored["chat_id"], "700");
+    assert_eq!(stored["ucloud_username"], "alice");
+    assert!(!stored["ucloud_password"]
+        .as_str()
+        .unwrap()
+        .contains("hunter2"));
+
+    let user_config = users::config_for(&config, 7, OWNER_ID, &db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(user_config.username, "alice");
+    assert_eq!(user_config.password, "hunter2");
+    assert_eq!(user_config.user_id, "7");
+    assert_eq!(user_config.telegram.unwrap().chat_id, "700");
+    assert!(user_config.ticktick.is_none() && user_config.lark.is_none());
+
+    let owner_config = users::config_for(&config, OWNER_ID, OWNER_ID, &db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(owner_config.user_id, d1::OWNER);
+    assert!(users::config_for(&config, 8, OWNER_ID, &db)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn cron_pushes_every_user_with_separate_state() {
+    let servers = Servers::start().await;
+    let shared = undone_item("s1", "Shared Lab", "2099-03-08 23:59:00");
+    serve_for(
+        &servers,
+        "student",
+        "secret",
+        &[
+            undone_item("a1", "Owner Essay", "2099-03-08 23:59:00"),
+            shared.clone(),
+        ],
+    )
+    .await;
+    serve_for(&servers, "alice", "hunter2", &[shared]).await;
+    reject_unknown_accounts(&servers).await;
+    let config = config(&servers);
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+    save_user("7", "700", "alice", "hunter2", KEY, &db).await;
+    save_user("8", "800", "bob", "expired", KEY, &db).await;
+
+    users::push_all(&config, &db, &kv).await.unwrap();
+
+    let messages = servers.telegram_messages().await;
+    let to = |chat: &str| -> Vec<String> {
+        messages
+            .iter()
+            .filter(|m| m["chat_id"] == chat)
+            .map(|m| m["text"].as_str().unwrap().to_string())
+            .collect()
+    };
+    let owner = to("42");
+    assert!(owner.iter().any(|t| t.contains("Owner Essay")));
+    assert!(owner.iter().any(|t| t.contains("Shared Lab")));
+    let alice = to("700");
+    assert_eq!(alice.len(), 1);
+    assert!(alice[0].contains("Shared Lab"));
+    // bob's failure is reported to bob and did not stop anyone else
+    let bob = to("800");
+    assert_eq!(bob.len(), 1);
+    assert!(bob[0].contains("推送失败"));
+
+    let rows =
+        db.query_json("SELECT user_id, activity_id FROM activities ORDER BY user_id, activity_id");
+    let rows: Vec<(&str, &str)> = rows
+        .iter()
+        .map(|r| {
+            (
+                r["user_id"].as_str().unwrap(),
+                r["activity_id"].as_str().unwrap(),
+            )
+        })
+        .collect();
+    assert_eq!(rows, [("", "a1"), ("", "s1"), ("7", "s1")]);
+    let deliveries = db.query_json("SELECT * FROM deliveries WHERE user_id = '7'");
+    assert_eq!(deliveries.len(), 1);
+    assert_eq!(deliveries[0]["sink"], "telegram");
+
+    // a second run sends nothing new to anyone but bob
+    users::push_all(&config, &db, &kv).await.unwrap();
+    let again = servers.telegram_messages().await;
+    assert_eq!(again.len(), messages.len() + 1);
+    assert_eq!(again.last().unwrap()["chat_id"], "800");
+
+    // unregistering forgets only that user
+    d1::delete_user("7", &db).await.unwrap();
+    assert_eq!(
+        db.query_json("SELECT * FROM activities WHERE user_id = '7'")
+            .len(),
+        0
+    );
+    assert_eq!(db.query_json("SELECT * FROM activities").len(), 2);
+    assert_eq!(db.query_json("SELECT user_id FROM users").len(), 1);
+}
+
+#[tokio::test]
+async fn credentials_under_a_previous_key_are_reencrypted() {
+    let servers = Servers::start().await;
+    serve_for(&servers, "alice", "hunter2", &[]).await;
+    let db = SqliteDatabase::new();
+    save_user("7", "700", "alice", "hunter2", OLD_KEY, &db).await;
+    let before = d1::get_user("7", &db).await.unwrap().unwrap();
+
+    let config = config(&servers);
+    assert!(users::push_all(&config, &db, &MemoryKv::default())
+        .await
+        .is_ok());
+    // without the old key the password cannot be read, which is reported
+    let messages = servers.telegram_messages().await;
+    assert!(messages
+        .iter()
+        .any(|m| m["chat_id"] == "700" && m["text"].as_str().unwrap().contains("推送失败")));
+    assert_eq!(
+        d1::get_user("7", &db)
+            .await
+            .unwrap()
+            .unwrap()
+            .ucloud_password,
+        before.ucloud_password
+    );
+
+    let cipher = Cipher::new(KEY)
+        .unwrap()
+        .with_previous_keys(OLD_KEY)
+        .unwrap();
+    let config = Config {
+        cipher: Some(cipher.clone()),
+        ..config
+    };
+    let user_config = users::config_for(&config, 7, OWNER_ID, &db)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(user_config.password, "hunter2");
+
+    let after = d1::get_user("7", &db)
+        .await
+        .unwrap()
+        .unwrap()
+        .ucloud_password;
+    assert!(!cipher.is_current(&before.ucloud_password));
+    assert!(cipher.is_current(&after));
+    assert_eq!(
+        Cipher::new(KEY).unwrap().decrypt(&after).unwrap(),
+        "hunter2"
+    );
+}
@@ -0,0 +1,151 @@
+mod common;
+
+use common::{detail, undone_item, MemoryKv, Servers, SqliteDatabase, WEBHOOK_SECRET};
+use serde_json::{json, Map, Value};
+use ucloud_push::api::webhook::{self, EVENT_HEADER, SIGNATURE_HEADER};
+use ucloud_push::config::Config;
+use ucloud_push::pipeline;
+
+/// The test config with the webhook as the only sink besides Telegram.
+fn config(servers: &Servers) -> Config {
+    Config {
+        ticktick: None,
+        lark: None,
+        caldav: None,
+        ..servers.config()
+    }
+}
+
+/// Event header and body of every webhook request, after checking its signature.
+async fn deliveries(servers: &Servers) -> Vec<(String, Value)> {
+    servers
+        .webhook
+        .received_requests()
+        .await
+        .unwrap()
+        .into_iter()
+        .map(|request| {
+            assert_eq!(
+                request.headers[SIGNATURE_HEADER],
+                webhook::sign(WEBHOOK_SECRET, &request.body)
+                    .unwrap()
+                    .as_str()
+            );
+            (
+                request.headers[EVENT_HEADER].to_str().unwrap().to_string(),
+                serde_json::from_slice(&request.body).unwrap(),
+            )
+        })
+        .collect()
+}
+
+#[test]
+fn signature_is_hmac_sha256_of_the_body() {
+    // RFC 4231 test case 2
+    assert_eq!(
+        webhook::sign("Jefe", b"what do ya want for nothing?").unwrap(),
+        "sha256=5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+    );
+}
+
+#[test]
+fn whole_placeholders_keep_their_type() {
+    let fields: Map<String, Value> = json!({
+        "name": "Lab \"1\"",
+        "changes": ["deadline", "description"],
+        "course": null,
+    })
+    .as_object()
+    .cloned()
+    .unwrap();
+    let template = json!({
+        "title": "{{name}}",
+        "text": "{{ name }} changed: {{changes}}{{course}}",
+        "changes": "{{changes}}",
+        "course": "{{course}}",
+        "nested": [{"unknown": "{{nope}}"}, 3, true],
+    });
+
+    assert_eq!(
+        webhook::render(&template, &fields),
+        json!({
+            "title": "Lab \"1\"",
+            "text": "Lab \"1\" changed: deadline, description",
+            "changes": ["deadline", "description"],
+            "course": null,
+            "nested": [{"unknown": "{{nope}}"}, 3, true],
+        })
+    );
+}
+
+#[tokio::test]
+async fn posts_new_changed_and_completed_activities() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let config = config(&servers);
+    let db = SqliteDatabase::new();
+    let kv = MemoryKv::default();
+
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    servers.ucloud.reset().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-10 23:59:00")])
+        .await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    servers.ucloud.reset().await;
+    servers.serve(&[], &[detail("a1")]).await;
+    pipeline::push(&config, &db, &kv).await.unwrap();
+
+    let deliveries = deliveries(&servers).await;
+    let events: Vec<&str> = deliveries.iter().map(|(e, _)| e.as_str()).collect();
+    assert_eq!(events, ["new", "changed", "completed"]);
+
+    let new = &deliveries[0].1;
+    assert_eq!(new["event"], "new");
+    assert_eq!(new["activity"]["id"], "a1");
+    assert_eq!(new["activity"]["name"], "Lab 1");
+    assert_eq!(new["activity"]["course"], "Operating Systems");
+    assert_eq!(new["activity"]["end_time"], "2025-03-08 23:59:00");
+    assert_eq!(new["changes"], json!([]));
+    assert!(new["previous_end_time"].is_null());
+    assert!(new["timestamp"].is_string());
+
+    let changed = &deliveries[1].1;
+    assert_eq!(changed["changes"], json!(["deadline"]));
+    assert_eq!(changed["previous_end_time"], "2025-03-08 23:59:00");
+    assert_eq!(changed["activity"]["end_time"], "2025-03-10 23:59:00");
+
+    let completed = &deliveries[2].1;
+    assert_eq!(completed["activity"]["id"], "a1");
+    assert_eq!(completed["activity"]["course"], "Operating Systems");
+}
+
+#[tokio::test]
+async fn user_templates_shape_the_payload() {
+    let servers = Servers::start().await;
+    servers
+        .serve_undone_list(&[undone_item("a1", "Lab 1", "2025-03-08 23:59:00")])
+        .await;
+    let mut config = config(&servers);
+    config.webhook.as_mut().unwrap().template = Some(json!({
+        "title": "UCloud: {{name}}",
+        "message": "{{course}} · due {{end_time}}",
+    }));
+
+    pipeline::push(&config, &SqliteDatabase::new(), &MemoryKv::default())
+        .await
+        .unwrap();
+
+    let deliveries = deliveries(&servers).await;
+    assert_eq!(
+        deliveries[0].1,
+        json!({
+            "title": "UCloud: Lab 1",
+            "message": "Operating Systems · due 2025-03-08 23:59:00",
+        })
+    );
+}